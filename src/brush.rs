@@ -0,0 +1,523 @@
+use na::{vector, Vector3};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::console::{Args, CommandError, Commands};
+use crate::procgen::Noise;
+use crate::types::Idx3;
+use crate::world::{DenseGrid, Region, Space, Voxel};
+
+use std::collections::VecDeque;
+
+/*********/
+/* Shape */
+/*********/
+
+// A solid in voxel coordinates, where each voxel is the point at its index
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Shape {
+    Sphere {
+        center: Vector3<f32>,
+        radius: f32,
+    },
+
+    Box(Region),
+
+    // upright, standing on *base* and *height* voxels tall
+    Cylinder {
+        base: Vector3<f32>,
+        radius: f32,
+        height: f32,
+    },
+
+    // every point within *radius* of the segment between *from* and *to*
+    Line {
+        from: Vector3<f32>,
+        to: Vector3<f32>,
+        radius: f32,
+    },
+}
+
+impl Shape {
+    // Signed distance from *p* to the surface of the shape, negative inside
+    pub fn distance(&self, p: Vector3<f32>) -> f32 {
+        // distance to a box centered on the origin with the given half size
+        fn box_distance(q: Vector3<f32>, half: Vector3<f32>) -> f32 {
+            let d = q.abs() - half;
+            d.sup(&Vector3::zeros()).norm() + d.max().min(0.0)
+        }
+
+        match *self {
+            Shape::Sphere { center, radius } => (p - center).norm() - radius,
+            Shape::Box(region) => {
+                let (min, max) = (to_point(region.min), to_point(region.max));
+                // half a voxel larger, so the voxels on its faces are well inside
+                let half = (max - min) / 2.0 + vector![0.5, 0.5, 0.5];
+                box_distance(p - (min + max) / 2.0, half)
+            }
+            Shape::Cylinder {
+                base,
+                radius,
+                height,
+            } => {
+                let q = p - base;
+                let across = vector![q.x, q.z].norm() - radius;
+                // the voxels from the base up are centered on the middle of the height
+                let along = (q.y - (height - 1.0) / 2.0).abs() - height / 2.0;
+                vector![across, along].sup(&vector![0.0, 0.0]).norm() + across.max(along).min(0.0)
+            }
+            Shape::Line { from, to, radius } => {
+                let dir = to - from;
+                let t = if dir.norm_squared() > 0.0 {
+                    ((p - from).dot(&dir) / dir.norm_squared()).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                (p - (from + dir * t)).norm() - radius
+            }
+        }
+    }
+
+    // Corners of a box around the shape
+    fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let r = |radius: f32| vector![radius, radius, radius];
+        match *self {
+            Shape::Sphere { center, radius } => (center - r(radius), center + r(radius)),
+            Shape::Box(region) => (to_point(region.min), to_point(region.max)),
+            Shape::Cylinder {
+                base,
+                radius,
+                height,
+            } => (
+                base - vector![radius, 0.0, radius],
+                base + vector![radius, height, radius],
+            ),
+            Shape::Line { from, to, radius } => {
+                (from.inf(&to) - r(radius), from.sup(&to) + r(radius))
+            }
+        }
+    }
+}
+
+fn to_point((x, y, z): Idx3) -> Vector3<f32> {
+    vector![x as f32, y as f32, z as f32]
+}
+
+// Roughens the surface of a shape by moving it in and out by gradient noise
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Roughness {
+    // most the surface moves, in voxels
+    pub amplitude: f32,
+
+    // size of the bumps, in voxels
+    pub scale: f32,
+
+    pub seed: u64,
+}
+
+/*********/
+/* Brush */
+/*********/
+
+// A set of voxels to paint, worked out from the space it is painted in
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Brush {
+    // every voxel inside a shape, roughened if given
+    Shape(Shape, Option<Roughness>),
+
+    // every voxel reachable from a starting one through faces, without crossing a different
+    // voxel
+    Flood(Idx3),
+
+    // every voxel of a material within a region
+    Replace(Region, Voxel),
+}
+
+impl Brush {
+    pub fn shape(shape: Shape) -> Brush {
+        Brush::Shape(shape, None)
+    }
+
+    // Positions of the voxels the brush covers in *space*
+    pub fn voxels(&self, space: &Space) -> Vec<Idx3> {
+        let grid = &space.voxels;
+        match *self {
+            Brush::Shape(shape, roughness) => {
+                let (mut min, mut max) = shape.bounds();
+                let noise = roughness.map(|r| {
+                    let margin = vector![r.amplitude, r.amplitude, r.amplitude];
+                    min -= margin;
+                    max += margin;
+                    (r, Noise::new(&mut StdRng::seed_from_u64(r.seed)))
+                });
+                let region = match clip(grid, min, max) {
+                    Some(region) => region,
+                    None => return Vec::new(),
+                };
+                region
+                    .iter()
+                    .filter(|&idx| {
+                        let p = to_point(idx);
+                        let bump = noise.as_ref().map_or(0.0, |(r, noise)| {
+                            let q = p / r.scale;
+                            r.amplitude * noise.noise3(q.x, q.y, q.z)
+                        });
+                        shape.distance(p) + bump <= 0.0
+                    })
+                    .collect()
+            }
+            Brush::Flood(start) => flood(grid, start),
+            Brush::Replace(region, from) => {
+                region.iter().filter(|&idx| grid[idx] == from).collect()
+            }
+        }
+    }
+}
+
+// The region of *grid* within the box between *min* and *max*, if they overlap
+fn clip(grid: &DenseGrid<Voxel>, min: Vector3<f32>, max: Vector3<f32>) -> Option<Region> {
+    let (sx, sy, sz) = *grid.shape();
+    let size = [sx, sy, sz];
+    let mut lo = [0; 3];
+    let mut hi = [0; 3];
+    for axis in 0..3 {
+        let (a, b) = (min[axis].floor(), max[axis].ceil());
+        if b < 0.0 || a > (size[axis] - 1) as f32 {
+            return None;
+        }
+        lo[axis] = a.max(0.0) as usize;
+        hi[axis] = (b as usize).min(size[axis] - 1);
+    }
+    Some(Region::new((lo[0], lo[1], lo[2]), (hi[0], hi[1], hi[2])))
+}
+
+fn flood(grid: &DenseGrid<Voxel>, start: Idx3) -> Vec<Idx3> {
+    let (sx, sy, sz) = *grid.shape();
+    let index = |(x, y, z): Idx3| x + sx * (y + sy * z);
+    let target = grid[start];
+
+    let mut seen = vec![false; grid.len()];
+    let mut queue = VecDeque::from([start]);
+    let mut filled = Vec::new();
+    seen[index(start)] = true;
+    while let Some((x, y, z)) = queue.pop_front() {
+        filled.push((x, y, z));
+        let neighbours = [
+            (x.wrapping_sub(1), y, z),
+            (x + 1, y, z),
+            (x, y.wrapping_sub(1), z),
+            (x, y + 1, z),
+            (x, y, z.wrapping_sub(1)),
+            (x, y, z + 1),
+        ];
+        for next in neighbours {
+            // below zero wraps around to above the size
+            if next.0 >= sx || next.1 >= sy || next.2 >= sz {
+                continue;
+            }
+            if !seen[index(next)] && grid[next] == target {
+                seen[index(next)] = true;
+                queue.push_back(next);
+            }
+        }
+    }
+    filled
+}
+
+/************/
+/* Commands */
+/************/
+
+// Takes a voxel position from the arguments of a command, which has to be inside the space
+pub fn position(space: &Space, args: &mut Args, name: &str) -> Result<Idx3, CommandError> {
+    let idx = (args.next(name)?, args.next(name)?, args.next(name)?);
+    let (sx, sy, sz) = *space.voxels.shape();
+    if idx.0 >= sx || idx.1 >= sy || idx.2 >= sz {
+        return Err(CommandError::Usage(format!(
+            "{} {:?} is outside the world, which is {}x{}x{}",
+            name, idx, sx, sy, sz
+        )));
+    }
+    Ok(idx)
+}
+
+// Console commands painting brushes into the space
+pub fn commands() -> Commands<Space> {
+    // a point anywhere, shapes can stick out of the space
+    fn point(args: &mut Args, name: &str) -> Result<Vector3<f32>, CommandError> {
+        Ok(vector![
+            args.next(name)?,
+            args.next(name)?,
+            args.next(name)?
+        ])
+    }
+
+    fn material(args: &mut Args) -> Result<Voxel, CommandError> {
+        args.next_with("material", Voxel::from_name)
+    }
+
+    // noise moving the surface of a shape by up to *amplitude* voxels, in bumps a few voxels wide
+    fn roughness(args: &mut Args) -> Result<Option<Roughness>, CommandError> {
+        let amplitude: Option<f32> = args.optional("roughness")?;
+        Ok(amplitude.filter(|a| *a > 0.0).map(|amplitude| Roughness {
+            amplitude,
+            scale: 4.0 + amplitude,
+            seed: 0,
+        }))
+    }
+
+    fn paint(space: &mut Space, brush: Brush, material: Voxel) -> Result<String, CommandError> {
+        Ok(match space.paint(&brush, material) {
+            Some(edit) => format!(
+                "changed {} voxels to {}",
+                edit.changes.len(),
+                material.name()
+            ),
+            None => "nothing changed".to_string(),
+        })
+    }
+
+    let mut commands: Commands<Space> = Commands::default();
+    commands.add(
+        "fill",
+        "<x0> <y0> <z0> <x1> <y1> <z1> <material>",
+        "fill the box between two corners, both included, with a material or empty",
+        |space, args| {
+            let a = position(space, args, "corner")?;
+            let b = position(space, args, "corner")?;
+            let vox = material(args)?;
+            args.end()?;
+            paint(space, Brush::shape(Shape::Box(Region::new(a, b))), vox)
+        },
+    );
+    commands.add(
+        "sphere",
+        "<x> <y> <z> <radius> <material> [roughness]",
+        "paint a ball, its surface moved in and out by up to roughness voxels",
+        |space, args| {
+            let center = point(args, "center")?;
+            let radius = args.next("radius")?;
+            let vox = material(args)?;
+            let roughness = roughness(args)?;
+            args.end()?;
+            let brush = Brush::Shape(Shape::Sphere { center, radius }, roughness);
+            paint(space, brush, vox)
+        },
+    );
+    commands.add(
+        "cylinder",
+        "<x> <y> <z> <radius> <height> <material> [roughness]",
+        "paint an upright cylinder standing on a point",
+        |space, args| {
+            let base = point(args, "base")?;
+            let radius = args.next("radius")?;
+            let height = args.next("height")?;
+            let vox = material(args)?;
+            let roughness = roughness(args)?;
+            args.end()?;
+            let shape = Shape::Cylinder {
+                base,
+                radius,
+                height,
+            };
+            paint(space, Brush::Shape(shape, roughness), vox)
+        },
+    );
+    commands.add(
+        "line",
+        "<x0> <y0> <z0> <x1> <y1> <z1> <material> [radius]",
+        "paint a line between two points, half a voxel thick unless given a radius",
+        |space, args| {
+            let from = point(args, "start")?;
+            let to = point(args, "end")?;
+            let vox = material(args)?;
+            let radius = args.optional("radius")?.unwrap_or(0.5);
+            args.end()?;
+            paint(space, Brush::shape(Shape::Line { from, to, radius }), vox)
+        },
+    );
+    commands.add(
+        "flood",
+        "<x> <y> <z> <material>",
+        "paint the voxels connected to a voxel that are the same as it",
+        |space, args| {
+            let start = position(space, args, "start")?;
+            let vox = material(args)?;
+            args.end()?;
+            paint(space, Brush::Flood(start), vox)
+        },
+    );
+    commands.add(
+        "replace",
+        "<material> <material>",
+        "change every voxel of the first material in the world to the second",
+        |space, args| {
+            let from = material(args)?;
+            let to = material(args)?;
+            args.end()?;
+            let (sx, sy, sz) = *space.voxels.shape();
+            let world = Region::new((0, 0, 0), (sx - 1, sy - 1, sz - 1));
+            paint(space, Brush::Replace(world, from), to)
+        },
+    );
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_space() -> Space {
+        Space::from_voxels(DenseGrid::fill((16, 16, 16), Voxel::Empty))
+    }
+
+    fn filled(space: &Space) -> usize {
+        space.voxels.iter().filter(|v| !v.is_empty()).count()
+    }
+
+    #[test]
+    fn paints_shapes_in_one_edit() {
+        let mut space = empty_space();
+        let sphere = Shape::Sphere {
+            center: vector![8.0, 8.0, 8.0],
+            radius: 2.0,
+        };
+        let edit = space.paint(&Brush::shape(sphere), Voxel::Stone).unwrap();
+
+        // the center, 6 voxels 1 away, 12 at sqrt(2), 8 at sqrt(3) and 6 at 2
+        assert_eq!(edit.changes.len(), 33);
+        assert_eq!(filled(&space), 33);
+        assert_eq!(edit.region, Region::new((6, 6, 6), (10, 10, 10)));
+        assert!(edit.changes.iter().all(|c| c.before.is_empty()));
+        assert_eq!(space.sdf[(8, 8, 8)], 0);
+        assert_eq!(space.sdf[(8, 8, 11)], 1);
+
+        // painting it again changes nothing
+        assert!(space.paint(&Brush::shape(sphere), Voxel::Stone).is_none());
+    }
+
+    #[test]
+    fn covers_boxes_cylinders_and_lines() {
+        let space = empty_space();
+        let count = |shape| Brush::shape(shape).voxels(&space).len();
+
+        assert_eq!(count(Shape::Box(Region::new((1, 2, 3), (3, 2, 6)))), 12);
+        let cylinder = Shape::Cylinder {
+            base: vector![8.0, 0.0, 8.0],
+            radius: 1.0,
+            height: 3.0,
+        };
+        assert_eq!(count(cylinder), 5 * 3);
+        let line = Shape::Line {
+            from: vector![2.0, 4.0, 4.0],
+            to: vector![9.0, 4.0, 4.0],
+            radius: 0.5,
+        };
+        assert_eq!(count(line), 8);
+
+        // shapes sticking out of the space are cut off
+        let sphere = Shape::Sphere {
+            center: vector![0.0, 0.0, 0.0],
+            radius: 1.0,
+        };
+        assert_eq!(count(sphere), 4);
+        let outside = Shape::Sphere {
+            center: vector![-5.0, 0.0, 0.0],
+            radius: 1.0,
+        };
+        assert_eq!(count(outside), 0);
+    }
+
+    #[test]
+    fn roughness_is_repeatable() {
+        let space = empty_space();
+        let sphere = Shape::Sphere {
+            center: vector![8.0, 8.0, 8.0],
+            radius: 5.0,
+        };
+        let rough = |seed| {
+            let roughness = Roughness {
+                amplitude: 2.0,
+                scale: 3.0,
+                seed,
+            };
+            Brush::Shape(sphere, Some(roughness)).voxels(&space)
+        };
+        assert_eq!(rough(1), rough(1));
+        assert_ne!(rough(1), rough(2));
+        assert_ne!(rough(1), Brush::shape(sphere).voxels(&space));
+    }
+
+    #[test]
+    fn floods_up_to_walls_and_replaces() {
+        let mut space = empty_space();
+        // a closed 3x3x3 box of stone, hollow inside
+        space.paint(
+            &Brush::shape(Shape::Box(Region::new((4, 4, 4), (6, 6, 6)))),
+            Voxel::Stone,
+        );
+        let middle = Region::new((5, 5, 5), (5, 5, 5));
+        space.paint(&Brush::shape(Shape::Box(middle)), Voxel::Empty);
+
+        let edit = space.paint(&Brush::Flood((5, 5, 5)), Voxel::Water).unwrap();
+        assert_eq!(edit.changes.len(), 1);
+        let edit = space.paint(&Brush::Flood((4, 4, 4)), Voxel::Sand).unwrap();
+        assert_eq!(edit.changes.len(), 26);
+
+        let world = Region::new((0, 0, 0), (15, 15, 15));
+        let edit = space
+            .paint(&Brush::Replace(world, Voxel::Sand), Voxel::Dirt)
+            .unwrap();
+        assert_eq!(
+            (edit.changes.len(), edit.region),
+            (26, Region::new((4, 4, 4), (6, 6, 6)))
+        );
+        assert!(edit.changes.iter().all(|c| c.before == Voxel::Sand));
+        assert_eq!(space.voxels[(5, 5, 5)], Voxel::Water);
+    }
+
+    #[test]
+    fn fills_boxes_from_the_console() {
+        let commands = commands();
+        let mut space = Space::from_voxels(DenseGrid::fill((8, 8, 8), Voxel::Empty));
+
+        let out = commands.run(&mut space, "fill 3 1 2 1 1 4 stone").unwrap();
+        assert_eq!(out, "changed 9 voxels to stone");
+        assert_eq!(space.voxels[(1, 1, 2)], Voxel::Stone);
+        assert_eq!(space.voxels[(3, 1, 4)], Voxel::Stone);
+        assert_eq!(space.voxels[(0, 1, 2)], Voxel::Empty);
+        assert_eq!(space.sdf[(2, 1, 3)], 0);
+
+        // nothing changes when any argument is wrong
+        assert!(commands.run(&mut space, "fill 0 0 0 8 0 0 stone").is_err());
+        assert!(commands.run(&mut space, "fill 0 0 0 1 1 1 lava").is_err());
+        assert!(commands
+            .run(&mut space, "fill 0 0 0 1 1 1 empty 2")
+            .is_err());
+        assert_eq!(filled(&space), 9);
+
+        // only the voxels that weren't stone already count
+        let out = commands.run(&mut space, "fill 1 0 2 1 2 2 stone").unwrap();
+        assert_eq!(out, "changed 2 voxels to stone");
+        let out = commands.run(&mut space, "fill 1 1 2 3 1 4 stone").unwrap();
+        assert_eq!(out, "nothing changed");
+    }
+
+    #[test]
+    fn paints_brushes_from_the_console() {
+        let commands = commands();
+        let mut space = empty_space();
+
+        let out = commands.run(&mut space, "sphere 8 8 8 2 grass").unwrap();
+        assert_eq!(out, "changed 33 voxels to grass");
+        commands.run(&mut space, "replace grass snow").unwrap();
+        assert_eq!(space.voxels[(8, 8, 8)], Voxel::Snow);
+        commands.run(&mut space, "line 0 0 0 15 0 0 wood").unwrap();
+        assert_eq!(space.voxels[(15, 0, 0)], Voxel::Wood);
+        let out = commands.run(&mut space, "flood 0 0 0 wood").unwrap();
+        assert_eq!(out, "nothing changed");
+        assert!(commands
+            .run(&mut space, "cylinder 8 0 8 3 4 sand 1.5")
+            .is_ok());
+        assert!(commands.run(&mut space, "flood 16 0 0 wood").is_err());
+    }
+}
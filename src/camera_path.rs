@@ -0,0 +1,301 @@
+use na::{Vector3, Vector5};
+
+use crate::world_loader::LoadError;
+
+use std::f32::consts::PI;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+// Camera state at a point in time along a path
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Keyframe {
+    // seconds since the start of the path
+    pub time: f32,
+    pub pos: Vector3<f32>,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl Keyframe {
+    fn as_vector(&self) -> Vector5<f32> {
+        Vector5::new(self.pos.x, self.pos.y, self.pos.z, self.yaw, self.pitch)
+    }
+}
+
+/***************/
+/* Camera path */
+/***************/
+
+// Keyframes in time order, smoothly interpolated with a Catmull-Rom spline
+#[derive(Clone, Default, Debug)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|k| k.time).unwrap_or(0.0)
+    }
+
+    // Camera state at *time* seconds into the path, held at the first and last keyframes outside
+    // of it
+    pub fn sample(&self, time: f32) -> Option<Keyframe> {
+        let keys = &self.keyframes;
+        let last = keys.len().checked_sub(1)?;
+        if time <= keys[0].time || last == 0 {
+            return Some(Keyframe { time, ..keys[0] });
+        }
+        if time >= keys[last].time {
+            return Some(Keyframe { time, ..keys[last] });
+        }
+
+        // segment from keyframe i to i + 1 containing *time*
+        let i = keys.iter().rposition(|k| k.time <= time).unwrap();
+        let (k1, k2) = (&keys[i], &keys[i + 1]);
+        let dt = k2.time - k1.time;
+        let u = (time - k1.time) / dt;
+
+        // tangents through the neighbouring keyframes, as in a Catmull-Rom spline with uneven
+        // spacing; the ends use the slope of their only segment
+        let tangent = |j: usize| {
+            let (a, b) = (&keys[j.saturating_sub(1)], &keys[(j + 1).min(last)]);
+            (b.as_vector() - a.as_vector()) / (b.time - a.time)
+        };
+        let (m1, m2) = (tangent(i) * dt, tangent(i + 1) * dt);
+
+        // cubic Hermite basis
+        let (u2, u3) = (u * u, u * u * u);
+        let v = k1.as_vector() * (2.0 * u3 - 3.0 * u2 + 1.0)
+            + m1 * (u3 - 2.0 * u2 + u)
+            + k2.as_vector() * (-2.0 * u3 + 3.0 * u2)
+            + m2 * (u3 - u2);
+
+        Some(Keyframe {
+            time,
+            pos: Vector3::new(v[0], v[1], v[2]),
+            yaw: v[3],
+            pitch: v[4],
+        })
+    }
+
+    // Text format: one keyframe per line as `time x y z yaw pitch`, with # comments
+    pub fn parse(src: &str) -> Result<CameraPath, LoadError> {
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+
+        for (line_no, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || LoadError::Parse(format!("camera path line {}: {:?}", line_no + 1, line));
+
+            // NaN and infinite values would make the times impossible to order
+            let values = line
+                .split_whitespace()
+                .map(|v| {
+                    v.parse::<f32>()
+                        .ok()
+                        .filter(|v| v.is_finite())
+                        .ok_or_else(err)
+                })
+                .collect::<Result<Vec<f32>, LoadError>>()?;
+            let key = match values[..] {
+                [time, x, y, z, yaw, pitch] => Keyframe {
+                    time,
+                    pos: Vector3::new(x, y, z),
+                    yaw,
+                    pitch,
+                },
+                _ => return Err(err()),
+            };
+            if keyframes.last().is_some_and(|k| k.time >= key.time) {
+                return Err(err());
+            }
+            keyframes.push(key);
+        }
+
+        Ok(CameraPath { keyframes })
+    }
+
+    pub fn load(path: &Path) -> Result<CameraPath, LoadError> {
+        CameraPath::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::from("# Camera path\n# time x y z yaw pitch\n");
+        for k in &self.keyframes {
+            writeln!(
+                out,
+                "{} {} {} {} {} {}",
+                k.time, k.pos.x, k.pos.y, k.pos.z, k.yaw, k.pitch
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+}
+
+/************/
+/* Recorder */
+/************/
+
+// Builds a path out of the camera states seen during live play
+pub struct PathRecorder {
+    pub path: CameraPath,
+
+    // seconds between keyframes
+    pub interval: f32,
+    time: f32,
+}
+
+impl PathRecorder {
+    pub fn new(interval: f32) -> PathRecorder {
+        PathRecorder {
+            path: CameraPath::default(),
+            interval,
+            time: 0.0,
+        }
+    }
+
+    // Records the camera as it is now, before *dt* more seconds pass, keeping a keyframe every
+    // `interval`
+    pub fn record(&mut self, dt: f32, pos: Vector3<f32>, yaw: f32, pitch: f32) {
+        let due = self
+            .path
+            .keyframes
+            .last()
+            .is_none_or(|k| self.time - k.time >= self.interval - 1e-4);
+        if due {
+            self.push(pos, yaw, pitch);
+        }
+        self.time += dt;
+    }
+
+    // Ends the recording with the camera's final state
+    pub fn finish(mut self, pos: Vector3<f32>, yaw: f32, pitch: f32) -> CameraPath {
+        if self
+            .path
+            .keyframes
+            .last()
+            .is_none_or(|k| k.time < self.time)
+        {
+            self.push(pos, yaw, pitch);
+        }
+        self.path
+    }
+
+    fn push(&mut self, pos: Vector3<f32>, yaw: f32, pitch: f32) {
+        // the camera's yaw wraps around; keep it continuous so turning through the wrap doesn't
+        // spin the camera the long way round on playback
+        let yaw = match self.path.keyframes.last() {
+            Some(prev) => yaw + ((prev.yaw - yaw) / (2.0 * PI)).round() * 2.0 * PI,
+            None => yaw,
+        };
+        self.path.keyframes.push(Keyframe {
+            time: self.time,
+            pos,
+            yaw,
+            pitch,
+        });
+    }
+}
+
+/************/
+/* Playback */
+/************/
+
+pub struct Playback {
+    pub path: CameraPath,
+
+    // seconds played so far
+    pub time: f32,
+}
+
+impl Playback {
+    pub fn new(path: CameraPath) -> Playback {
+        Playback { path, time: 0.0 }
+    }
+
+    // Advances by *dt* seconds, giving the camera state to show
+    pub fn advance(&mut self, dt: f32) -> Option<Keyframe> {
+        self.time += dt;
+        self.path.sample(self.time)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.time >= self.path.duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use na::vector;
+
+    fn key(time: f32, x: f32, yaw: f32) -> Keyframe {
+        Keyframe {
+            time,
+            pos: vector![x, 2.0, 0.0],
+            yaw,
+            pitch: 0.0,
+        }
+    }
+
+    #[test]
+    fn passes_through_keyframes() {
+        let path = CameraPath {
+            keyframes: vec![key(0.0, 0.0, 0.0), key(1.0, 3.0, 1.0), key(3.0, -1.0, 0.5)],
+        };
+        for k in &path.keyframes {
+            let s = path.sample(k.time).unwrap();
+            assert!((s.pos - k.pos).norm() < 1e-5 && (s.yaw - k.yaw).abs() < 1e-5);
+        }
+        assert_eq!(path.sample(-1.0).unwrap().pos, path.keyframes[0].pos);
+        assert_eq!(path.sample(10.0).unwrap().pos, path.keyframes[2].pos);
+    }
+
+    #[test]
+    fn steady_motion_stays_steady() {
+        let path = CameraPath {
+            keyframes: (0..5).map(|i| key(i as f32, 2.0 * i as f32, 0.0)).collect(),
+        };
+        for i in 0..40 {
+            let t = i as f32 / 10.0;
+            assert!((path.sample(t).unwrap().pos.x - 2.0 * t).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn saved_paths_load_back() {
+        let path = CameraPath {
+            keyframes: vec![key(0.0, 0.5, -3.0), key(0.25, 1.5, 7.0)],
+        };
+        let loaded = CameraPath::parse(&path.to_text()).unwrap();
+        assert_eq!(loaded.keyframes, path.keyframes);
+
+        assert!(CameraPath::parse("0 1 2 3").is_err());
+        assert!(CameraPath::parse("1 0 0 0 0 0\n0 0 0 0 0 0").is_err());
+        for bad in ["NaN", "inf", "-inf"] {
+            assert!(CameraPath::parse(&format!("{} 0 0 0 0 0\n1 0 0 0 0 0", bad)).is_err());
+            assert!(CameraPath::parse(&format!("0 0 {} 0 0 0", bad)).is_err());
+        }
+    }
+
+    #[test]
+    fn recorded_yaw_does_not_wrap() {
+        let mut rec = PathRecorder::new(0.5);
+        rec.record(0.5, Vector3::zeros(), 2.0 * PI - 0.1, 0.0);
+        rec.record(0.5, Vector3::zeros(), 0.1, 0.0);
+        let path = rec.finish(Vector3::zeros(), 0.2, 0.0);
+
+        let yaws: Vec<f32> = path.keyframes.iter().map(|k| k.yaw).collect();
+        assert_eq!(path.keyframes.len(), 3);
+        assert!((yaws[1] - (2.0 * PI + 0.1)).abs() < 1e-5, "{:?}", yaws);
+        assert!((yaws[2] - (2.0 * PI + 0.2)).abs() < 1e-5, "{:?}", yaws);
+    }
+}
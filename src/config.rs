@@ -0,0 +1,780 @@
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::debug_view::DebugView;
+use crate::gfx::SHADER_PATH_NAME;
+use crate::input::BINDINGS_PATH_NAME;
+use crate::post;
+use crate::projection::Projection;
+use crate::resolution;
+
+// read when present and no other config file is given on the command line
+pub const CONFIG_PATH_NAME: &str = "config.toml";
+
+pub const USAGE: &str = "\
+usage: ray [options]
+
+options:
+    --config <path>         read settings from this TOML file (default: config.toml, if present)
+    --level <path>          level to load, in any format a loader is registered for
+    --seed <n>              generate the world procedurally from this seed instead
+    --shader-dir <path>     directory the shaders are loaded and hot reloaded from
+    --post <pass,...>       post processing passes to run, in order (empty for none)
+    --render-scale <factor> fraction of the window resolution to march at, up to 1
+    --frame-budget <ms>     adjust the render scale to keep frames within this many milliseconds
+    --pixelated             upscale to blocky pixels instead of smoothing them
+    --debug-view <view>     show off, steps, sdf, depth, normals or material (cycled with F4)
+    --stats                 start with the frame statistics overlay shown (toggled with F3)
+    --stats-csv <path>      log the timings of every frame to this CSV file
+    --screenshot-scale <n>  take screenshots (F12) n times the window size, rendered in tiles
+    --record-video          start recording a video right away (toggled with F9)
+    --video-fps <n>         frames per second of recorded videos (default: 60)
+    --video-encoder <cmd>   pipe raw RGB video frames to this command instead of saving PNGs,
+                            with {width}, {height} and {fps} in it filled in
+    --bindings <path>       input bindings file
+    --projection <kind>     perspective, orthographic, equirectangular or fisheye
+    --fov <degrees>         horizontal field of view of perspective and fisheye projections
+    --ortho-size <voxels>   width of the view of the orthographic projection
+    --mouse-speed <factor>  mouse look sensitivity
+    --window-size <WxH>     initial window size, e.g. 1280x720
+    --vsync, --no-vsync     turn vertical sync on or off
+    --export <path>         write the level out in the format given by the extension and exit
+    --record-path <path>    record the camera while playing and save it as a camera path on exit
+    --play-path <path>      fly the camera along a recorded camera path
+    --capture-dir <dir>     render the camera path offscreen into numbered PNGs and exit
+    --capture-fps <n>       frames per second of the captured path (default: 60)
+    --record-input <path>   record every input and frame, saved on exit for replaying later
+    --replay-input <path>   replay a recording of inputs instead of taking live input
+    --print-config          print the resulting configuration as TOML and exit
+    --help                  print this message and exit
+
+Command line options override the config file, which overrides the defaults.";
+
+#[derive(Debug, Display)]
+pub enum ConfigError {
+    #[display(fmt = "unable to read {:?}: {}", _0, _1)]
+    Io(PathBuf, io::Error),
+
+    #[display(fmt = "invalid config file {:?}: {}", _0, _1)]
+    Parse(PathBuf, toml::de::Error),
+
+    #[display(fmt = "{}\n\n{}", _0, USAGE)]
+    Usage(String),
+
+    #[display(fmt = "invalid configuration:\n  {}", "_0.join(\"\\n  \")")]
+    Invalid(Vec<String>),
+}
+
+/**********/
+/* Config */
+/**********/
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub level: PathBuf,
+
+    // when set, the world is generated from this seed and `level` is ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    pub shader_dir: PathBuf,
+
+    // post processing passes run after marching, in order; each one is a shader in the post
+    // subdirectory of `shader_dir`
+    pub post_passes: Vec<String>,
+
+    // fraction of the window resolution to march at along each axis; the image is upscaled to
+    // the window afterwards
+    pub render_scale: f32,
+
+    // when set, the render scale starts at `render_scale` and is adjusted to keep frames within
+    // this many milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_budget: Option<f32>,
+
+    // upscale to blocky pixels instead of smoothing them
+    pub pixelated: bool,
+
+    // what the marcher shows to begin with, the shaded scene or one of the debug views
+    pub debug_view: DebugView,
+
+    // show the frame statistics overlay from the start, rather than once it's toggled on
+    pub show_stats: bool,
+
+    // screenshots taken with the key are this many times the window size along each axis,
+    // rendered offscreen in window sized tiles when above 1
+    pub screenshot_scale: u32,
+
+    pub bindings: PathBuf,
+
+    pub projection: ProjectionKind,
+
+    // horizontal field of view of the perspective and fisheye projections, in degrees
+    pub fov: f32,
+
+    // width of the orthographic view, in voxels
+    pub ortho_size: f32,
+
+    pub mouse_speed: f32,
+
+    pub window: WindowConfig,
+
+    pub video: VideoConfig,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectionKind {
+    Perspective,
+    Orthographic,
+    Equirectangular,
+    Fisheye,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    pub vsync: bool,
+}
+
+// How videos are recorded while playing (toggled with F9)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    // frames per second of simulated time; recording slows the game down to render every one
+    pub fps: u32,
+
+    // each recording goes into a new directory of numbered PNGs in here
+    pub dir: PathBuf,
+
+    // when set, frames are piped to this command instead, see `VideoRecorder::to_encoder`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            level: PathBuf::from("res/levels/test.gox"),
+            seed: None,
+            shader_dir: PathBuf::from(SHADER_PATH_NAME),
+            post_passes: post::DEFAULT_PASSES.iter().map(|p| p.to_string()).collect(),
+            render_scale: 1.0,
+            frame_budget: None,
+            pixelated: false,
+            debug_view: DebugView::Off,
+            show_stats: false,
+            screenshot_scale: 1,
+            bindings: PathBuf::from(BINDINGS_PATH_NAME),
+            projection: ProjectionKind::Perspective,
+            fov: 45.0,
+            ortho_size: 64.0,
+            mouse_speed: 1.0,
+            window: WindowConfig::default(),
+            video: VideoConfig::default(),
+        }
+    }
+}
+
+impl Default for WindowConfig {
+    fn default() -> WindowConfig {
+        WindowConfig {
+            width: 1024,
+            height: 768,
+            vsync: true,
+        }
+    }
+}
+
+impl Default for VideoConfig {
+    fn default() -> VideoConfig {
+        VideoConfig {
+            fps: 60,
+            dir: PathBuf::from("videos"),
+            encoder: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let src = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.into(), err))?;
+        toml::from_str(&src).map_err(|err| ConfigError::Parse(path.into(), err))
+    }
+
+    pub fn projection(&self) -> Projection {
+        match self.projection {
+            ProjectionKind::Perspective => Projection::Perspective { fov: self.fov },
+            ProjectionKind::Orthographic => Projection::Orthographic {
+                size: self.ortho_size,
+            },
+            ProjectionKind::Equirectangular => Projection::Equirectangular,
+            ProjectionKind::Fisheye => Projection::Fisheye { fov: self.fov },
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("config is always representable as TOML")
+    }
+
+    // Checks the settings make sense together, reporting every problem at once. Relative paths
+    // are looked up from *base*.
+    pub fn validate(&self, base: &Path) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+
+        if self.seed.is_none() && !base.join(&self.level).is_file() {
+            errors.push(format!("level: {:?} is not a file", self.level));
+        }
+        for pass in &self.post_passes {
+            let path = post::pass_path(&base.join(&self.shader_dir), pass);
+            if !path.is_file() {
+                errors.push(format!(
+                    "post_passes: no shader for {:?} at {:?}",
+                    pass, path
+                ));
+            }
+        }
+        if !(self.render_scale > 0.0 && self.render_scale <= 1.0) {
+            errors.push(format!(
+                "render_scale: must be above 0 and at most 1, got {}",
+                self.render_scale
+            ));
+        }
+        if !(1..=resolution::MAX_TILE_FACTOR).contains(&self.screenshot_scale) {
+            errors.push(format!(
+                "screenshot_scale: must be between 1 and {}, got {}",
+                resolution::MAX_TILE_FACTOR,
+                self.screenshot_scale
+            ));
+        }
+        if let Some(budget) = self.frame_budget {
+            if !(budget > 0.0 && budget.is_finite()) {
+                errors.push(format!("frame_budget: must be positive, got {}", budget));
+            }
+        }
+        if !base.join(&self.shader_dir).is_dir() {
+            errors.push(format!(
+                "shader_dir: {:?} is not a directory",
+                self.shader_dir
+            ));
+        }
+        // a pinhole camera can't see sideways, a fisheye can see all around
+        let max_fov = match self.projection {
+            ProjectionKind::Fisheye => 360.0,
+            _ => 180.0,
+        };
+        if !(self.fov > 0.0 && self.fov < max_fov) {
+            errors.push(format!(
+                "fov: must be between 0 and {} degrees, got {}",
+                max_fov, self.fov
+            ));
+        }
+        if !(self.ortho_size > 0.0 && self.ortho_size.is_finite()) {
+            errors.push(format!(
+                "ortho_size: must be positive, got {}",
+                self.ortho_size
+            ));
+        }
+        if !(self.mouse_speed > 0.0 && self.mouse_speed.is_finite()) {
+            errors.push(format!(
+                "mouse_speed: must be positive, got {}",
+                self.mouse_speed
+            ));
+        }
+        let (w, h) = (self.window.width, self.window.height);
+        if w == 0 || h == 0 || w > 16384 || h > 16384 {
+            errors.push(format!(
+                "window: size must be between 1x1 and 16384x16384, got {}x{}",
+                w, h
+            ));
+        }
+        if self.video.fps == 0 {
+            errors.push("video.fps: must be at least 1".to_string());
+        }
+        let encoder = self.video.encoder.as_deref();
+        if encoder.is_some_and(|e| e.trim().is_empty()) {
+            errors.push("video.encoder: must not be empty".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+}
+
+/****************/
+/* Command line */
+/****************/
+
+// What to do once the configuration is settled
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Run,
+    PrintConfig,
+    Help,
+}
+
+pub struct Options {
+    pub config: Config,
+    pub mode: Mode,
+
+    // `--export <path>`: write the level to this file instead of running the game
+    pub export: Option<PathBuf>,
+
+    // camera path to record to, or to play back (and maybe capture into `capture_dir`)
+    pub record_path: Option<PathBuf>,
+    pub play_path: Option<PathBuf>,
+    pub capture_dir: Option<PathBuf>,
+    pub capture_fps: u32,
+
+    // input recording to write, or to replay in place of live input
+    pub record_input: Option<PathBuf>,
+    pub replay_input: Option<PathBuf>,
+
+    // `--stats-csv <path>`: log the timings of every frame to this file
+    pub stats_csv: Option<PathBuf>,
+
+    // `--record-video`: start recording a video as soon as the game starts
+    pub record_video: bool,
+}
+
+// Builds the configuration from the defaults, the config file and the command line *args*
+// (without the program name), in increasing order of priority. The config file is the one given
+// with --config, or else *default_config* if there is one.
+pub fn from_args(args: &[String], default_config: Option<&Path>) -> Result<Options, ConfigError> {
+    let value = |i: usize| {
+        args.get(i + 1)
+            .ok_or_else(|| ConfigError::Usage(format!("missing value for {}", args[i])))
+    };
+    fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, ConfigError> {
+        value
+            .parse()
+            .map_err(|_| ConfigError::Usage(format!("invalid value for {}: {:?}", flag, value)))
+    }
+
+    // the config file has to be read before anything on the command line is applied to it
+    let config_flag = args.iter().position(|a| a == "--config");
+    let mut config = match config_flag {
+        Some(i) => Config::load(Path::new(value(i)?))?,
+        None => match default_config {
+            Some(path) if path.is_file() => Config::load(path)?,
+            _ => Config::default(),
+        },
+    };
+    let mut mode = Mode::Run;
+    let mut export = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut capture_dir = None;
+    let mut capture_fps = 60;
+    let mut record_input = None;
+    let mut replay_input = None;
+    let mut stats_csv = None;
+    let mut record_video = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        let takes_value = match flag {
+            "--config" => true,
+            "--level" => {
+                config.level = value(i)?.into();
+                config.seed = None;
+                true
+            }
+            "--seed" => {
+                config.seed = Some(parse(flag, value(i)?)?);
+                true
+            }
+            "--shader-dir" => {
+                config.shader_dir = value(i)?.into();
+                true
+            }
+            "--post" => {
+                config.post_passes = value(i)?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                true
+            }
+            "--render-scale" => {
+                config.render_scale = parse(flag, value(i)?)?;
+                true
+            }
+            "--frame-budget" => {
+                config.frame_budget = Some(parse(flag, value(i)?)?);
+                true
+            }
+            "--pixelated" => {
+                config.pixelated = true;
+                false
+            }
+            "--debug-view" => {
+                let name = value(i)?;
+                config.debug_view = DebugView::from_name(name)
+                    .ok_or_else(|| ConfigError::Usage(format!("unknown debug view {:?}", name)))?;
+                true
+            }
+            "--stats" => {
+                config.show_stats = true;
+                false
+            }
+            "--screenshot-scale" => {
+                config.screenshot_scale = parse(flag, value(i)?)?;
+                true
+            }
+            "--record-video" => {
+                record_video = true;
+                false
+            }
+            "--video-fps" => {
+                config.video.fps = parse(flag, value(i)?)?;
+                true
+            }
+            "--video-encoder" => {
+                config.video.encoder = Some(value(i)?.clone());
+                true
+            }
+            "--stats-csv" => {
+                stats_csv = Some(value(i)?.into());
+                true
+            }
+            "--bindings" => {
+                config.bindings = value(i)?.into();
+                true
+            }
+            "--fov" => {
+                config.fov = parse(flag, value(i)?)?;
+                true
+            }
+            "--projection" => {
+                let kind = value(i)?;
+                config.projection = toml::Value::String(kind.clone())
+                    .try_into()
+                    .map_err(|_| ConfigError::Usage(format!("unknown projection {:?}", kind)))?;
+                true
+            }
+            "--ortho-size" => {
+                config.ortho_size = parse(flag, value(i)?)?;
+                true
+            }
+            "--mouse-speed" => {
+                config.mouse_speed = parse(flag, value(i)?)?;
+                true
+            }
+            "--window-size" => {
+                let size = value(i)?;
+                let (w, h) = size
+                    .split_once('x')
+                    .ok_or_else(|| ConfigError::Usage(format!("invalid window size {:?}", size)))?;
+                config.window.width = parse(flag, w)?;
+                config.window.height = parse(flag, h)?;
+                true
+            }
+            "--vsync" | "--no-vsync" => {
+                config.window.vsync = flag == "--vsync";
+                false
+            }
+            "--export" => {
+                export = Some(value(i)?.into());
+                true
+            }
+            "--record-path" => {
+                record_path = Some(value(i)?.into());
+                true
+            }
+            "--play-path" => {
+                play_path = Some(value(i)?.into());
+                true
+            }
+            "--capture-dir" => {
+                capture_dir = Some(value(i)?.into());
+                true
+            }
+            "--capture-fps" => {
+                capture_fps = parse(flag, value(i)?)?;
+                true
+            }
+            "--record-input" => {
+                record_input = Some(value(i)?.into());
+                true
+            }
+            "--replay-input" => {
+                replay_input = Some(value(i)?.into());
+                true
+            }
+            "--print-config" => {
+                mode = Mode::PrintConfig;
+                false
+            }
+            "--help" | "-h" => {
+                mode = Mode::Help;
+                false
+            }
+            _ => return Err(ConfigError::Usage(format!("unknown option {:?}", flag))),
+        };
+        i += if takes_value { 2 } else { 1 };
+    }
+
+    if capture_dir.is_some() && play_path.is_none() {
+        return Err(ConfigError::Usage(
+            "--capture-dir needs a camera path to play".to_string(),
+        ));
+    }
+    if capture_fps == 0 {
+        return Err(ConfigError::Usage(
+            "--capture-fps must be at least 1".to_string(),
+        ));
+    }
+
+    Ok(Options {
+        config,
+        mode,
+        export,
+        record_path,
+        play_path,
+        capture_dir,
+        capture_fps,
+        record_input,
+        replay_input,
+        stats_csv,
+        record_video,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    // a directory holding the default level and post pass shaders, for the test called *name*
+    fn base_dir(name: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        let config = Config::default();
+        let shader_dir = dir.join(&config.shader_dir);
+        let files = config
+            .post_passes
+            .iter()
+            .map(|pass| post::pass_path(&shader_dir, pass))
+            .chain([dir.join(&config.level)]);
+        for file in files {
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let path = temp_dir("config").join("test.toml");
+        fs::write(
+            &path,
+            "fov = 70.0\nmouse_speed = 2.0\n[window]\nvsync = false\n",
+        )
+        .unwrap();
+
+        let options = from_args(
+            &args(&[
+                "--config",
+                path.to_str().unwrap(),
+                "--fov",
+                "90",
+                "--window-size",
+                "640x480",
+            ]),
+            None,
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let config = options.config;
+        assert_eq!(config.fov, 90.0);
+        assert_eq!(config.mouse_speed, 2.0);
+        assert!(!config.window.vsync);
+        assert_eq!((config.window.width, config.window.height), (640, 480));
+    }
+
+    #[test]
+    fn reads_the_default_config_file_if_present() {
+        let dir = temp_dir("config_default");
+        let default = dir.join("config.toml");
+        let config = from_args(&args(&[]), Some(&default)).unwrap().config;
+        assert_eq!(config.fov, Config::default().fov);
+
+        fs::write(&default, "fov = 60.0\n").unwrap();
+        let config = from_args(&args(&[]), Some(&default)).unwrap().config;
+        assert_eq!(config.fov, 60.0);
+
+        // a file given on the command line takes its place
+        let other = dir.join("other.toml");
+        fs::write(&other, "mouse_speed = 3.0\n").unwrap();
+        let config = from_args(
+            &args(&["--config", other.to_str().unwrap()]),
+            Some(&default),
+        )
+        .unwrap()
+        .config;
+        assert_eq!((config.fov, config.mouse_speed), (45.0, 3.0));
+    }
+
+    #[test]
+    fn finds_files_from_the_base_directory() {
+        let base = base_dir("config_base");
+        assert!(Config::default().validate(&base).is_ok());
+
+        // the level, shader directory and post passes are all missing from an empty directory
+        match Config::default().validate(&temp_dir("config_empty")) {
+            Err(ConfigError::Invalid(errors)) => {
+                assert_eq!(errors.len(), 2 + post::DEFAULT_PASSES.len(), "{:?}", errors)
+            }
+            other => panic!("expected validation errors, got {:?}", other.err()),
+        }
+
+        // a seed makes the level file unnecessary, and absolute paths ignore the base
+        let config = Config {
+            seed: Some(1),
+            level: base.join("missing.gox"),
+            shader_dir: base.join(SHADER_PATH_NAME),
+            ..Default::default()
+        };
+        assert!(config.validate(&temp_dir("config_absolute")).is_ok());
+    }
+
+    #[test]
+    fn printed_config_reads_back() {
+        let config = Config {
+            seed: Some(7),
+            window: WindowConfig {
+                width: 800,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let read: Config = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(read.seed, Some(7));
+        assert_eq!(read.window.width, 800);
+        assert_eq!(read.level, config.level);
+    }
+
+    #[test]
+    fn reports_every_invalid_setting() {
+        let config = Config {
+            fov: 200.0,
+            mouse_speed: 0.0,
+            window: WindowConfig {
+                height: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        match config.validate(&base_dir("config_invalid")) {
+            Err(ConfigError::Invalid(errors)) => assert_eq!(errors.len(), 3, "{:?}", errors),
+            other => panic!("expected validation errors, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn render_scale_is_a_fraction() {
+        let config = from_args(&args(&["--render-scale", "0.25", "--pixelated"]), None)
+            .unwrap()
+            .config;
+        assert_eq!(config.render_scale, 0.25);
+        assert!(config.pixelated);
+
+        let base = base_dir("config_render_scale");
+        for scale in [0.0, 1.5] {
+            let config = Config {
+                render_scale: scale,
+                ..Default::default()
+            };
+            assert!(config.validate(&base).is_err(), "accepted {}", scale);
+        }
+    }
+
+    #[test]
+    fn screenshot_scale_is_bounded() {
+        let config = from_args(&args(&["--screenshot-scale", "4"]), None)
+            .unwrap()
+            .config;
+        assert_eq!(config.screenshot_scale, 4);
+
+        let base = base_dir("config_screenshot_scale");
+        for scale in [0, resolution::MAX_TILE_FACTOR + 1] {
+            let config = Config {
+                screenshot_scale: scale,
+                ..Default::default()
+            };
+            assert!(config.validate(&base).is_err(), "accepted {}", scale);
+        }
+    }
+
+    #[test]
+    fn configures_video_recording() {
+        let options = from_args(
+            &args(&[
+                "--record-video",
+                "--video-fps",
+                "30",
+                "--video-encoder",
+                "ffmpeg -i - out.mp4",
+            ]),
+            None,
+        )
+        .unwrap();
+        assert!(options.record_video);
+        assert_eq!(options.config.video.fps, 30);
+        assert_eq!(
+            options.config.video.encoder.as_deref(),
+            Some("ffmpeg -i - out.mp4")
+        );
+
+        let mut config = Config::default();
+        config.video.fps = 0;
+        assert!(config.validate(&base_dir("config_video")).is_err());
+    }
+
+    #[test]
+    fn selects_projection() {
+        let config = from_args(&args(&["--projection", "fisheye", "--fov", "270"]), None)
+            .unwrap()
+            .config;
+        assert_eq!(config.projection(), Projection::Fisheye { fov: 270.0 });
+        assert!(from_args(&args(&["--projection", "cubic"]), None).is_err());
+    }
+
+    #[test]
+    fn selects_debug_view() {
+        let config = from_args(&args(&["--debug-view", "normals"]), None)
+            .unwrap()
+            .config;
+        assert_eq!(config.debug_view, DebugView::Normals);
+        assert!(from_args(&args(&["--debug-view", "wireframe"]), None).is_err());
+
+        let read: Config = toml::from_str("debug_view = \"steps\"\n").unwrap();
+        assert_eq!(read.debug_view, DebugView::Steps);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(from_args(&args(&["--fov"]), None).is_err());
+        assert!(from_args(&args(&["--fov", "wide"]), None).is_err());
+        assert!(from_args(&args(&["--frobnicate"]), None).is_err());
+    }
+}
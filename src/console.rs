@@ -0,0 +1,520 @@
+use derive_more::Display;
+use glium::glutin::event::VirtualKeyCode;
+
+use crate::brush;
+use crate::game::Game;
+use crate::gfx::DenseCartesianRenderer;
+use crate::history;
+use crate::prefab;
+use crate::resolution;
+use crate::world_exporter;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Display, PartialEq)]
+pub enum CommandError {
+    #[display(fmt = "{}", _0)]
+    Parse(String),
+
+    #[display(fmt = "unknown command {:?}, see help", _0)]
+    Unknown(String),
+
+    // missing or invalid arguments, with the usage of the command once it's known
+    #[display(fmt = "{}", _0)]
+    Usage(String),
+
+    // the command ran but couldn't do what it was asked
+    #[display(fmt = "{}", _0)]
+    Failed(String),
+}
+
+// Splits a command line into words at whitespace. Words can be quoted with double quotes to
+// include whitespace, e.g. for paths.
+pub fn parse(line: &str) -> Result<Vec<String>, CommandError> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => word.push(c),
+                    None => return Err(CommandError::Parse("unterminated quote".to_string())),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/********/
+/* Args */
+/********/
+
+// The arguments of a command, taken one by one by its handler
+pub struct Args<'a> {
+    words: &'a [String],
+    next: usize,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(words: &'a [String]) -> Args<'a> {
+        Args { words, next: 0 }
+    }
+
+    // The next argument, converted by *convert*; *name* says which one it is in errors
+    pub fn next_with<T>(
+        &mut self,
+        name: &str,
+        convert: impl Fn(&str) -> Option<T>,
+    ) -> Result<T, CommandError> {
+        let word = self
+            .words
+            .get(self.next)
+            .ok_or_else(|| CommandError::Usage(format!("missing {}", name)))?;
+        self.next += 1;
+        convert(word).ok_or_else(|| CommandError::Usage(format!("invalid {}: {:?}", name, word)))
+    }
+
+    pub fn next<T: FromStr>(&mut self, name: &str) -> Result<T, CommandError> {
+        self.next_with(name, |word| word.parse().ok())
+    }
+
+    // The next argument if there is one
+    pub fn optional<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, CommandError> {
+        if self.next < self.words.len() {
+            self.next(name).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Checks every argument was taken. Handlers call this before acting, so that a command with
+    // too many arguments does nothing rather than something unexpected.
+    pub fn end(&self) -> Result<(), CommandError> {
+        match self.words.get(self.next) {
+            Some(word) => Err(CommandError::Usage(format!("unexpected {:?}", word))),
+            None => Ok(()),
+        }
+    }
+}
+
+/************/
+/* Commands */
+/************/
+
+// what a command gives back to show in the console
+pub type Output = Result<String, CommandError>;
+
+type Handler<C> = Box<dyn Fn(&mut C, &mut Args) -> Output>;
+
+struct Command<C> {
+    // arguments, as shown by help, e.g. "<x> <y> <z>"
+    usage: String,
+    help: String,
+    handler: Handler<C>,
+}
+
+// Commands acting on a *C*. Each subsystem registers its own commands on itself, and those are
+// included into the commands of whatever contains it, down to the console's `Context`.
+pub struct Commands<C> {
+    commands: BTreeMap<String, Command<C>>,
+}
+
+impl<C> Default for Commands<C> {
+    fn default() -> Commands<C> {
+        Commands {
+            commands: BTreeMap::new(),
+        }
+    }
+}
+
+impl<C> Commands<C> {
+    pub fn add(
+        &mut self,
+        name: &str,
+        usage: &str,
+        help: &str,
+        handler: impl Fn(&mut C, &mut Args) -> Output + 'static,
+    ) {
+        let command = Command {
+            usage: usage.to_string(),
+            help: help.to_string(),
+            handler: Box::new(handler),
+        };
+        self.commands.insert(name.to_string(), command);
+    }
+
+    // Adds the commands of a part of *C*, found with *part*
+    pub fn include<S: 'static>(
+        &mut self,
+        other: Commands<S>,
+        part: impl Fn(&mut C) -> &mut S + Copy + 'static,
+    ) {
+        for (name, command) in other.commands {
+            let handler = command.handler;
+            let command = Command {
+                usage: command.usage,
+                help: command.help,
+                handler: Box::new(move |c: &mut C, args: &mut Args| handler(part(c), args)),
+            };
+            self.commands.insert(name, command);
+        }
+    }
+
+    // Every command with its usage and what it does
+    pub fn help(&self) -> String {
+        let lines: Vec<String> = self
+            .commands
+            .iter()
+            .map(|(name, c)| format!("{} {}\n    {}", name, c.usage, c.help))
+            .collect();
+        lines.join("\n")
+    }
+
+    // Parses *line* and runs the command it names on *target*. `help` lists the commands.
+    pub fn run(&self, target: &mut C, line: &str) -> Output {
+        let words = parse(line)?;
+        let (name, args) = match words.split_first() {
+            Some(split) => split,
+            None => return Ok(String::new()),
+        };
+        if name == "help" {
+            return Ok(self.help());
+        }
+        let command = self
+            .commands
+            .get(name)
+            .ok_or_else(|| CommandError::Unknown(name.clone()))?;
+        (command.handler)(target, &mut Args::new(args)).map_err(|err| match err {
+            CommandError::Usage(msg) => {
+                CommandError::Usage(format!("{}\nusage: {} {}", msg, name, command.usage))
+            }
+            err => err,
+        })
+    }
+}
+
+/***********/
+/* Context */
+/***********/
+
+// Something only the main loop can do, asked for by a command
+#[derive(Clone, PartialEq, Debug)]
+pub enum Request {
+    // load the level at the path in the background, and watch it like the one given at startup
+    Load(PathBuf),
+
+    // save what's on screen as a PNG, at the path if given; at a scale above 1 the image is that
+    // many times the window size along each axis, rendered again offscreen in tiles
+    Screenshot { path: Option<PathBuf>, scale: u32 },
+
+    // start recording a video, or stop the one being recorded
+    Video(bool),
+}
+
+// Everything the console's commands can reach
+pub struct Context<'a> {
+    pub game: &'a mut Game,
+    pub renderer: &'a mut DenseCartesianRenderer,
+
+    // left for the main loop to carry out
+    pub requests: Vec<Request>,
+}
+
+// The console's commands: those of each subsystem, and those the main loop carries out
+pub fn commands<'a>() -> Commands<Context<'a>> {
+    let mut commands: Commands<Context> = Commands::default();
+    commands.include(Game::commands(), |ctx: &mut Context| &mut *ctx.game);
+    commands.include(brush::commands(), |ctx: &mut Context| &mut ctx.game.world);
+    commands.include(history::commands(), |ctx: &mut Context| &mut ctx.game.world);
+    commands.include(prefab::commands(), |ctx: &mut Context| &mut *ctx.game);
+    commands.include(world_exporter::commands(), |ctx: &mut Context| {
+        &mut ctx.game.world
+    });
+    commands.include(DenseCartesianRenderer::commands(), |ctx: &mut Context| {
+        &mut *ctx.renderer
+    });
+
+    commands.add(
+        "load",
+        "<level>",
+        "load a level in the background, in any format a loader is registered for",
+        |ctx, args| {
+            let path: PathBuf = args.next("level")?;
+            args.end()?;
+            ctx.requests.push(Request::Load(path.clone()));
+            Ok(format!("loading {:?}", path))
+        },
+    );
+    commands.add(
+        "screenshot",
+        "[file.png]",
+        "save what's on screen as a PNG",
+        |ctx, args| {
+            let path = args.optional("file")?;
+            args.end()?;
+            ctx.requests.push(Request::Screenshot { path, scale: 1 });
+            Ok(String::new())
+        },
+    );
+    commands.add(
+        "screenshot_tiled",
+        "<scale> [file.png]",
+        "save a PNG scale times the window size, rendered in tiles",
+        |ctx, args| {
+            let scale = args.next_with("scale", |word| {
+                word.parse()
+                    .ok()
+                    .filter(|n| (1..=resolution::MAX_TILE_FACTOR).contains(n))
+            })?;
+            let path = args.optional("file")?;
+            args.end()?;
+            ctx.requests.push(Request::Screenshot { path, scale });
+            Ok(String::new())
+        },
+    );
+    commands.add(
+        "video",
+        "<start|stop>",
+        "record every frame at a fixed frame rate, as set in the video section of the config",
+        |ctx, args| {
+            let start = args.next_with("start or stop", |word| match word {
+                "start" => Some(true),
+                "stop" => Some(false),
+                _ => None,
+            })?;
+            args.end()?;
+            ctx.requests.push(Request::Video(start));
+            Ok(String::new())
+        },
+    );
+    commands
+}
+
+/***********/
+/* Console */
+/***********/
+
+// A drop-down console to type commands into, drawn as text over the game
+#[derive(Default)]
+pub struct Console {
+    pub open: bool,
+
+    // the line being typed
+    pub input: String,
+
+    // commands entered and what they gave back, oldest first
+    pub output: Vec<String>,
+
+    // commands entered, oldest first, and which one is recalled with the up and down keys
+    history: Vec<String>,
+    recalled: Option<usize>,
+
+    // set when the console opens, so the character of the key that opened it isn't typed
+    swallow: bool,
+}
+
+impl Console {
+    // lines of output shown under the input
+    const SHOWN_LINES: usize = 12;
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.swallow = self.open;
+    }
+
+    // Called once a frame; a character that didn't come right after the key that opened the
+    // console was typed on purpose
+    pub fn frame(&mut self) {
+        self.swallow = false;
+    }
+
+    pub fn character(&mut self, c: char) {
+        if std::mem::take(&mut self.swallow) || c.is_control() {
+            return;
+        }
+        self.input.push(c);
+    }
+
+    // Handles the editing keys, returning the line to run when return is pressed
+    pub fn key(&mut self, key: VirtualKeyCode) -> Option<String> {
+        match key {
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+                let line = std::mem::take(&mut self.input);
+                self.recalled = None;
+                if line.trim().is_empty() {
+                    return None;
+                }
+                if self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                }
+                self.output.push(format!("> {}", line));
+                return Some(line);
+            }
+            VirtualKeyCode::Back => {
+                self.input.pop();
+            }
+            VirtualKeyCode::Escape => self.open = false,
+            VirtualKeyCode::Up if !self.history.is_empty() => {
+                let i = self
+                    .recalled
+                    .map_or(self.history.len() - 1, |i| i.saturating_sub(1));
+                self.recalled = Some(i);
+                self.input = self.history[i].clone();
+            }
+            VirtualKeyCode::Down => match self.recalled {
+                Some(i) if i + 1 < self.history.len() => {
+                    self.recalled = Some(i + 1);
+                    self.input = self.history[i + 1].clone();
+                }
+                _ => {
+                    self.recalled = None;
+                    self.input.clear();
+                }
+            },
+            _ => (),
+        }
+        None
+    }
+
+    // Shows what a command gave back
+    pub fn print(&mut self, output: Output) {
+        let text = match output {
+            Ok(text) => text,
+            Err(err) => format!("error: {}", err),
+        };
+        self.output.extend(text.lines().map(str::to_string));
+    }
+
+    // Text of the console: the latest output, then the line being typed
+    pub fn text(&self) -> String {
+        let start = self.output.len().saturating_sub(Console::SHOWN_LINES);
+        let mut lines = self.output[start..].to_vec();
+        lines.push(format!("] {}_", self.input));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Counter {
+        count: i32,
+    }
+
+    #[derive(Default)]
+    struct Outer {
+        counter: Counter,
+        name: String,
+    }
+
+    fn counter_commands() -> Commands<Counter> {
+        let mut commands: Commands<Counter> = Commands::default();
+        commands.add("add", "<n> [times]", "add n to the count", |c, args| {
+            let n: i32 = args.next("n")?;
+            let times = args.optional("times")?.unwrap_or(1);
+            args.end()?;
+            c.count += n * times;
+            Ok(c.count.to_string())
+        });
+        commands
+    }
+
+    fn outer_commands() -> Commands<Outer> {
+        let mut commands: Commands<Outer> = Commands::default();
+        commands.include(counter_commands(), |o: &mut Outer| &mut o.counter);
+        commands.add("name", "<name>", "set the name", |o, args| {
+            o.name = args.next("name")?;
+            args.end()?;
+            Ok(String::new())
+        });
+        commands
+    }
+
+    #[test]
+    fn parses_quoted_words() {
+        assert_eq!(
+            parse("  load \"my level.gox\"  x ").unwrap(),
+            ["load", "my level.gox", "x"]
+        );
+        assert_eq!(parse("").unwrap(), Vec::<String>::new());
+        assert!(parse("load \"oops").is_err());
+    }
+
+    #[test]
+    fn dispatches_to_included_commands() {
+        let commands = outer_commands();
+        let mut outer = Outer::default();
+
+        assert_eq!(commands.run(&mut outer, "add 2 3"), Ok("6".to_string()));
+        assert_eq!(commands.run(&mut outer, "add -1"), Ok("5".to_string()));
+        commands.run(&mut outer, "name \"a b\"").unwrap();
+        assert_eq!(outer.name, "a b");
+        assert!(commands
+            .run(&mut outer, "help")
+            .unwrap()
+            .contains("add <n> [times]"));
+        assert_eq!(commands.run(&mut outer, ""), Ok(String::new()));
+    }
+
+    #[test]
+    fn reports_bad_commands_without_running_them() {
+        let commands = outer_commands();
+        let mut outer = Outer::default();
+
+        assert_eq!(
+            commands.run(&mut outer, "jump"),
+            Err(CommandError::Unknown("jump".to_string()))
+        );
+        let err = commands.run(&mut outer, "add x").unwrap_err().to_string();
+        assert_eq!(err, "invalid n: \"x\"\nusage: add <n> [times]");
+        assert!(commands.run(&mut outer, "add").is_err());
+        assert!(commands.run(&mut outer, "add 1 2 3").is_err());
+        assert_eq!(outer.counter.count, 0);
+    }
+
+    #[test]
+    fn edits_and_recalls_lines() {
+        let mut console = Console::default();
+        console.toggle();
+        // the key that opened it
+        console.character('`');
+        for c in "tp 1 2 3".chars() {
+            console.character(c);
+        }
+        assert_eq!(
+            console.key(VirtualKeyCode::Return),
+            Some("tp 1 2 3".to_string())
+        );
+        console.print(Err(CommandError::Failed("no".to_string())));
+        assert!(console.text().ends_with("> tp 1 2 3\nerror: no\n] _"));
+
+        console.character('x');
+        console.key(VirtualKeyCode::Back);
+        console.key(VirtualKeyCode::Up);
+        assert_eq!(console.input, "tp 1 2 3");
+        console.key(VirtualKeyCode::Down);
+        assert_eq!(console.input, "");
+        assert_eq!(console.key(VirtualKeyCode::Return), None);
+    }
+}
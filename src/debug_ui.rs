@@ -0,0 +1,368 @@
+use glium::glutin::event::VirtualKeyCode;
+
+use crate::debug_view::DebugView;
+use crate::game::Game;
+use crate::gfx::DenseCartesianRenderer;
+use crate::projection::Projection;
+
+use std::ops::RangeInclusive;
+
+// Keyboard navigation of the panel. The mouse stays with the camera, so the panel is driven with
+// the arrow keys while it is open: up and down select a setting, left and right change it, and
+// return toggles it or moves it to its next value.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Nav {
+    Up,
+    Down,
+    Left,
+    Right,
+    Activate,
+}
+
+impl Nav {
+    pub fn from_key(key: VirtualKeyCode) -> Option<Nav> {
+        match key {
+            VirtualKeyCode::Up => Some(Nav::Up),
+            VirtualKeyCode::Down => Some(Nav::Down),
+            VirtualKeyCode::Left => Some(Nav::Left),
+            VirtualKeyCode::Right => Some(Nav::Right),
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Some(Nav::Activate),
+            _ => None,
+        }
+    }
+}
+
+/************/
+/* Debug UI */
+/************/
+
+// An immediate mode panel of information and settings, drawn as text over the game. The widgets
+// are declared anew every frame by whoever owns the values they show, so nothing is copied in or
+// out of the panel; only the selection and the keys pressed since the last frame are kept here.
+#[derive(Default)]
+pub struct DebugUi {
+    pub open: bool,
+
+    // index of the selected widget, among those that can be changed
+    selected: usize,
+
+    // number of widgets that could be changed in the last frame, to wrap the selection around
+    widgets: usize,
+
+    // navigation since the last frame, applied when the next one is built
+    pending: Vec<Nav>,
+}
+
+impl DebugUi {
+    // Takes a key press meant for the panel, returning whether it was one, so that the game
+    // doesn't act on it too
+    pub fn key(&mut self, key: VirtualKeyCode) -> bool {
+        if !self.open {
+            return false;
+        }
+        match Nav::from_key(key) {
+            Some(nav) => {
+                self.pending.push(nav);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Starts laying out this frame's panel
+    pub fn begin(&mut self) -> Panel<'_> {
+        let mut adjust = Vec::new();
+        for nav in std::mem::take(&mut self.pending) {
+            match nav {
+                Nav::Up if self.widgets > 0 => {
+                    self.selected = (self.selected + self.widgets - 1) % self.widgets;
+                    adjust.clear();
+                }
+                Nav::Down if self.widgets > 0 => {
+                    self.selected = (self.selected + 1) % self.widgets;
+                    adjust.clear();
+                }
+                Nav::Left => adjust.push(-1),
+                Nav::Right | Nav::Activate => adjust.push(1),
+                _ => (),
+            }
+        }
+        Panel {
+            adjust: adjust.iter().sum(),
+            activated: !adjust.is_empty(),
+            ui: self,
+            lines: Vec::new(),
+            widgets: 0,
+        }
+    }
+}
+
+// One frame of the panel, collecting its lines as the widgets are declared
+pub struct Panel<'a> {
+    ui: &'a mut DebugUi,
+    lines: Vec<String>,
+
+    // widgets declared so far that can be changed
+    widgets: usize,
+
+    // steps to move the selected widget's value by this frame, and whether it was touched at all
+    adjust: i32,
+    activated: bool,
+}
+
+impl<'a> Panel<'a> {
+    // width of the names in front of the values, in characters
+    const NAME_WIDTH: usize = 16;
+
+    pub fn heading(&mut self, title: &str) {
+        if !self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        self.lines.push(format!("-- {} --", title));
+    }
+
+    pub fn label(&mut self, name: &str, value: impl std::fmt::Display) {
+        self.lines
+            .push(format!("  {:w$} {}", name, value, w = Self::NAME_WIDTH));
+    }
+
+    // Declares a widget, giving how many steps to change it by if it's selected
+    fn widget(&mut self, name: &str, value: String) -> Option<i32> {
+        let selected = self.widgets == self.ui.selected;
+        self.widgets += 1;
+        let marker = if selected { '>' } else { ' ' };
+        self.lines.push(format!(
+            "{} {:w$} < {} >",
+            marker,
+            name,
+            value,
+            w = Self::NAME_WIDTH
+        ));
+        (selected && self.activated).then_some(self.adjust)
+    }
+
+    // A number changed in steps of *step* within *range*, returning whether it changed
+    pub fn slider(
+        &mut self,
+        name: &str,
+        value: &mut f32,
+        range: RangeInclusive<f32>,
+        step: f32,
+    ) -> bool {
+        let steps = match self.widget(name, format!("{:.2}", value)) {
+            Some(steps) => steps,
+            None => return false,
+        };
+        let old = *value;
+        // snapped to the steps, so that they add up to round numbers
+        let new = ((*value + steps as f32 * step) / step).round() * step;
+        *value = new.clamp(*range.start(), *range.end());
+        *value != old
+    }
+
+    // An on/off setting, flipped by any key that changes it
+    pub fn toggle(&mut self, name: &str, value: &mut bool) -> bool {
+        let text = if *value { "on" } else { "off" };
+        match self.widget(name, text.to_string()) {
+            Some(steps) if steps % 2 != 0 => {
+                *value = !*value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // One of *options*, shown by *label*, wrapping around at either end
+    pub fn choice<T: Copy + PartialEq>(
+        &mut self,
+        name: &str,
+        value: &mut T,
+        options: &[T],
+        label: impl Fn(&T) -> &'static str,
+    ) -> bool {
+        let steps = match self.widget(name, label(value).to_string()) {
+            Some(steps) => steps,
+            None => return false,
+        };
+        let n = options.len() as i32;
+        let i = options.iter().position(|o| o == value).unwrap_or(0) as i32;
+        let old = *value;
+        *value = options[(i + steps).rem_euclid(n) as usize];
+        *value != old
+    }
+
+    // The text of the panel
+    pub fn finish(self) -> String {
+        self.ui.widgets = self.widgets;
+        if self.widgets > 0 {
+            self.ui.selected = self.ui.selected.min(self.widgets - 1);
+        }
+        let mut lines = self.lines;
+        lines.push(String::new());
+        lines.push("up/down: select   left/right/return: change".to_string());
+        lines.join("\n")
+    }
+}
+
+/******************/
+/* Panel contents */
+/******************/
+
+// Counts of what the world is made of, which takes a scan of the whole world to work out
+pub struct WorldStats {
+    pub filled: usize,
+    pub total: usize,
+}
+
+impl WorldStats {
+    pub fn count(game: &Game) -> WorldStats {
+        let voxels = &game.world.voxels;
+        WorldStats {
+            filled: voxels.iter().filter(|v| !v.is_empty()).count(),
+            total: voxels.len(),
+        }
+    }
+}
+
+// Lays out the panel over the game and the renderer, applying any changes made in it right
+// away. *auto_scale* is set when the render scale is being adjusted to a frame budget.
+pub fn build(
+    ui: &mut DebugUi,
+    game: &mut Game,
+    renderer: &mut DenseCartesianRenderer,
+    world: &WorldStats,
+    auto_scale: bool,
+) -> String {
+    let mut panel = ui.begin();
+
+    panel.heading("camera");
+    let cam = &game.camera;
+    panel.label(
+        "position",
+        format!("{:.1} {:.1} {:.1}", cam.pos.x, cam.pos.y, cam.pos.z),
+    );
+    panel.label(
+        "yaw / pitch",
+        format!(
+            "{:.1} / {:.1}",
+            cam.yaw.to_degrees(),
+            cam.pitch.to_degrees()
+        ),
+    );
+    panel.label("movement", format!("{:?}", game.player.mode));
+
+    panel.heading("world");
+    let (x, y, z) = *game.world.voxels.shape();
+    panel.label("size", format!("{}x{}x{}", x, y, z));
+    panel.label(
+        "filled voxels",
+        format!(
+            "{} ({:.1}%)",
+            world.filled,
+            100.0 * world.filled as f32 / world.total.max(1) as f32
+        ),
+    );
+
+    panel.heading("view");
+    match &mut game.camera.projection {
+        Projection::Perspective { fov } => {
+            panel.slider("fov", fov, 10.0..=170.0, 5.0);
+        }
+        Projection::Fisheye { fov } => {
+            panel.slider("fov", fov, 10.0..=360.0, 5.0);
+        }
+        Projection::Orthographic { size } => {
+            panel.slider("view width", size, 4.0..=512.0, 4.0);
+        }
+        Projection::Equirectangular => panel.label("fov", "360 (equirectangular)"),
+    }
+    panel.slider("mouse speed", &mut game.mouse_speed, 0.1..=5.0, 0.1);
+
+    panel.heading("lighting");
+    let lighting = &mut renderer.lighting;
+    panel.slider("intensity", &mut lighting.intensity, 0.0..=1000.0, 10.0);
+    panel.slider("saturation", &mut lighting.saturation, 0.0..=1.0, 0.05);
+
+    panel.heading("renderer");
+    panel.choice(
+        "debug view",
+        &mut renderer.debug_view,
+        &DebugView::ALL,
+        DebugView::name,
+    );
+    if auto_scale {
+        panel.label(
+            "render scale",
+            format!("{:.2} (frame budget)", renderer.render_scale),
+        );
+    } else {
+        panel.slider("render scale", &mut renderer.render_scale, 0.25..=1.0, 0.05);
+    }
+    panel.toggle("pixelated", &mut renderer.post.pixelated);
+    for pass in &mut renderer.post.passes {
+        panel.toggle(&format!("post/{}", pass.name), &mut pass.enabled);
+    }
+
+    panel.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // lays out a panel of a slider, a toggle and a choice over the given values
+    fn frame(ui: &mut DebugUi, value: &mut f32, flag: &mut bool, view: &mut DebugView) -> String {
+        let mut panel = ui.begin();
+        panel.heading("test");
+        panel.label("info", 42);
+        panel.slider("value", value, 0.0..=1.0, 0.25);
+        panel.toggle("flag", flag);
+        panel.choice("view", view, &DebugView::ALL, DebugView::name);
+        panel.finish()
+    }
+
+    #[test]
+    fn keys_only_go_to_an_open_panel() {
+        let mut ui = DebugUi::default();
+        assert!(!ui.key(VirtualKeyCode::Up));
+        ui.open = true;
+        assert!(ui.key(VirtualKeyCode::Up));
+        assert!(!ui.key(VirtualKeyCode::W));
+    }
+
+    #[test]
+    fn navigates_and_changes_widgets() {
+        let mut ui = DebugUi {
+            open: true,
+            ..Default::default()
+        };
+        let (mut value, mut flag, mut view) = (0.5, false, DebugView::Off);
+
+        let text = frame(&mut ui, &mut value, &mut flag, &mut view);
+        assert!(text.contains("> value"), "{}", text);
+        assert!(text.contains("  info"), "{}", text);
+
+        // the slider stops at the end of its range
+        for _ in 0..3 {
+            ui.key(VirtualKeyCode::Right);
+        }
+        frame(&mut ui, &mut value, &mut flag, &mut view);
+        assert_eq!(value, 1.0);
+
+        ui.key(VirtualKeyCode::Down);
+        ui.key(VirtualKeyCode::Return);
+        let text = frame(&mut ui, &mut value, &mut flag, &mut view);
+        assert!(flag);
+        assert!(text.contains("> flag"), "{}", text);
+
+        // choices wrap around, and so does the selection
+        ui.key(VirtualKeyCode::Down);
+        ui.key(VirtualKeyCode::Left);
+        frame(&mut ui, &mut value, &mut flag, &mut view);
+        assert_eq!(view, DebugView::Material);
+        ui.key(VirtualKeyCode::Down);
+        let text = frame(&mut ui, &mut value, &mut flag, &mut view);
+        assert!(text.contains("> value"), "{}", text);
+        assert_eq!((value, flag), (1.0, true));
+    }
+}
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+// sample counts, SDF values and hit distances at which the debug views run out of color; shared
+// with the shaders as DEBUG_*_RANGE by `gfx::shader_defines`
+pub const STEPS_RANGE: f32 = 256.0;
+pub const SDF_RANGE: f32 = 8.0;
+pub const DEPTH_RANGE: f32 = 192.0;
+
+// What the marcher shows in place of the shaded scene, to see what it is doing. Each view is
+// drawn by the matching branch of `debug_color` in debug.glsl; the post passes are skipped while
+// one is on, so its colors reach the screen unchanged.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DebugView {
+    // the shaded scene
+    #[default]
+    Off,
+
+    // heatmap of the SDF samples taken for each pixel, blue for few and red for `STEPS_RANGE`
+    Steps,
+
+    // heatmap of the SDF value of the last sample before the hit
+    Sdf,
+
+    // distance to the hit, white up close fading to black at `DEPTH_RANGE`
+    Depth,
+
+    // normal of the face hit, each axis mapped from -1..1 to 0..1
+    Normals,
+
+    // a false color for each voxel id, white where the ray left the world
+    Material,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 6] = [
+        DebugView::Off,
+        DebugView::Steps,
+        DebugView::Sdf,
+        DebugView::Depth,
+        DebugView::Normals,
+        DebugView::Material,
+    ];
+
+    // id of the view in the shader's `debug_view` uniform
+    pub fn id(&self) -> i32 {
+        *self as i32
+    }
+
+    // name of the view in config files and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            DebugView::Off => "off",
+            DebugView::Steps => "steps",
+            DebugView::Sdf => "sdf",
+            DebugView::Depth => "depth",
+            DebugView::Normals => "normals",
+            DebugView::Material => "material",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DebugView> {
+        DebugView::ALL.iter().copied().find(|v| v.name() == name)
+    }
+
+    // The view after this one, going back to `Off` after the last
+    pub fn next(&self) -> DebugView {
+        DebugView::ALL[(self.id() as usize + 1) % DebugView::ALL.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_through_every_view() {
+        let mut view = DebugView::Off;
+        for (i, expected) in DebugView::ALL.iter().enumerate() {
+            assert_eq!(view, *expected);
+            assert_eq!(view.id(), i as i32);
+            assert_eq!(DebugView::from_name(view.name()), Some(view));
+            view = view.next();
+        }
+        assert_eq!(view, DebugView::Off);
+        assert_eq!(DebugView::from_name("wireframe"), None);
+    }
+}
@@ -2,95 +2,320 @@
 use glium::{glutin, Surface};
 use std::f32::consts::PI;
 
-use na::vector;
+use na::{vector, Rotation3, Vector2, Vector3};
 //use na::{Rotation3, Vector4};
 
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 
 //use glium::texture::integral_texture3d::IntegralTexture3d;
 
+use crate::camera_path::{CameraPath, PathRecorder, Playback};
+use crate::console::{CommandError, Commands};
+use crate::input::{Action, Binding, InputBindings, InputState};
+use crate::physics::{MovementMode, PlayerController};
+use crate::prefab::Clipboard;
+use crate::projection::Projection;
+use crate::replay::{self, Entry, InputEvent, InputRecording};
 use crate::world::{Space, WORLD_DIM};
 
-use std::fs::File;
-
 
 pub struct Game {
-    pub begin_time: SystemTime,
+    // simulated time since the game started
     pub time_elapsed: Duration,
-    pub keyboard: KeyboardState,
+
+    // simulated time not yet consumed by a fixed step
+    pub accumulator: Duration,
+    pub input: InputState,
     pub mouse_delta: (f32, f32),
     pub camera: Camera,
     pub world: Space,
     //pub world_uniforms: Dense
     pub walk_speed: f32,
+    pub player: PlayerController,
+    pub mouse_speed: f32,
+
+    // camera position before the last fixed step, for interpolating between steps when rendering
+    pub prev_camera_pos: Vector3<f32>,
+
+    // camera path driving the camera instead of user input
+    pub playback: Option<Playback>,
+
+    // camera path being recorded from live play
+    pub recorder: Option<PathRecorder>,
+
+    // every input and frame, for replaying the session later
+    pub input_recording: Option<InputRecording>,
+
+    // voxels copied from the world, kept when another level is loaded
+    pub clipboard: Clipboard,
 }
 
 impl Game {
     const STRAFE_FACTOR: f32 = 0.5;
-    const MOUSE_SPEED: f32 = 1.0;
 
-    pub fn new() -> Game {
-        let mut file = File::open("res/levels/test.gox").unwrap();
+    // the simulation always advances in steps of this length, whatever the frame rate
+    pub const TIMESTEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+    // longest frame simulated in full; after a longer hitch the game slows down instead of
+    // running a huge batch of steps at once
+    const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+    pub fn with_world(world: Space) -> Game {
+        let pos = vector![
+            1.0 + (WORLD_DIM.0 / 16) as f32,
+            1.0 + (WORLD_DIM.1 / 16) as f32,
+            1.0 + (WORLD_DIM.2 / 16) as f32
+        ];
         Game {
-            begin_time: SystemTime::now(),
             time_elapsed: Duration::from_millis(0),
+            accumulator: Duration::from_millis(0),
             camera: Camera {
-                pos: vector![1.0+(WORLD_DIM.0 / 16) as f32, 1.0+(WORLD_DIM.1 / 16) as f32, 1.0+(WORLD_DIM.2 / 16) as f32],
+                pos,
                 yaw: 0.0,
                 pitch: 0.0,
                 dir: vector![0.0, 0.0, 1.0],
                 forward: vector![0.0, 0.0, 1.0],
                 right: vector![1.0, 0.0, 0.0],
+                projection: Projection::Perspective { fov: 45.0 },
             },
-            keyboard: KeyboardState {
-                ..Default::default()
-            },
+            input: InputState::new(InputBindings::default()),
             mouse_delta: (0.0, 0.0),
-            world: Space::from_gox(WORLD_DIM, &mut file),
+            world,
             walk_speed: 1.0,
+            player: PlayerController::new(MovementMode::Fly),
+            mouse_speed: 1.0,
+            prev_camera_pos: pos,
+            playback: None,
+            recorder: None,
+            input_recording: None,
+            clipboard: Clipboard::default(),
+        }
+    }
+
+    // Advances the game by *frame_time* of real time: looking around is applied right away, while
+    // movement runs in as many fixed steps as fit, carrying the remainder over to the next frame
+    pub fn update(&mut self, frame_time: Duration) {
+        if let Some(recording) = &mut self.input_recording {
+            recording.push(self.time_elapsed, Entry::Frame(frame_time));
+        }
+
+        let frame_time = frame_time.min(Game::MAX_FRAME_TIME);
+        self.time_elapsed += frame_time;
+
+        // looking around is left to the camera path while one is playing
+        if self.playback.is_none() {
+            self.camera.set_rotation(
+                self.camera.yaw + self.mouse_delta.0 * 1e-3 * self.mouse_speed,
+                self.camera.pitch - self.mouse_delta.1 * 1e-3 * self.mouse_speed,
+            );
+        }
+        self.mouse_delta = (0.0, 0.0);
+
+        self.accumulator += frame_time;
+        while self.accumulator >= Game::TIMESTEP {
+            self.step(Game::TIMESTEP.as_secs_f32());
+            self.accumulator -= Game::TIMESTEP;
         }
     }
 
-    // TODO: Make a better keyboard input handler (onKeyPressed, onKeyReleased, onKeyHold, etc...)
-    pub fn tick(&mut self) {
-        self.time_elapsed = SystemTime::now().duration_since(self.begin_time).unwrap();
-        self.camera.set_rotation(
-            self.camera.yaw + (self.mouse_delta.0 as f32) * 1e-3 * Game::MOUSE_SPEED,
-            self.camera.pitch - (self.mouse_delta.1 as f32) * 1e-3 * Game::MOUSE_SPEED,
+    // how far the simulation is between the previous and the next fixed step, from 0 to 1
+    pub fn interpolation_alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / Game::TIMESTEP.as_secs_f32()
+    }
+
+    // camera position to render, smoothed between the last two fixed steps
+    pub fn render_camera_pos(&self) -> Vector3<f32> {
+        self.prev_camera_pos
+            .lerp(&self.camera.pos, self.interpolation_alpha())
+    }
+
+    // Console commands moving the camera and changing its view
+    pub fn commands() -> Commands<Game> {
+        let mut commands: Commands<Game> = Commands::default();
+        commands.add(
+            "tp",
+            "<x> <y> <z>",
+            "teleport the camera to a position, in voxels",
+            |game, args| {
+                let pos = vector![args.next("x")?, args.next("y")?, args.next("z")?];
+                args.end()?;
+                game.camera.pos = pos;
+                // rendering jumps straight there rather than sliding over from the old position
+                game.prev_camera_pos = pos;
+                game.player.velocity = Vector3::zeros();
+                Ok(format!("teleported to {} {} {}", pos.x, pos.y, pos.z))
+            },
         );
+        commands.add(
+            "fov",
+            "<degrees>",
+            "set the field of view of a perspective or fisheye projection",
+            |game, args| {
+                let degrees: f32 = args.next("degrees")?;
+                args.end()?;
+                // the same limits as the fov setting in config.rs
+                let max = match game.camera.projection {
+                    Projection::Perspective { .. } => 180.0,
+                    Projection::Fisheye { .. } => 360.0,
+                    _ => {
+                        return Err(CommandError::Failed(format!(
+                            "{:?} projections have no field of view",
+                            game.camera.projection
+                        )))
+                    }
+                };
+                if !(degrees > 0.0 && degrees < max) {
+                    return Err(CommandError::Usage(format!(
+                        "the field of view must be between 0 and {} degrees",
+                        max
+                    )));
+                }
+                if let Projection::Perspective { fov } | Projection::Fisheye { fov } =
+                    &mut game.camera.projection
+                {
+                    *fov = degrees;
+                }
+                Ok(String::new())
+            },
+        );
+        commands
+    }
+
+    // Starts replaying *path* in place of user input
+    pub fn play_path(&mut self, path: CameraPath) {
+        self.playback = Some(Playback::new(path));
+    }
+
+    pub fn playback_finished(&self) -> bool {
+        self.playback.as_ref().is_some_and(Playback::finished)
+    }
 
-        if is_key_pressed(self.keyboard.front) {
-            self.camera.pos += self.walk_speed * self.camera.dir;
-        } else if is_key_pressed(self.keyboard.back) {
-            self.camera.pos -= self.walk_speed * self.camera.dir;
+    // Starts recording the camera, with a keyframe every *interval* seconds
+    pub fn start_recording(&mut self, interval: f32) {
+        self.recorder = Some(PathRecorder::new(interval));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<CameraPath> {
+        let cam = &self.camera;
+        self.recorder
+            .take()
+            .map(|rec| rec.finish(cam.pos, cam.yaw, cam.pitch))
+    }
+
+    fn step(&mut self, dt: f32) {
+        self.prev_camera_pos = self.camera.pos;
+        self.input.update();
+
+        if let Some(key) = self.playback.as_mut().and_then(|p| p.advance(dt)) {
+            self.camera.pos = key.pos;
+            self.camera.set_rotation(key.yaw, key.pitch);
+            return;
+        }
+        if let Some(recorder) = &mut self.recorder {
+            let cam = &self.camera;
+            recorder.record(dt, cam.pos, cam.yaw, cam.pitch);
         }
 
-        if is_key_pressed(self.keyboard.right) {
-            self.camera.pos += self.walk_speed * self.camera.right * Game::STRAFE_FACTOR;
-        } else if is_key_pressed(self.keyboard.left) {
-            self.camera.pos -= self.walk_speed * self.camera.right * Game::STRAFE_FACTOR;
+        // toggle between flying and walking once per press
+        if self.input.just_pressed(Action::ToggleFly) {
+            self.player.toggle_mode();
         }
 
-        if is_key_pressed(self.keyboard.walk) {
+        // flying follows the view direction, walking only its horizontal part
+        let forward = match self.player.mode {
+            MovementMode::Fly => self.camera.dir,
+            MovementMode::Walk => self.camera.forward,
+        };
+        let mut wish_dir = forward * self.input.axis(Action::Forward, Action::Back)
+            + self.camera.right
+                * self.input.axis(Action::Right, Action::Left)
+                * Game::STRAFE_FACTOR;
+        if self.player.mode == MovementMode::Fly {
+            wish_dir += vector![0.0, 1.0, 0.0] * self.input.axis(Action::Up, Action::Down);
+        }
+
+        // moving diagonally is no faster than moving straight
+        if wish_dir.norm() > 1.0 {
+            wish_dir = wish_dir.normalize();
+        }
+
+        if self.input.held(Action::Slow) {
             self.walk_speed = 0.5;
         } else {
             self.walk_speed = 1.0;
         }
 
+        match self.player.mode {
+            MovementMode::Fly => {
+                self.player
+                    .fly(&mut self.camera.pos, wish_dir * self.walk_speed, dt);
+            }
+
+            MovementMode::Walk => {
+                // letting go of jump on the way up cuts the jump short
+                if self.input.just_released(Action::Jump) && self.player.velocity.y > 0.0 {
+                    self.player.velocity.y *= 0.5;
+                }
+                self.player.walk(
+                    &self.world,
+                    &mut self.camera.pos,
+                    wish_dir * self.walk_speed,
+                    self.input.held(Action::Jump),
+                    dt,
+                );
+            }
+        }
+    }
+
+    // Applies one input, recording it first if a recording is running. All the callbacks below
+    // go through here, and so does replaying a recording.
+    pub fn handle_input(&mut self, event: InputEvent) {
+        if let Some(recording) = &mut self.input_recording {
+            if replay::is_recordable(&event) {
+                recording.push(self.time_elapsed, Entry::Input(event));
+            }
+        }
+
+        match event {
+            InputEvent::Button(Binding::Key(key), state) => self.input.key_event(key, state),
+            InputEvent::Button(Binding::Mouse(button), state) => {
+                self.input.mouse_button_event(button, state)
+            }
+            InputEvent::Button(Binding::Axis { .. }, _) => (),
+            // several motion events can arrive between two frames, so they add up until the next
+            // update
+            InputEvent::MouseMotion(dx, dy) => {
+                self.mouse_delta.0 += dx;
+                self.mouse_delta.1 += dy;
+            }
+            InputEvent::Axis(axis, value) => self.input.axis_event(axis, value),
+        }
     }
 
     // callback for mouse input event
     pub fn mouse_input(&mut self, delta: (f32, f32)) {
-        self.mouse_delta = delta;
+        self.handle_input(InputEvent::MouseMotion(delta.0, delta.1));
     }
 
     // callback for keyboard input event
     pub fn keyboard_input(&mut self, input: glutin::event::KeyboardInput) {
-        let key = match input.virtual_keycode {
-            Some(key) => key,
-            None => return,
-        };
-        update_key_state(&mut self.keyboard, key, input.state);
+        if let Some(key) = input.virtual_keycode {
+            self.handle_input(InputEvent::Button(Binding::Key(key), input.state));
+        }
+    }
+
+    // callback for mouse button event
+    pub fn mouse_button_input(
+        &mut self,
+        button: glutin::event::MouseButton,
+        state: glutin::event::ElementState,
+    ) {
+        self.handle_input(InputEvent::Button(Binding::Mouse(button), state));
+    }
+
+    // callback for analog axis event (gamepad sticks and triggers)
+    pub fn axis_input(&mut self, axis: u32, value: f32) {
+        self.handle_input(InputEvent::Axis(axis, value));
     }
 }
 
@@ -109,6 +334,8 @@ pub struct Camera {
     pub dir: na::Vector3<f32>,
     pub forward: na::Vector3<f32>,
     pub right: na::Vector3<f32>,
+
+    pub projection: Projection,
 }
 
 impl Camera {
@@ -119,54 +346,184 @@ impl Camera {
         self.dir = self.pitch.cos() * self.forward + self.pitch.sin() * vector![0.0, 1.0, 0.0];
         self.right = vector![self.yaw.cos(), 0.0, -self.yaw.sin()];
     }
-}
 
-pub struct KeyboardState {
-    pub front: glutin::event::ElementState,
-    pub back: glutin::event::ElementState,
-    pub left: glutin::event::ElementState,
-    pub right: glutin::event::ElementState,
-    pub walk: glutin::event::ElementState,
+    // rotation from camera space (looking down +z) to world space
+    pub fn rotation(&self) -> Rotation3<f32> {
+        Rotation3::face_towards(&self.dir, &vector![0.0, 1.0, 0.0])
+    }
+
+    // World space ray through the screen position *pos* (from -1 to 1 on both axes, y up), the
+    // same one the shader marches for that pixel
+    #[allow(dead_code)]
+    pub fn ray(
+        &self,
+        pos: Vector2<f32>,
+        aspect_ratio: f32,
+    ) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let (origin, dir) = self.projection.ray(pos, aspect_ratio)?;
+        let rot = self.rotation();
+        Some((self.pos + rot * origin, rot * dir))
+    }
 }
 
-impl Default for KeyboardState {
-    fn default() -> KeyboardState {
-        KeyboardState {
-            front: glutin::event::ElementState::Released,
-            back: glutin::event::ElementState::Released,
-            left: glutin::event::ElementState::Released,
-            right: glutin::event::ElementState::Released,
-            walk: glutin::event::ElementState::Released,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{DenseGrid, Voxel};
+    use glutin::event::{ElementState, VirtualKeyCode};
+
+    fn empty_game() -> Game {
+        Game::with_world(Space::from_voxels(DenseGrid::fill(
+            (16, 16, 16),
+            Voxel::Empty,
+        )))
+    }
+
+    fn press(game: &mut Game, key: VirtualKeyCode) {
+        game.handle_input(InputEvent::Button(Binding::Key(key), ElementState::Pressed));
+    }
+
+    fn release(game: &mut Game, key: VirtualKeyCode) {
+        game.handle_input(InputEvent::Button(
+            Binding::Key(key),
+            ElementState::Released,
+        ));
+    }
+
+    // runs the game at a steady frame rate for the given number of frames
+    fn run(game: &mut Game, fps: u32, frames: u32) {
+        let frame_time = Duration::from_secs(1) / fps;
+        for _ in 0..frames {
+            game.update(frame_time);
         }
     }
-}
 
-pub fn is_key_pressed(state: glutin::event::ElementState) -> bool {
-    return state == glutin::event::ElementState::Pressed;
-}
+    #[test]
+    fn movement_does_not_depend_on_frame_rate() {
+        let mut slow = empty_game();
+        let mut fast = empty_game();
+        press(&mut slow, VirtualKeyCode::W);
+        press(&mut fast, VirtualKeyCode::W);
+
+        run(&mut slow, 30, 30);
+        run(&mut fast, 144, 144);
+
+        let (a, b) = (slow.render_camera_pos(), fast.render_camera_pos());
+        assert!((a - b).norm() < 0.05, "{:?} != {:?}", a, b);
+        assert!(a.z > 10.0);
+    }
+
+    #[test]
+    fn accelerates_to_top_speed() {
+        let mut game = empty_game();
+        press(&mut game, VirtualKeyCode::W);
+
+        run(&mut game, 60, 1);
+        let early = game.player.velocity.norm();
+        run(&mut game, 60, 60);
+
+        assert!(early > 0.0 && early < PlayerController::FLY_SPEED);
+        assert!((game.player.velocity.norm() - PlayerController::FLY_SPEED).abs() < 1e-3);
+    }
+
+    #[test]
+    fn frames_shorter_than_a_step_are_interpolated() {
+        let mut game = empty_game();
+        press(&mut game, VirtualKeyCode::W);
+        run(&mut game, 60, 10);
 
-pub fn update_key_state(
-    kb_state: &mut KeyboardState,
-    keycode: glutin::event::VirtualKeyCode,
-    key_state: glutin::event::ElementState,
-) {
-    match keycode {
-        // W
-        glutin::event::VirtualKeyCode::W => kb_state.front = key_state,
+        let before = game.camera.pos;
+        game.update(Game::TIMESTEP / 2);
 
-        // A
-        glutin::event::VirtualKeyCode::A => kb_state.left = key_state,
+        // no step was taken, but rendering moves halfway towards the latest step
+        assert_eq!(game.camera.pos, before);
+        assert!((game.interpolation_alpha() - 0.5).abs() < 1e-3);
+        let expected = game.prev_camera_pos.lerp(&before, 0.5);
+        assert!((game.render_camera_pos() - expected).norm() < 1e-5);
+    }
+
+    #[test]
+    fn long_hitches_are_clamped() {
+        let mut game = empty_game();
+        game.update(Duration::from_secs(10));
+        assert_eq!(game.time_elapsed, Game::MAX_FRAME_TIME);
+    }
 
-        // S
-        glutin::event::VirtualKeyCode::S => kb_state.back = key_state,
+    #[test]
+    fn moves_diagonally_and_vertically() {
+        let mut game = empty_game();
+        press(&mut game, VirtualKeyCode::W);
+        press(&mut game, VirtualKeyCode::D);
+        press(&mut game, VirtualKeyCode::E);
+        run(&mut game, 60, 30);
 
-        // D
-        glutin::event::VirtualKeyCode::D => kb_state.right = key_state,
+        let v = game.player.velocity;
+        assert!(v.x > 0.0 && v.y > 0.0 && v.z > 0.0, "{:?}", v);
+        assert!(v.norm() <= PlayerController::FLY_SPEED + 1e-3);
+    }
+
+    #[test]
+    fn taps_shorter_than_a_step_toggle_once() {
+        let mut game = empty_game();
+        press(&mut game, VirtualKeyCode::F);
+        release(&mut game, VirtualKeyCode::F);
+        run(&mut game, 60, 5);
+        assert_eq!(game.player.mode, MovementMode::Walk);
 
-        // LShift
-        glutin::event::VirtualKeyCode::LShift => kb_state.walk = key_state,
+        press(&mut game, VirtualKeyCode::F);
+        run(&mut game, 60, 5);
+        assert_eq!(game.player.mode, MovementMode::Fly);
+    }
 
-        // Other
-        _ => (),
+    #[test]
+    fn camera_rays_follow_the_view() {
+        let mut game = empty_game();
+        game.mouse_input((400.0, -300.0));
+        game.update(Duration::from_millis(0));
+
+        let (origin, dir) = game.camera.ray(vector![0.0, 0.0], 1.0).unwrap();
+        assert_eq!(origin, game.camera.pos);
+        assert!((dir - game.camera.dir).norm() < 1e-5);
+
+        // the right edge of the screen leans towards the camera's right
+        let (_, right) = game.camera.ray(vector![1.0, 0.0], 1.0).unwrap();
+        assert!(right.dot(&game.camera.right) > 0.0);
+    }
+
+    #[test]
+    fn recorded_paths_replay() {
+        let mut game = empty_game();
+        game.start_recording(0.25);
+        press(&mut game, VirtualKeyCode::W);
+        game.mouse_input((300.0, 0.0));
+        run(&mut game, 60, 60);
+        let end = (game.camera.pos, game.camera.yaw);
+        let path = game.stop_recording().unwrap();
+
+        let mut replay = empty_game();
+        replay.play_path(path);
+        run(&mut replay, 60, 61);
+
+        assert!(replay.playback_finished());
+        assert!((replay.camera.pos - end.0).norm() < 1e-4);
+        assert!((replay.camera.yaw - end.1).abs() < 1e-4);
+    }
+
+    #[test]
+    fn console_moves_the_camera() {
+        let commands = Game::commands();
+        let mut game = empty_game();
+
+        commands.run(&mut game, "tp 4 5.5 6").unwrap();
+        assert_eq!(game.render_camera_pos(), vector![4.0, 5.5, 6.0]);
+
+        commands.run(&mut game, "fov 70").unwrap();
+        assert_eq!(
+            game.camera.projection,
+            Projection::Perspective { fov: 70.0 }
+        );
+        assert!(commands.run(&mut game, "fov 200").is_err());
+        game.camera.projection = Projection::Orthographic { size: 32.0 };
+        assert!(commands.run(&mut game, "fov 70").is_err());
     }
 }
@@ -1,106 +1,404 @@
 use crate::game::Game;
 
-use glium::texture::UnsignedTexture3d;
+use glium::framebuffer::{MultiOutputFrameBuffer, SimpleFrameBuffer};
+use glium::program::ProgramCreationInput;
+use glium::texture::{
+    MipmapsOption, RawImage2d, Texture2d, UncompressedFloatFormat, UnsignedTexture3d,
+};
 
 #[allow(unused_imports)]
-use glium::{glutin, Surface};
-use na::vector;
-use std::fs;
-use std::path::Path;
+use glium::{glutin, uniforms::MagnifySamplerFilter, DrawParameters, Surface};
+use std::path::{Path, PathBuf};
 
+use crate::console::{CommandError, Commands};
+use crate::debug_view::{self, DebugView};
+use crate::image::Image;
+use crate::post::PostPipeline;
+use crate::preprocess::{self, ShaderError, Source};
+use crate::projection::Projection;
+use crate::resolution::{self, Tile};
+use crate::stats::GpuTimer;
 use crate::uniforms::AsGPUResource;
+use crate::world::{Voxel, WORLD_DIM};
 
+// default shader directory, see `Config::shader_dir`
 pub const SHADER_PATH_NAME: &str = "src/shaders";
 
 pub struct DenseCartesianUniforms {
     pub sdf: UnsignedTexture3d,
     pub voxels: UnsignedTexture3d,
+
+    // `Space::generation` of the world the textures were made from
+    pub generation: u64,
+}
+
+// How the marched voxels are lit
+#[derive(Clone, Copy, Debug)]
+pub struct Lighting {
+    // brightness of a voxel one unit away; it falls off with the square of the distance
+    pub intensity: f32,
+
+    // saturation the lit colors are pushed to, from 0 to 1
+    pub saturation: f32,
+}
+
+impl Default for Lighting {
+    fn default() -> Lighting {
+        Lighting {
+            intensity: 100.0,
+            saturation: 0.99,
+        }
+    }
 }
 
 pub struct DenseCartesianRenderer {
     pub uniforms: DenseCartesianUniforms,
+    pub post: PostPipeline,
+
+    // fraction of the output resolution to march at along each axis, upscaled afterwards
+    pub render_scale: f32,
+
+    pub lighting: Lighting,
+
+    // what the marcher shows, the shaded scene or one of the debug views
+    pub debug_view: DebugView,
+
+    // times the draw calls of each frame on the GPU
+    pub gpu_timer: GpuTimer,
 }
 
 impl DenseCartesianRenderer {
+    // Makes the world's textures again if it changed since they were made
     fn update_uniforms(&mut self, facade: &dyn glium::backend::Facade, game: &Game) {
+        if self.uniforms.generation == game.world.generation {
+            return;
+        }
         self.uniforms.sdf = game.world.sdf.as_gpu_resource(facade);
         self.uniforms.voxels = game.world.voxels.as_gpu_resource(facade);
+        self.uniforms.generation = game.world.generation;
+    }
+
+    // Console commands changing how frames are drawn
+    pub fn commands() -> Commands<DenseCartesianRenderer> {
+        let mut commands: Commands<DenseCartesianRenderer> = Commands::default();
+        commands.add(
+            "view",
+            "<off|steps|sdf|depth|normals|material>",
+            "show the shaded scene or one of the marcher's debug views",
+            |renderer, args| {
+                renderer.debug_view = args.next_with("view", DebugView::from_name)?;
+                args.end()?;
+                Ok(String::new())
+            },
+        );
+        commands.add(
+            "scale",
+            "<fraction>",
+            "set the render scale, the fraction of the resolution to march at",
+            |renderer, args| {
+                let scale: f32 = args.next("fraction")?;
+                args.end()?;
+                if !(scale > 0.0 && scale <= 1.0) {
+                    return Err(CommandError::Usage(
+                        "the render scale must be above 0 and at most 1".to_string(),
+                    ));
+                }
+                renderer.render_scale = scale;
+                Ok(String::new())
+            },
+        );
+        commands
+    }
+
+    // Renders a frame offscreen at the given size and reads it back, without touching the window
+    pub fn capture(
+        &mut self,
+        display: &glium::Display,
+        vertex_buffer: &glium::VertexBuffer<attrib::Vertex>,
+        index_buffer: &glium::IndexBuffer<u16>,
+        program: &glium::Program,
+        game: &Game,
+        size: (u32, u32),
+    ) -> Image {
+        let texture = empty_texture(display, size);
+        let mut target = SimpleFrameBuffer::new(display, &texture).unwrap();
+        self.draw(
+            display,
+            &mut target,
+            vertex_buffer,
+            index_buffer,
+            program,
+            game,
+        );
+        read_texture(&texture)
     }
 
-    pub fn draw(
+    // Renders an image *factor* times the given size along each axis, as that many tiles of the
+    // given size side by side, at full render scale. Post passes that look at neighbouring
+    // pixels can show seams between the tiles.
+    #[allow(clippy::too_many_arguments)]
+    pub fn capture_tiled(
         &mut self,
         display: &glium::Display,
         vertex_buffer: &glium::VertexBuffer<attrib::Vertex>,
         index_buffer: &glium::IndexBuffer<u16>,
         program: &glium::Program,
         game: &Game,
+        (width, height): (u32, u32),
+        factor: u32,
+    ) -> Image {
+        let render_scale = std::mem::replace(&mut self.render_scale, 1.0);
+        let texture = empty_texture(display, (width, height));
+        let mut image = Image::blank((width * factor) as usize, (height * factor) as usize);
+        for tile in Tile::grid(factor) {
+            let mut target = SimpleFrameBuffer::new(display, &texture).unwrap();
+            self.draw_tile(
+                display,
+                &mut target,
+                vertex_buffer,
+                index_buffer,
+                program,
+                game,
+                tile,
+            );
+            let x = (tile.col * width) as usize;
+            let y = (tile.row * height) as usize;
+            image.paste(&read_texture(&texture), x, y);
+        }
+        self.render_scale = render_scale;
+        image
+    }
+
+    // Renders a frame into *target*, which anything else shown on screen can then be drawn over
+    pub fn draw<S: Surface>(
+        &mut self,
+        facade: &dyn glium::backend::Facade,
+        target: &mut S,
+        vertex_buffer: &glium::VertexBuffer<attrib::Vertex>,
+        index_buffer: &glium::IndexBuffer<u16>,
+        program: &glium::Program,
+        game: &Game,
     ) {
-        let ref cam = game.camera;
+        self.draw_tile(
+            facade,
+            target,
+            vertex_buffer,
+            index_buffer,
+            program,
+            game,
+            Tile::FULL,
+        );
+    }
 
-        // up vector in world coordinates
-        let up = vector![0.0, 1.0, 0.0];
+    // Renders the part of a frame covered by *tile* into *target*
+    #[allow(clippy::too_many_arguments)]
+    fn draw_tile<S: Surface>(
+        &mut self,
+        facade: &dyn glium::backend::Facade,
+        target: &mut S,
+        vertex_buffer: &glium::VertexBuffer<attrib::Vertex>,
+        index_buffer: &glium::IndexBuffer<u16>,
+        program: &glium::Program,
+        game: &Game,
+        tile: Tile,
+    ) {
+        let ref cam = game.camera;
 
         // camera rotation matrix
-        let cam_rot = na::Rotation3::face_towards(&cam.dir, &up);
+        let cam_rot = cam.rotation();
 
         let cam_pos = {
-            let cv = cam.pos.data.0;
+            let cv = game.render_camera_pos().data.0;
             (cv[0][0], cv[0][1], cv[0][2])
         };
 
-        let fov_h: f32 = 45.0;
-        let near = 1.0 / (fov_h / 2.0).tan();
-        let aspect_ratio: f32 = {
-            let (w, h) = display.get_framebuffer_dimensions();
-            (w as f32) / (h as f32)
-        };
+        let size = target.get_dimensions();
+        let aspect_ratio = (size.0 as f32) / (size.1 as f32);
 
-        self.update_uniforms(display, game);
+        self.update_uniforms(facade, game);
+        let gpu_timer = self.gpu_timer.query(facade);
 
         let ref uniforms = uniform! {
             cam_pos: cam_pos,
             cam_rot: cam_rot.matrix().data.0,
-            near: near,
+            projection: cam.projection.id(),
+            proj_param: cam.projection.param(),
             time: game.time_elapsed.as_secs_f32(),
             aspect_ratio: aspect_ratio,
+            view_offset: tile.offset,
+            view_scale: tile.scale,
+            debug_view: self.debug_view.id(),
+            light_intensity: self.lighting.intensity,
+            saturation: self.lighting.saturation,
             sdf_data: &self.uniforms.sdf,
             voxels: &self.uniforms.voxels,
         };
 
-        // drawing a frame
-        let mut target = display.draw();
-        target.clear_color(0.0, 0.0, 0.0, 1.0);
-        target
-            .draw(
-                vertex_buffer,
-                index_buffer,
-                program,
-                uniforms,
-                &Default::default(),
-            )
+        // march into the offscreen targets...
+        let march_size = resolution::scaled_size(size, self.render_scale);
+        let targets = self.post.targets(facade, size, march_size);
+        let mut gbuffer = MultiOutputFrameBuffer::new(
+            facade,
+            [
+                ("f_color", &targets.color),
+                ("f_normal_depth", &targets.normal_depth),
+                ("f_material", &targets.material),
+            ],
+        )
+        .unwrap();
+        gbuffer.clear_color(0.0, 0.0, 0.0, 1.0);
+        let params = DrawParameters {
+            time_elapsed_query: gpu_timer,
+            ..Default::default()
+        };
+        gbuffer
+            .draw(vertex_buffer, index_buffer, program, uniforms, &params)
             .unwrap();
-        target.finish().unwrap();
+
+        // ...then post process them into the target, leaving the colors of the debug views as they
+        // are
+        self.post.skip_passes = self.debug_view != DebugView::Off;
+        self.post.run(
+            facade,
+            target,
+            vertex_buffer,
+            index_buffer,
+            game.time_elapsed.as_secs_f32(),
+            gpu_timer,
+        );
     }
 }
 
-/// Loads a shader by file path
-pub fn load_shader(
+// Reads back what has been drawn into *frame* so far, such as the window before anything is
+// drawn over the game
+pub fn read_frame<S: Surface>(facade: &dyn glium::backend::Facade, frame: &S) -> Image {
+    let texture = empty_texture(facade, frame.get_dimensions());
+    let target = SimpleFrameBuffer::new(facade, &texture).unwrap();
+    frame.fill(&target, MagnifySamplerFilter::Nearest);
+    read_texture(&texture)
+}
+
+fn empty_texture(facade: &dyn glium::backend::Facade, (width, height): (u32, u32)) -> Texture2d {
+    Texture2d::empty_with_format(
+        facade,
+        UncompressedFloatFormat::U8U8U8U8,
+        MipmapsOption::NoMipmap,
+        width,
+        height,
+    )
+    .unwrap()
+}
+
+fn read_texture(texture: &Texture2d) -> Image {
+    // OpenGL rows go bottom to top
+    let raw: RawImage2d<u8> = texture.read();
+    let pixels = raw
+        .data
+        .chunks(4 * raw.width as usize)
+        .rev()
+        .flat_map(|row| row.chunks(4).map(|px| (px[0], px[1], px[2])))
+        .collect();
+    Image {
+        width: raw.width as usize,
+        height: raw.height as usize,
+        pixels,
+    }
+}
+
+/// A compiled program with the files it was built from
+pub struct ShaderProgram {
+    pub program: glium::Program,
+
+    // canonical paths of the shader files and everything they include
+    pub files: Vec<PathBuf>,
+}
+
+impl ShaderProgram {
+    pub fn depends_on(&self, path: &Path) -> bool {
+        preprocess::depends_on(&self.files, path)
+    }
+}
+
+/// Defines every shader is compiled with, so constants shared with the Rust side live in one place
+pub fn shader_defines() -> Vec<(String, String)> {
+    let mut defines = vec![
+        ("WORLD_DIM_X".to_string(), WORLD_DIM.0.to_string()),
+        ("WORLD_DIM_Y".to_string(), WORLD_DIM.1.to_string()),
+        ("WORLD_DIM_Z".to_string(), WORLD_DIM.2.to_string()),
+    ];
+    for voxel in Voxel::MATERIALS {
+        let name = format!("VOX_{:?}", voxel).to_uppercase();
+        defines.push((name, voxel.id().to_string()));
+    }
+    let projections = [
+        ("PROJ_PERSPECTIVE", Projection::Perspective { fov: 0.0 }),
+        ("PROJ_ORTHOGRAPHIC", Projection::Orthographic { size: 0.0 }),
+        ("PROJ_EQUIRECTANGULAR", Projection::Equirectangular),
+        ("PROJ_FISHEYE", Projection::Fisheye { fov: 0.0 }),
+    ];
+    for (name, projection) in projections {
+        defines.push((name.to_string(), projection.id().to_string()));
+    }
+    for view in DebugView::ALL {
+        let name = format!("DEBUG_{}", view.name()).to_uppercase();
+        defines.push((name, view.id().to_string()));
+    }
+    let ranges = [
+        ("DEBUG_STEPS_RANGE", debug_view::STEPS_RANGE),
+        ("DEBUG_SDF_RANGE", debug_view::SDF_RANGE),
+        ("DEBUG_DEPTH_RANGE", debug_view::DEPTH_RANGE),
+    ];
+    for (name, range) in ranges {
+        defines.push((name.to_string(), format!("{:.1}", range)));
+    }
+    defines
+}
+
+/// Preprocesses and compiles a program out of a vertex and a fragment shader. *outputs_srgb*
+/// tells the driver the shader already gamma corrects its output.
+pub fn load_program(
     display: &glium::Display,
-    name: &str,
-) -> Result<glium::Program, glium::ProgramCreationError> {
-    let shader_path = Path::new(SHADER_PATH_NAME);
+    vert_path: &Path,
+    frag_path: &Path,
+    outputs_srgb: bool,
+) -> Result<ShaderProgram, ShaderError> {
+    let defines = shader_defines();
+    let vert = Source::load(vert_path, &defines)?;
+    let frag = Source::load(frag_path, &defines)?;
 
-    let vert_fname = format!("{}.vert", name);
-    let vert_path = shader_path.join(vert_fname);
-    let vert_src = fs::read_to_string(&vert_path)
-        .expect(&format!("Unable to read vertex shader: {:?}", &vert_path));
+    let program = glium::Program::new(
+        display,
+        ProgramCreationInput::SourceCode {
+            vertex_shader: &vert.text,
+            tessellation_control_shader: None,
+            tessellation_evaluation_shader: None,
+            geometry_shader: None,
+            fragment_shader: &frag.text,
+            transform_feedback_varyings: None,
+            outputs_srgb,
+            uses_point_size: false,
+        },
+    )
+    .map_err(|err| preprocess::compile_error(err, &vert, &frag))?;
 
-    let frag_fname = format!("{}.frag", name);
-    let frag_path = shader_path.join(frag_fname);
-    let frag_src = fs::read_to_string(&frag_path)
-        .expect(&format!("Unable to read fragment shader: {:?}", &frag_path));
+    let mut files = vert.files;
+    for file in frag.files {
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+    Ok(ShaderProgram { program, files })
+}
 
-    glium::Program::from_source(display, &vert_src, &frag_src, None)
+/// Loads a shader by name from the shader directory
+pub fn load_shader(
+    display: &glium::Display,
+    shader_path: &Path,
+    name: &str,
+) -> Result<ShaderProgram, ShaderError> {
+    let vert_path = shader_path.join(format!("{}.vert", name));
+    let frag_path = shader_path.join(format!("{}.frag", name));
+    load_program(display, &vert_path, &frag_path, false)
 }
 
 pub mod attrib {
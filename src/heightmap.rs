@@ -0,0 +1,217 @@
+use crate::image::{luminance, Image};
+use crate::types::Dimension3;
+use crate::world::{DenseGrid, Space, Voxel};
+use crate::world_loader::{Level, LevelLoader, LoadError};
+
+use std::path::{Path, PathBuf};
+
+// Extrudes a grayscale image into columns of voxels. The image spans the whole x/z extent of the
+// world (x to the right, z downwards), and brighter pixels make taller columns.
+pub struct HeightmapLoader {
+    // height in voxels of a white pixel, on top of `base_height`
+    pub vertical_scale: f32,
+
+    // height of the columns under a black pixel
+    pub base_height: usize,
+
+    // material for each band of height, as (upper bound as a fraction of the column height
+    // range, material); voxels above the last bound use the last material
+    pub bands: Vec<(f32, Voxel)>,
+
+    // suffix appended to the heightmap's file stem to find its optional color map, which
+    // overrides the material of the top voxel of each column
+    pub color_map_suffix: String,
+}
+
+impl Default for HeightmapLoader {
+    fn default() -> HeightmapLoader {
+        HeightmapLoader {
+            vertical_scale: 24.0,
+            base_height: 1,
+            bands: vec![
+                (0.2, Voxel::Sand),
+                (0.55, Voxel::Grass),
+                (0.8, Voxel::Stone),
+                (1.0, Voxel::Snow),
+            ],
+            color_map_suffix: "_color".to_string(),
+        }
+    }
+}
+
+impl HeightmapLoader {
+    fn band_material(&self, fraction: f32) -> Voxel {
+        self.bands
+            .iter()
+            .find(|(bound, _)| fraction <= *bound)
+            .or_else(|| self.bands.last())
+            .map(|(_, vox)| *vox)
+            .unwrap_or(Voxel::Stone)
+    }
+
+    // path of the color map belonging to a heightmap, if one exists next to it
+    fn color_map_path(&self, path: &Path) -> Option<PathBuf> {
+        self.color_map_candidates(path)
+            .into_iter()
+            .find(|p| p.exists())
+    }
+
+    // paths a heightmap's color map could have, in order of preference
+    fn color_map_candidates(&self, path: &Path) -> Vec<PathBuf> {
+        let (stem, ext) = match (path.file_stem(), path.extension()) {
+            (Some(stem), Some(ext)) => (stem.to_string_lossy(), ext.to_string_lossy()),
+            _ => return Vec::new(),
+        };
+        [&ext, "png", "ppm"]
+            .iter()
+            .map(|e| path.with_file_name(format!("{}{}.{}", stem, self.color_map_suffix, e)))
+            .collect()
+    }
+
+    pub fn extrude(
+        &self,
+        heights: &Image,
+        colors: Option<&Image>,
+        shape: Dimension3,
+    ) -> DenseGrid<Voxel> {
+        let mut voxels = DenseGrid::fill(shape, Voxel::Empty);
+        let max_height = self.base_height as f32 + self.vertical_scale;
+
+        for z in 0..shape.2 {
+            for x in 0..shape.0 {
+                let (u, v) = (
+                    (x as f32 + 0.5) / shape.0 as f32,
+                    (z as f32 + 0.5) / shape.2 as f32,
+                );
+                let value = luminance(heights.sample(u, v));
+                let height =
+                    (self.base_height as f32 + value * self.vertical_scale).round() as usize;
+                let height = height.min(shape.1);
+
+                for y in 0..height {
+                    voxels[(x, y, z)] = self.band_material((y + 1) as f32 / max_height);
+                }
+                if let (Some(colors), true) = (colors, height > 0) {
+                    voxels[(x, height - 1, z)] = Voxel::nearest_to_color(colors.sample(u, v));
+                }
+            }
+        }
+
+        voxels
+    }
+}
+
+impl LevelLoader for HeightmapLoader {
+    fn extensions(&self) -> &[&str] {
+        &["png", "pgm"]
+    }
+
+    fn load(&self, path: &Path, shape: Dimension3) -> Result<Level, LoadError> {
+        let heights = Image::load(path)?;
+        let colors = match self.color_map_path(path) {
+            Some(color_path) => Some(Image::load(&color_path)?),
+            None => None,
+        };
+        if heights.width == 0 || heights.height == 0 {
+            return Err(LoadError::Parse("heightmap has no pixels".to_string()));
+        }
+
+        let voxels = self.extrude(&heights, colors.as_ref(), shape);
+        Ok(Space::from_voxels(voxels).into())
+    }
+
+    fn dependencies(&self, path: &Path) -> Vec<PathBuf> {
+        let mut files = vec![path.to_path_buf()];
+        files.extend(self.color_map_candidates(path));
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    use std::fs;
+
+    // columns 1 voxel tall for black and 5 for white, sand in the lower half and stone above
+    fn loader() -> HeightmapLoader {
+        HeightmapLoader {
+            vertical_scale: 4.0,
+            base_height: 1,
+            bands: vec![(0.5, Voxel::Sand), (1.0, Voxel::Stone)],
+            ..Default::default()
+        }
+    }
+
+    fn column(voxels: &DenseGrid<Voxel>, x: usize) -> Vec<Voxel> {
+        (0..voxels.shape().1).map(|y| voxels[(x, y, 0)]).collect()
+    }
+
+    #[test]
+    fn extrudes_columns_in_bands() {
+        use Voxel::{Empty, Sand, Stone};
+
+        let heights = Image::from_pnm(b"P2 2 1 255 0 255").unwrap();
+        let voxels = loader().extrude(&heights, None, (2, 6, 1));
+        assert_eq!(
+            column(&voxels, 0),
+            [Sand, Empty, Empty, Empty, Empty, Empty]
+        );
+        assert_eq!(column(&voxels, 1), [Sand, Sand, Stone, Stone, Stone, Empty]);
+
+        // columns are cut off at the top of the world, and the image is stretched across it
+        let voxels = loader().extrude(&heights, None, (4, 3, 1));
+        assert_eq!(column(&voxels, 1), [Sand, Empty, Empty]);
+        assert_eq!(column(&voxels, 2), [Sand, Sand, Stone]);
+
+        let bands = loader();
+        assert_eq!(bands.band_material(0.0), Sand);
+        assert_eq!(bands.band_material(0.5), Sand);
+        assert_eq!(bands.band_material(1.5), Stone);
+        let none = HeightmapLoader {
+            bands: Vec::new(),
+            ..loader()
+        };
+        assert_eq!(none.band_material(0.5), Stone);
+    }
+
+    #[test]
+    fn colors_the_tops_of_columns() {
+        use Voxel::{Blue, Red, Sand, Stone};
+
+        let heights = Image::from_pnm(b"P2 2 1 255 0 255").unwrap();
+        let colors = Image::from_pnm(b"P3 2 1 255 250 0 0 0 0 250").unwrap();
+        let voxels = loader().extrude(&heights, Some(&colors), (2, 5, 1));
+        assert_eq!(column(&voxels, 0)[0], Red);
+        assert_eq!(column(&voxels, 1), [Sand, Sand, Stone, Stone, Blue]);
+    }
+
+    #[test]
+    fn loads_a_color_map_next_to_the_heightmap() {
+        let dir = temp_dir("heightmap");
+        let path = dir.join("hills.pgm");
+        fs::write(&path, "P2 2 1 255 0 255").unwrap();
+
+        let deps = loader().dependencies(&path);
+        assert_eq!(deps[0], path);
+        assert!(deps.contains(&dir.join("hills_color.pgm")));
+        assert!(deps.contains(&dir.join("hills_color.png")));
+        assert!(deps.contains(&dir.join("hills_color.ppm")));
+
+        let level = loader().load(&path, (2, 5, 1)).unwrap().space;
+        assert_eq!(level.voxels[(1, 4, 0)], Voxel::Stone);
+
+        fs::write(dir.join("hills_color.ppm"), "P3 1 1 255 0 250 0").unwrap();
+        let level = loader().load(&path, (2, 5, 1)).unwrap().space;
+        assert_eq!(level.voxels[(0, 0, 0)], Voxel::Green);
+        assert_eq!(level.voxels[(1, 4, 0)], Voxel::Green);
+        assert_eq!(level.voxels[(1, 3, 0)], Voxel::Stone);
+
+        // an empty image is refused, whichever of the two it is
+        fs::write(dir.join("hills_color.ppm"), "P3 0 0 255").unwrap();
+        assert!(loader().load(&path, (2, 5, 1)).is_err());
+        fs::write(&path, "P2 0 0 255").unwrap();
+        assert!(loader().load(&path, (2, 5, 1)).is_err());
+    }
+}
@@ -0,0 +1,460 @@
+use crate::console::{Args, CommandError, Commands};
+use crate::types::Idx3;
+use crate::world::{DenseGrid, Region, Space, Voxel};
+
+use std::collections::{HashMap, VecDeque};
+
+/********/
+/* Edit */
+/********/
+
+// One voxel an edit changed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Change {
+    pub idx: Idx3,
+    pub before: Voxel,
+    pub after: Voxel,
+}
+
+// What a change to the world did, kept as only the voxels that changed so that a long history of
+// small edits to a large world stays small
+#[derive(Clone, PartialEq, Debug)]
+pub struct Edit {
+    // the smallest region holding every change
+    pub region: Region,
+    pub changes: Vec<Change>,
+}
+
+impl Edit {
+    // The edit making *changes*, if there are any
+    pub fn new(changes: Vec<Change>) -> Option<Edit> {
+        let region = Region::around(changes.iter().map(|c| c.idx))?;
+        Some(Edit { region, changes })
+    }
+
+    // Sets the changed voxels to what they were after the edit, or before it
+    pub fn apply(&self, voxels: &mut DenseGrid<Voxel>, undo: bool) {
+        for change in &self.changes {
+            voxels[change.idx] = if undo { change.before } else { change.after };
+        }
+    }
+
+    // The one edit doing this one and then *later*. Voxels the two change back to what they were
+    // are left out, so merging can leave nothing.
+    fn merge(self, later: Edit) -> Option<Edit> {
+        let mut changes = self.changes;
+        let mut positions: HashMap<Idx3, usize> = changes
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.idx, i))
+            .collect();
+        for change in later.changes {
+            match positions.get(&change.idx) {
+                Some(&i) => changes[i].after = change.after,
+                None => {
+                    positions.insert(change.idx, changes.len());
+                    changes.push(change);
+                }
+            }
+        }
+        changes.retain(|c| c.before != c.after);
+        Edit::new(changes)
+    }
+}
+
+/***********/
+/* History */
+/***********/
+
+// Whether edits are being merged into one, see `History::begin_stroke`
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Stroke {
+    Closed,
+
+    // open, but nothing has been edited in it yet
+    Started,
+
+    // open, with its edits merged into the newest one
+    Painting,
+}
+
+// Edits made to a space that can be undone, newest last, and edits undone that can be redone
+pub struct History {
+    // most edits kept to undo, and most changes kept in all of them together; the oldest edits
+    // are forgotten past either, though the newest is always kept
+    pub limit: usize,
+    pub change_limit: usize,
+
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    stroke: Stroke,
+
+    // changes held by the edits to undo and redo
+    stored: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(History::DEFAULT_LIMIT, History::DEFAULT_CHANGE_LIMIT)
+    }
+}
+
+impl History {
+    pub const DEFAULT_LIMIT: usize = 256;
+
+    // a change takes 32 bytes, so this keeps the history to about 64 MB, enough for a few
+    // replaces over the whole world
+    pub const DEFAULT_CHANGE_LIMIT: usize = 1 << 21;
+
+    pub fn new(limit: usize, change_limit: usize) -> History {
+        History {
+            limit,
+            change_limit,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            stroke: Stroke::Closed,
+            stored: 0,
+        }
+    }
+
+    // number of edits that can be undone and redone
+    pub fn len(&self) -> (usize, usize) {
+        (self.undo.len(), self.redo.len())
+    }
+
+    // Adds an edit that has just been made, which can't be followed by redoing anything undone
+    // before it
+    pub fn record(&mut self, edit: Edit) {
+        for undone in self.redo.drain(..) {
+            self.stored -= undone.changes.len();
+        }
+        if self.stroke == Stroke::Painting {
+            let last = self.undo.pop_back();
+            self.stored -= last.as_ref().map_or(0, |last| last.changes.len());
+            match last.and_then(|last| last.merge(edit)) {
+                Some(merged) => {
+                    self.stored += merged.changes.len();
+                    self.undo.push_back(merged);
+                }
+                // the stroke undid itself, so its next edit starts a new one rather than being
+                // merged into the edit made before the stroke
+                None => self.stroke = Stroke::Started,
+            }
+        } else {
+            self.stored += edit.changes.len();
+            self.undo.push_back(edit);
+            if self.stroke == Stroke::Started {
+                self.stroke = Stroke::Painting;
+            }
+        }
+
+        while self.undo.len() > 1
+            && (self.undo.len() > self.limit || self.stored > self.change_limit)
+        {
+            if let Some(oldest) = self.undo.pop_front() {
+                self.stored -= oldest.changes.len();
+            }
+        }
+    }
+
+    // Until `end_stroke`, merges the edits made into one, so that something painted over many
+    // frames or commands is undone in one go
+    pub fn begin_stroke(&mut self) {
+        self.stroke = Stroke::Started;
+    }
+
+    pub fn end_stroke(&mut self) {
+        self.stroke = Stroke::Closed;
+    }
+
+    // Moves the newest edit over to be redone, giving it back to be undone. Ends any stroke, so
+    // that the next edit is not merged into one that was undone.
+    fn undo(&mut self) -> Option<&Edit> {
+        self.stroke = Stroke::Closed;
+        let edit = self.undo.pop_back()?;
+        self.redo.push(edit);
+        self.redo.last()
+    }
+
+    fn redo(&mut self) -> Option<&Edit> {
+        self.stroke = Stroke::Closed;
+        let edit = self.redo.pop()?;
+        self.undo.push_back(edit);
+        self.undo.back()
+    }
+}
+
+impl Space {
+    // Undoes the newest edit, giving back the region it changed
+    pub fn undo(&mut self) -> Option<Region> {
+        let edit = self.history.undo()?;
+        edit.apply(&mut self.voxels, true);
+        let region = edit.region;
+        self.changed(region);
+        Some(region)
+    }
+
+    // Redoes the edit undone last, giving back the region it changed
+    pub fn redo(&mut self) -> Option<Region> {
+        let edit = self.history.redo()?;
+        edit.apply(&mut self.voxels, false);
+        let region = edit.region;
+        self.changed(region);
+        Some(region)
+    }
+}
+
+/************/
+/* Commands */
+/************/
+
+// Console commands going back and forth through the edits to the space
+pub fn commands() -> Commands<Space> {
+    fn steps(
+        space: &mut Space,
+        args: &mut Args,
+        step: fn(&mut Space) -> Option<Region>,
+        verb: &str,
+    ) -> Result<String, CommandError> {
+        let steps: usize = args.optional("steps")?.unwrap_or(1);
+        args.end()?;
+        let done = (0..steps).take_while(|_| step(space).is_some()).count();
+        let (undo, redo) = space.history.len();
+        Ok(format!(
+            "{} {} edits, {} left to undo and {} to redo",
+            verb, done, undo, redo
+        ))
+    }
+
+    let mut commands: Commands<Space> = Commands::default();
+    commands.add(
+        "undo",
+        "[steps]",
+        "undo the last edits to the world",
+        |space, args| steps(space, args, Space::undo, "undid"),
+    );
+    commands.add(
+        "redo",
+        "[steps]",
+        "redo edits that were undone",
+        |space, args| steps(space, args, Space::redo, "redid"),
+    );
+    commands.add(
+        "stroke",
+        "<begin|end>",
+        "merge the edits between begin and end so that they are undone together",
+        |space, args| {
+            let begin = args.next_with("begin|end", |word| match word {
+                "begin" => Some(true),
+                "end" => Some(false),
+                _ => None,
+            })?;
+            args.end()?;
+            if begin {
+                space.history.begin_stroke();
+                Ok("stroke begun".to_string())
+            } else {
+                space.history.end_stroke();
+                Ok("stroke ended".to_string())
+            }
+        },
+    );
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brush::{Brush, Shape};
+    use crate::types::GPUFormat;
+    use na::vector;
+
+    fn space() -> Space {
+        let mut voxels = DenseGrid::fill((12, 12, 12), Voxel::Empty);
+        for (i, v) in voxels.iter_mut().enumerate() {
+            if i % 7 < 3 {
+                *v = Voxel::MATERIALS[i % Voxel::MATERIALS.len()];
+            }
+        }
+        Space::from_voxels(voxels)
+    }
+
+    // the voxels and distance field as the GPU gets them
+    fn bytes(space: &Space) -> (Box<[u8]>, Vec<u8>) {
+        (
+            (&space.voxels).gpu_format(),
+            (&space.sdf).gpu_format().to_vec(),
+        )
+    }
+
+    fn sphere(x: f32, radius: f32) -> Brush {
+        Brush::shape(Shape::Sphere {
+            center: vector![x, 6.0, 6.0],
+            radius,
+        })
+    }
+
+    #[test]
+    fn undo_and_redo_restore_identical_bytes() {
+        let mut space = space();
+        let mut states = vec![bytes(&space)];
+        space.paint(&sphere(3.0, 3.0), Voxel::Stone);
+        states.push(bytes(&space));
+        space.paint(&sphere(6.0, 4.0), Voxel::Empty);
+        states.push(bytes(&space));
+        space.paint(&Brush::Flood((0, 0, 0)), Voxel::Water);
+        states.push(bytes(&space));
+        assert_eq!(space.history.len(), (3, 0));
+
+        for state in states.iter().rev().skip(1) {
+            assert!(space.undo().is_some());
+            assert!(bytes(&space) == *state);
+        }
+        assert_eq!(space.undo(), None);
+        for state in states.iter().skip(1) {
+            assert!(space.redo().is_some());
+            assert!(bytes(&space) == *state);
+        }
+        assert_eq!(space.redo(), None);
+
+        // a new edit forgets what was undone
+        space.undo();
+        space.paint(&sphere(8.0, 1.0), Voxel::Wood);
+        assert_eq!(space.history.len(), (3, 0));
+    }
+
+    #[test]
+    fn every_change_is_a_new_generation() {
+        let other = space();
+        let mut space = space();
+        let mut seen = vec![other.generation, space.generation];
+        let mut changed = |space: &Space| {
+            let new = !seen.contains(&space.generation);
+            seen.push(space.generation);
+            new
+        };
+
+        space.paint(&sphere(3.0, 3.0), Voxel::Stone);
+        assert!(changed(&space));
+        // painting what is already there changes nothing
+        assert_eq!(space.paint(&sphere(3.0, 3.0), Voxel::Stone), None);
+        assert!(!changed(&space));
+        space.undo();
+        assert!(changed(&space));
+        space.redo();
+        assert!(changed(&space));
+        assert_eq!(space.redo(), None);
+        assert!(!changed(&space));
+    }
+
+    #[test]
+    fn stores_only_changes() {
+        let mut space = space();
+        let before = space.voxels[(6, 6, 6)];
+        let edit = space.paint(&sphere(6.0, 0.0), Voxel::Snow).unwrap();
+        assert_eq!(edit.region, Region::new((6, 6, 6), (6, 6, 6)));
+        assert_eq!(
+            edit.changes,
+            [Change {
+                idx: (6, 6, 6),
+                before,
+                after: Voxel::Snow
+            }]
+        );
+        assert!(space.paint(&sphere(6.0, 0.0), Voxel::Snow).is_none());
+        assert_eq!(space.history.len(), (1, 0));
+    }
+
+    #[test]
+    fn merges_strokes() {
+        let mut space = space();
+        let start = bytes(&space);
+        space.paint(&sphere(2.0, 1.0), Voxel::Dirt);
+        let first = bytes(&space);
+
+        space.history.begin_stroke();
+        for x in 3..9 {
+            space.paint(&sphere(x as f32, 1.5), Voxel::Sand);
+        }
+        space.history.end_stroke();
+        space.paint(&sphere(9.0, 1.0), Voxel::Grass);
+        assert_eq!(space.history.len(), (3, 0));
+        let third = bytes(&space);
+
+        // a stroke putting back what it changed leaves nothing to undo, and what it paints after
+        // that isn't merged into the edit before the stroke
+        let old = space.voxels[(10, 10, 10)];
+        let corner = Brush::shape(Shape::Box(Region::new((10, 10, 10), (10, 10, 10))));
+        space.history.begin_stroke();
+        space.paint(&corner, Voxel::Red);
+        space.paint(&corner, old);
+        assert_eq!(space.history.len(), (3, 0));
+        space.paint(&corner, Voxel::Snow);
+        space.paint(&sphere(1.0, 0.0), Voxel::Snow);
+        space.history.end_stroke();
+        assert_eq!(space.history.len(), (4, 0));
+
+        space.undo();
+        assert!(bytes(&space) == third);
+        space.undo();
+        space.undo();
+        assert!(bytes(&space) == first);
+        space.undo();
+        assert!(bytes(&space) == start);
+    }
+
+    #[test]
+    fn forgets_the_oldest_edits() {
+        let mut space = space();
+        space.history.limit = 2;
+        for x in 0..5 {
+            space.paint(&sphere(x as f32 * 2.0, 1.0), Voxel::Wood);
+        }
+        assert_eq!(space.history.len(), (2, 0));
+        assert_eq!(space.voxels[(4, 6, 6)], Voxel::Wood);
+        space.undo();
+        space.undo();
+        assert_eq!(space.undo(), None);
+        assert_eq!(space.voxels[(4, 6, 6)], Voxel::Wood);
+        assert_ne!(space.voxels[(6, 6, 6)], Voxel::Wood);
+    }
+
+    #[test]
+    fn forgets_edits_past_the_change_limit() {
+        // each ball is 7 voxels, so only two fit in 20 changes, but a larger edit is still kept
+        let mut space = space();
+        space.history.change_limit = 20;
+        for x in 0..4 {
+            space.paint(&sphere(x as f32 * 3.0, 1.0), Voxel::Snow);
+        }
+        assert_eq!(space.history.len(), (2, 0));
+        space.paint(&sphere(6.0, 3.0), Voxel::Sand);
+        assert_eq!(space.history.len(), (1, 0));
+        space.undo();
+        space.paint(&sphere(6.0, 1.0), Voxel::Leaves);
+        space.paint(&sphere(9.0, 1.0), Voxel::Leaves);
+        assert_eq!(space.history.len(), (2, 0));
+    }
+
+    #[test]
+    fn undoes_from_the_console() {
+        let mut commands = commands();
+        commands.include(crate::brush::commands(), |space: &mut Space| space);
+        let mut space = space();
+        let start = bytes(&space);
+
+        commands.run(&mut space, "stroke begin").unwrap();
+        commands.run(&mut space, "fill 0 0 0 3 3 3 stone").unwrap();
+        commands.run(&mut space, "fill 4 0 0 5 3 3 stone").unwrap();
+        commands.run(&mut space, "stroke end").unwrap();
+        commands.run(&mut space, "sphere 9 9 9 2 snow").unwrap();
+
+        let out = commands.run(&mut space, "undo 5").unwrap();
+        assert_eq!(out, "undid 2 edits, 0 left to undo and 2 to redo");
+        assert!(bytes(&space) == start);
+        let out = commands.run(&mut space, "redo").unwrap();
+        assert_eq!(out, "redid 1 edits, 1 left to undo and 1 to redo");
+        assert_eq!(space.voxels[(5, 3, 3)], Voxel::Stone);
+        assert!(commands.run(&mut space, "stroke maybe").is_err());
+    }
+}
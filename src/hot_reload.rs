@@ -0,0 +1,192 @@
+use notify::DebouncedEvent;
+
+use crate::gfx::ShaderProgram;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// how long the watched files have to stay untouched before reloading. Editors often save in
+// several steps (write a temporary file, remove the original, rename), and a checkout can touch
+// several files at once, so this waits for all of them instead of building each half-saved state.
+pub const RELOAD_DELAY: Duration = Duration::from_millis(150);
+
+// Files changed since the last reload
+#[derive(Clone, Default, Debug)]
+pub struct Changes {
+    pub paths: Vec<PathBuf>,
+
+    // the watcher lost track of what changed, so everything has to be reloaded
+    pub rescan: bool,
+}
+
+impl Changes {
+    pub fn affect(&self, shader: &ShaderProgram) -> bool {
+        self.rescan || self.paths.iter().any(|path| shader.depends_on(path))
+    }
+
+    // Whether any of *files*, as given by `canonical`, changed
+    pub fn affect_any(&self, files: &[PathBuf]) -> bool {
+        self.rescan
+            || self
+                .paths
+                .iter()
+                .any(|path| files.contains(&canonical(path)))
+    }
+}
+
+// Absolute path without links, to compare with the paths in watcher events. Files that don't exist
+// (yet) keep their name in their canonical directory.
+pub fn canonical(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            fs::canonicalize(dir)
+                .map(|dir| dir.join(name))
+                .unwrap_or_else(|_| path.into())
+        }
+        _ => path.into(),
+    }
+}
+
+/**************/
+/* Hot reload */
+/**************/
+
+// Collects file events until they settle, and keeps track of what failed to reload so the errors
+// can be shown until they are fixed
+#[derive(Default)]
+pub struct HotReload {
+    pending: Changes,
+    last_event: Option<Instant>,
+
+    // error of each shader program (or the level) whose last reload failed, by name
+    pub errors: BTreeMap<String, String>,
+}
+
+impl HotReload {
+    pub fn event(&mut self, event: DebouncedEvent, now: Instant) {
+        let path = match event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Remove(path)
+            | DebouncedEvent::Rename(_, path) => path,
+            DebouncedEvent::Rescan => {
+                self.pending.rescan = true;
+                self.last_event = Some(now);
+                return;
+            }
+            DebouncedEvent::Error(err, path) => {
+                eprintln!("File watcher error ({:?}): {}", path, err);
+                return;
+            }
+            // the notices come before the debounced events they announce, and permissions
+            // don't change what the shaders say
+            DebouncedEvent::NoticeWrite(_)
+            | DebouncedEvent::NoticeRemove(_)
+            | DebouncedEvent::Chmod(_) => return,
+        };
+        if !self.pending.paths.contains(&path) {
+            self.pending.paths.push(path);
+        }
+        self.last_event = Some(now);
+    }
+
+    // The changes to reload, once no event has come in for `RELOAD_DELAY`
+    pub fn due(&mut self, now: Instant) -> Option<Changes> {
+        let last_event = self.last_event?;
+        if now.duration_since(last_event) < RELOAD_DELAY {
+            return None;
+        }
+        self.last_event = None;
+        Some(std::mem::take(&mut self.pending))
+    }
+
+    // Records how reloading whatever is called *name* went. A failed reload leaves it as it was,
+    // so the error is kept around until a later reload succeeds.
+    pub fn report<E: fmt::Display>(&mut self, name: &str, result: Result<(), E>) {
+        match result {
+            Ok(()) => {
+                println!("Successfully loaded {}.", name);
+                self.errors.remove(name);
+            }
+            Err(err) => {
+                eprintln!("\nError loading {}: {}", name, err);
+                self.errors.insert(name.to_string(), err.to_string());
+            }
+        }
+    }
+
+    // Text for the error overlay, if anything failed
+    pub fn error_text(&self) -> Option<String> {
+        if self.errors.is_empty() {
+            return None;
+        }
+        let mut text = String::from("Reload errors, still showing the last working version:\n");
+        for (name, err) in &self.errors {
+            text.push_str(&format!("\n{}: {}\n", name, err));
+        }
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocess::ShaderError;
+    use std::io;
+
+    #[test]
+    fn waits_for_events_to_settle() {
+        let mut reload = HotReload::default();
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+
+        // an atomic save: write a temporary file, move the old one away, move the new one in
+        reload.event(DebouncedEvent::Create("a.frag.tmp".into()), ms(0));
+        reload.event(DebouncedEvent::Remove("a.frag".into()), ms(10));
+        reload.event(
+            DebouncedEvent::Rename("a.frag.tmp".into(), "a.frag".into()),
+            ms(20),
+        );
+        reload.event(DebouncedEvent::NoticeWrite("b.glsl".into()), ms(30));
+        assert!(reload.due(ms(100)).is_none());
+
+        let changes = reload.due(ms(200)).unwrap();
+        assert_eq!(
+            changes.paths,
+            [PathBuf::from("a.frag.tmp"), PathBuf::from("a.frag")]
+        );
+        assert!(!changes.rescan);
+        assert!(reload.due(ms(1000)).is_none());
+
+        reload.event(DebouncedEvent::Rescan, ms(1000));
+        assert!(reload.due(ms(2000)).unwrap().rescan);
+    }
+
+    #[test]
+    fn keeps_errors_until_fixed() {
+        let mut reload = HotReload::default();
+        let missing = || ShaderError::Io("a.frag".into(), io::ErrorKind::NotFound.into());
+
+        reload.report("shader", Err(missing()));
+        reload.report("post/bloom", Err(ShaderError::Compile("oops".into())));
+        reload.report("post/gamma", Ok::<(), ShaderError>(()));
+        let text = reload.error_text().unwrap();
+        assert!(text.contains("shader: unable to read"), "{}", text);
+        assert!(text.contains("post/bloom: oops"), "{}", text);
+
+        reload.report("shader", Ok::<(), ShaderError>(()));
+        reload.report("post/bloom", Ok::<(), ShaderError>(()));
+        assert!(reload.error_text().is_none());
+    }
+}
@@ -0,0 +1,325 @@
+use crate::types::Color;
+use crate::world_loader::LoadError;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+// 8-bit RGB image, stored row by row starting at the top left
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    // Loads a PNG, or a binary/ASCII PGM or PPM, based on the file extension
+    pub fn load(path: &Path) -> Result<Image, LoadError> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+
+        match ext.as_str() {
+            "png" => Image::load_png(path),
+            "pgm" | "ppm" | "pnm" => Image::from_pnm(&fs::read(path)?),
+            _ => Err(LoadError::UnsupportedFormat(path.display().to_string())),
+        }
+    }
+
+    fn load_png(path: &Path) -> Result<Image, LoadError> {
+        let png_err = |e: png::DecodingError| LoadError::Parse(format!("png: {}", e));
+
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        // expand palettes and bit depths so every pixel is 8-bit gray or rgb, with or without alpha
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(png_err)?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(png_err)?;
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => {
+                return Err(LoadError::Parse("png: unexpanded palette".to_string()))
+            }
+        };
+
+        let pixels = buf[..info.buffer_size()]
+            .chunks(channels)
+            .map(|px| match channels {
+                1 | 2 => (px[0], px[0], px[0]),
+                _ => (px[0], px[1], px[2]),
+            })
+            .collect::<Vec<_>>();
+
+        let (width, height) = (info.width as usize, info.height as usize);
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return Err(LoadError::Parse(
+                "png: pixel data doesn't match size".to_string(),
+            ));
+        }
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|&(r, g, b)| [r, g, b])
+            .collect();
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(io::Error::other)
+    }
+
+    // Parses the netpbm formats P2/P5 (gray) and P3/P6 (color)
+    pub fn from_pnm(data: &[u8]) -> Result<Image, LoadError> {
+        let parse_err = |msg: &str| LoadError::Parse(format!("pnm: {}", msg));
+
+        // reads the next whitespace separated token, skipping # comments
+        fn next_token(data: &[u8], pos: &mut usize) -> Option<String> {
+            loop {
+                while *pos < data.len() && data[*pos].is_ascii_whitespace() {
+                    *pos += 1;
+                }
+                if *pos < data.len() && data[*pos] == b'#' {
+                    while *pos < data.len() && data[*pos] != b'\n' {
+                        *pos += 1;
+                    }
+                    continue;
+                }
+                break;
+            }
+            let start = *pos;
+            while *pos < data.len() && !data[*pos].is_ascii_whitespace() {
+                *pos += 1;
+            }
+            if start == *pos {
+                None
+            } else {
+                Some(String::from_utf8_lossy(&data[start..*pos]).into_owned())
+            }
+        }
+        let next_number = |pos: &mut usize| -> Result<usize, LoadError> {
+            next_token(data, pos)
+                .and_then(|t| t.parse::<usize>().ok())
+                .ok_or_else(|| parse_err("expected a number"))
+        };
+
+        let mut pos = 0;
+        let magic = next_token(data, &mut pos).ok_or_else(|| parse_err("empty file"))?;
+        let (binary, channels) = match magic.as_str() {
+            "P2" => (false, 1),
+            "P3" => (false, 3),
+            "P5" => (true, 1),
+            "P6" => (true, 3),
+            _ => return Err(parse_err("unsupported magic number")),
+        };
+        let width = next_number(&mut pos)?;
+        let height = next_number(&mut pos)?;
+        let max_val = next_number(&mut pos)?;
+        if max_val == 0 || max_val > 0xffff {
+            return Err(parse_err("invalid maximum value"));
+        }
+        if width == 0 || height == 0 {
+            return Err(parse_err("image has no pixels"));
+        }
+
+        // the size comes from the file, so it is checked before anything is allocated for it
+        let count = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(channels))
+            .ok_or_else(|| parse_err("too large"))?;
+        let samples: Vec<usize> = if binary {
+            // exactly one whitespace byte separates the header from the raster
+            let start = pos + 1;
+            let bytes_per_sample = if max_val < 256 { 1 } else { 2 };
+            let end = count
+                .checked_mul(bytes_per_sample)
+                .and_then(|n| n.checked_add(start))
+                .ok_or_else(|| parse_err("too large"))?;
+            let raster = data
+                .get(start..end)
+                .ok_or_else(|| parse_err("truncated raster"))?;
+            raster
+                .chunks(bytes_per_sample)
+                .map(|b| match b {
+                    [v] => *v as usize,
+                    [hi, lo] => ((*hi as usize) << 8) | *lo as usize,
+                    _ => unreachable!(),
+                })
+                .collect()
+        } else {
+            (0..count)
+                .map(|_| next_number(&mut pos))
+                .collect::<Result<_, _>>()?
+        };
+
+        let scale = |v: usize| (v.min(max_val) * 255 / max_val) as u8;
+        let pixels = samples
+            .chunks(channels)
+            .map(|px| match px {
+                [v] => (scale(*v), scale(*v), scale(*v)),
+                _ => (scale(px[0]), scale(px[1]), scale(px[2])),
+            })
+            .collect();
+
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn blank(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![(0, 0, 0); width * height],
+        }
+    }
+
+    // Copies *other* into this image with its top left corner at *x*, *y*, cutting off whatever
+    // falls outside
+    pub fn paste(&mut self, other: &Image, x: usize, y: usize) {
+        let width = other.width.min(self.width.saturating_sub(x));
+        for row in 0..other.height.min(self.height.saturating_sub(y)) {
+            let dst = x + self.width * (y + row);
+            let src = other.width * row;
+            self.pixels[dst..dst + width].copy_from_slice(&other.pixels[src..src + width]);
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[x + self.width * y]
+    }
+
+    // Samples the pixel nearest to the normalized coordinates (u, v) in [0, 1). An image without
+    // pixels samples as black.
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        if self.width == 0 || self.height == 0 {
+            return (0, 0, 0);
+        }
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.get(x, y)
+    }
+}
+
+// perceived brightness of a color, from 0 to 1
+pub fn luminance((r, g, b): Color) -> f32 {
+    (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) / 255.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn parses_ascii_pnm() {
+        // comments may come anywhere in the header, and samples are scaled to the maximum value
+        let gray = Image::from_pnm(b"P2 # a gray ramp\n3 1\n# max\n4\n0 2 4\n").unwrap();
+        assert_eq!((gray.width, gray.height), (3, 1));
+        assert_eq!(gray.pixels, [(0, 0, 0), (127, 127, 127), (255, 255, 255)]);
+
+        let color = Image::from_pnm(b"P3\n1 2\n255\n255 0 0\n0 0 255\n").unwrap();
+        assert_eq!(color.get(0, 0), (255, 0, 0));
+        assert_eq!(color.get(0, 1), (0, 0, 255));
+
+        assert!(Image::from_pnm(b"").is_err());
+        assert!(Image::from_pnm(b"P4\n1 1\n1\n0\n").is_err());
+        assert!(Image::from_pnm(b"P2\n2 1\n0\n0 0\n").is_err());
+        assert!(Image::from_pnm(b"P2\n2 1\n255\n0\n").is_err());
+        assert!(Image::from_pnm(b"P2\n2 x\n255\n0 0\n").is_err());
+        assert!(Image::from_pnm(b"P2\n0 0\n255\n").is_err());
+        assert!(Image::from_pnm(b"P3\n0 5\n255\n").is_err());
+    }
+
+    #[test]
+    fn parses_binary_pnm() {
+        let gray = Image::from_pnm(b"P5\n2 1\n255\n\x00\x80").unwrap();
+        assert_eq!(gray.pixels, [(0, 0, 0), (128, 128, 128)]);
+
+        // past 255 every sample takes two bytes, most significant first
+        let color = Image::from_pnm(b"P6\n1 1\n65535\n\xff\xff\x80\x00\x00\x00").unwrap();
+        assert_eq!(color.pixels, [(255, 127, 0)]);
+
+        assert!(Image::from_pnm(b"P5\n2 1\n255\n\x00").is_err());
+        assert!(Image::from_pnm(b"P6\n1 1\n65535\n\xff\xff\x80\x00\x00").is_err());
+
+        // sizes whose sample count overflows are refused rather than wrapping around
+        assert!(Image::from_pnm(b"P5 4294967296 4294967296 255\n").is_err());
+        let huge = format!("P6 {} 1 65535\n", usize::MAX / 3);
+        assert!(Image::from_pnm(huge.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn loads_what_it_saves() {
+        let dir = temp_dir("image");
+        let mut image = Image::blank(3, 2);
+        image.pixels[1] = (10, 20, 30);
+        image.pixels[5] = (255, 255, 255);
+        let path = dir.join("saved.png");
+        image.save_png(&path).unwrap();
+
+        let loaded = Image::load(&path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.pixels, image.pixels);
+
+        let pgm = dir.join("GRAY.PGM");
+        fs::write(&pgm, "P2 1 1 255 7").unwrap();
+        assert_eq!(Image::load(&pgm).unwrap().pixels, [(7, 7, 7)]);
+
+        let bmp = dir.join("image.bmp");
+        fs::write(&bmp, "BM").unwrap();
+        assert!(matches!(
+            Image::load(&bmp),
+            Err(LoadError::UnsupportedFormat(_))
+        ));
+        assert!(matches!(
+            Image::load(&dir.join("missing.png")),
+            Err(LoadError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn samples_and_pastes() {
+        let mut image = Image::from_pnm(b"P2 2 2 255 0 1 2 3").unwrap();
+        assert_eq!(image.sample(0.0, 0.0), (0, 0, 0));
+        assert_eq!(image.sample(0.75, 0.25), (1, 1, 1));
+        assert_eq!(image.sample(0.25, 0.99), (2, 2, 2));
+        assert_eq!(image.sample(1.0, 1.0), (3, 3, 3));
+
+        // only the part of the pasted image that fits is copied
+        let white = Image {
+            width: 2,
+            height: 2,
+            pixels: vec![(255, 255, 255); 4],
+        };
+        image.paste(&white, 1, 1);
+        assert_eq!(image.get(0, 1), (2, 2, 2));
+        assert_eq!(image.get(1, 1), (255, 255, 255));
+        assert_eq!(image.get(1, 0), (1, 1, 1));
+
+        assert_eq!(Image::blank(0, 0).sample(0.5, 0.5), (0, 0, 0));
+
+        assert_eq!(luminance((0, 0, 0)), 0.0);
+        assert!((luminance((255, 255, 255)) - 1.0).abs() < 1e-6);
+        assert!(luminance((0, 255, 0)) > luminance((255, 0, 0)));
+    }
+}
@@ -0,0 +1,554 @@
+use derive_more::{Display, From};
+use glium::glutin::event::{ElementState, MouseButton, VirtualKeyCode};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::hash::Hash;
+use std::path::Path;
+
+pub const BINDINGS_PATH_NAME: &str = "res/input.cfg";
+
+/**********/
+/* Action */
+/**********/
+
+// Something the player can do, independent of which inputs trigger it
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    Slow,
+    Jump,
+    ToggleFly,
+    ToggleStats,
+    CycleDebugView,
+    ToggleDebugUi,
+    ToggleConsole,
+    Screenshot,
+    ToggleVideo,
+}
+
+impl Action {
+    pub const ALL: [Action; 15] = [
+        Action::Forward,
+        Action::Back,
+        Action::Left,
+        Action::Right,
+        Action::Up,
+        Action::Down,
+        Action::Slow,
+        Action::Jump,
+        Action::ToggleFly,
+        Action::ToggleStats,
+        Action::CycleDebugView,
+        Action::ToggleDebugUi,
+        Action::ToggleConsole,
+        Action::Screenshot,
+        Action::ToggleVideo,
+    ];
+
+    // name of the action in binding files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Forward => "forward",
+            Action::Back => "back",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Slow => "slow",
+            Action::Jump => "jump",
+            Action::ToggleFly => "toggle_fly",
+            Action::ToggleStats => "toggle_stats",
+            Action::CycleDebugView => "cycle_debug_view",
+            Action::ToggleDebugUi => "toggle_debug_ui",
+            Action::ToggleConsole => "toggle_console",
+            Action::Screenshot => "screenshot",
+            Action::ToggleVideo => "toggle_video",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|a| a.name() == name)
+    }
+}
+
+/***********/
+/* Binding */
+/***********/
+
+// A physical input that can drive an action
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+
+    // one half of an analog axis (e.g. a gamepad stick), identified by its device axis id
+    Axis { axis: u32, positive: bool },
+}
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        &[$((stringify!($key), VirtualKeyCode::$key)),*]
+    };
+}
+
+// names of the keys that can be used in binding files, the same as the VirtualKeyCode variants
+#[rustfmt::skip]
+const KEY_NAMES: &[(&str, VirtualKeyCode)] = key_names![
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Snapshot, Scroll, Pause, Insert, Home, Delete, End, PageDown, PageUp,
+    Left, Up, Right, Down, Back, Return, Space, Tab,
+    Numlock, Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8,
+    Numpad9, NumpadAdd, NumpadDivide, NumpadDecimal, NumpadEnter, NumpadMultiply, NumpadSubtract,
+    Apostrophe, Backslash, Capital, Comma, Equals, Grave, LBracket, Minus, Period, RBracket,
+    Semicolon, Slash,
+    LAlt, LControl, LShift, LWin, RAlt, RControl, RShift, RWin,
+];
+
+pub fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    KEY_NAMES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, key)| *key)
+}
+
+pub fn key_name(key: VirtualKeyCode) -> Option<&'static str> {
+    KEY_NAMES.iter().find(|(_, k)| *k == key).map(|(n, _)| *n)
+}
+
+impl Binding {
+    // Parses a binding as written in binding files: a key name ("W", "LShift"), a mouse button
+    // ("Mouse:Left", "Mouse:4") or half of an axis ("Axis:1+", "Axis:1-")
+    pub fn parse(src: &str) -> Option<Binding> {
+        if let Some(button) = src.strip_prefix("Mouse:") {
+            return Some(Binding::Mouse(match button {
+                "Left" => MouseButton::Left,
+                "Right" => MouseButton::Right,
+                "Middle" => MouseButton::Middle,
+                other => MouseButton::Other(other.parse().ok()?),
+            }));
+        }
+        if let Some(axis) = src.strip_prefix("Axis:") {
+            let (id, positive) = if let Some(id) = axis.strip_suffix('+') {
+                (id, true)
+            } else {
+                (axis.strip_suffix('-')?, false)
+            };
+            return Some(Binding::Axis {
+                axis: id.parse().ok()?,
+                positive,
+            });
+        }
+        key_from_name(src).map(Binding::Key)
+    }
+}
+
+// Writes the binding the way `Binding::parse` reads it
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => match key_name(*key) {
+                Some(name) => write!(f, "{}", name),
+                None => write!(f, "{:?}", key),
+            },
+            Binding::Mouse(MouseButton::Other(n)) => write!(f, "Mouse:{}", n),
+            Binding::Mouse(button) => write!(f, "Mouse:{:?}", button),
+            Binding::Axis { axis, positive } => {
+                write!(f, "Axis:{}{}", axis, if *positive { '+' } else { '-' })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Display, From)]
+pub enum BindingError {
+    #[display(fmt = "unable to read bindings: {}", _0)]
+    Io(std::io::Error),
+
+    #[display(fmt = "bindings line {}: {}", line, msg)]
+    #[from(ignore)]
+    Parse { line: usize, msg: String },
+}
+
+/******************/
+/* Input bindings */
+/******************/
+
+pub struct InputBindings {
+    bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputBindings {
+    fn default() -> InputBindings {
+        InputBindings::parse(include_str!("../res/input.cfg"))
+            .expect("default bindings are invalid")
+    }
+}
+
+impl InputBindings {
+    // Parses lines of the form `action = binding, binding, ...`; blank lines and lines starting
+    // with # are ignored. Actions not mentioned have no bindings.
+    pub fn parse(src: &str) -> Result<InputBindings, BindingError> {
+        let mut bindings: HashMap<Action, Vec<Binding>> = HashMap::new();
+
+        for (line_no, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: String| BindingError::Parse {
+                line: line_no + 1,
+                msg,
+            };
+
+            let (name, inputs) = line
+                .split_once('=')
+                .ok_or_else(|| err("expected `action = binding, ...`".to_string()))?;
+            let action = Action::from_name(name.trim())
+                .ok_or_else(|| err(format!("unknown action {:?}", name.trim())))?;
+
+            let entry = bindings.entry(action).or_default();
+            for input in inputs.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+                let binding = Binding::parse(input)
+                    .ok_or_else(|| err(format!("unknown input {:?}", input)))?;
+                entry.push(binding);
+            }
+        }
+
+        Ok(InputBindings { bindings })
+    }
+
+    pub fn load(path: &Path) -> Result<InputBindings, BindingError> {
+        InputBindings::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+/***************/
+/* Input state */
+/***************/
+
+// Tracks the raw inputs as events come in, and turns them into action states once per
+// simulation step
+pub struct InputState {
+    pub bindings: InputBindings,
+
+    keys: HashSet<VirtualKeyCode>,
+    buttons: HashSet<MouseButton>,
+    axes: HashMap<u32, f32>,
+
+    // inputs pressed since the last update, so taps shorter than a step still register
+    tapped: HashSet<Binding>,
+
+    held: HashSet<Action>,
+    prev_held: HashSet<Action>,
+}
+
+impl InputState {
+    // analog values above this count as holding the action down
+    const HELD_THRESHOLD: f32 = 0.5;
+
+    pub fn new(bindings: InputBindings) -> InputState {
+        InputState {
+            bindings,
+            keys: HashSet::new(),
+            buttons: HashSet::new(),
+            axes: HashMap::new(),
+            tapped: HashSet::new(),
+            held: HashSet::new(),
+            prev_held: HashSet::new(),
+        }
+    }
+
+    pub fn key_event(&mut self, key: VirtualKeyCode, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                if self.keys.insert(key) {
+                    self.tapped.insert(Binding::Key(key));
+                }
+            }
+            ElementState::Released => {
+                self.keys.remove(&key);
+            }
+        }
+    }
+
+    pub fn mouse_button_event(&mut self, button: MouseButton, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                if self.buttons.insert(button) {
+                    self.tapped.insert(Binding::Mouse(button));
+                }
+            }
+            ElementState::Released => {
+                self.buttons.remove(&button);
+            }
+        }
+    }
+
+    pub fn axis_event(&mut self, axis: u32, value: f32) {
+        self.axes.insert(axis, value);
+    }
+
+    // how strongly a single input is currently pressed, from 0 to 1
+    fn binding_value(&self, binding: &Binding) -> f32 {
+        let pressed = |down: bool| if down { 1.0 } else { 0.0 };
+        match binding {
+            Binding::Key(key) => pressed(self.keys.contains(key)),
+            Binding::Mouse(button) => pressed(self.buttons.contains(button)),
+            Binding::Axis { axis, positive } => {
+                let value = self.axes.get(axis).copied().unwrap_or(0.0);
+                let value = if *positive { value } else { -value };
+                value.clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    // Samples the inputs into action states. Call once at the start of every simulation step.
+    pub fn update(&mut self) {
+        self.prev_held = std::mem::take(&mut self.held);
+        for action in Action::ALL.iter() {
+            let bindings = self.bindings.bindings(*action);
+            let tapped = bindings.iter().any(|b| self.tapped.contains(b));
+            if tapped || self.value(*action) > InputState::HELD_THRESHOLD {
+                self.held.insert(*action);
+            }
+        }
+        self.tapped.clear();
+    }
+
+    // analog value of the action right now, from 0 to 1, taking the strongest of its inputs
+    pub fn value(&self, action: Action) -> f32 {
+        self.bindings
+            .bindings(action)
+            .iter()
+            .map(|b| self.binding_value(b))
+            .fold(0.0, f32::max)
+    }
+
+    // whether *key* is down right now, so that key repeats can be told apart from presses
+    pub fn key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys.contains(&key)
+    }
+
+    // whether the action was held down at the last update
+    pub fn held(&self, action: Action) -> bool {
+        self.held.contains(&action)
+    }
+
+    // whether the action went down at the last update
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.held.contains(&action) && !self.prev_held.contains(&action)
+    }
+
+    // whether the action went up at the last update
+    pub fn just_released(&self, action: Action) -> bool {
+        !self.held.contains(&action) && self.prev_held.contains(&action)
+    }
+
+    // value of *positive* minus value of *negative*, from -1 to 1
+    pub fn axis(&self, positive: Action, negative: Action) -> f32 {
+        self.value(positive) - self.value(negative)
+    }
+}
+
+/***************/
+/* Axis filter */
+/***************/
+
+// Tells gamepad axes apart from pointer motion, which winit reports through the same axis events.
+// Devices that move the mouse or scroll are pointers, whose axes are dropped. Pointers send their
+// axes before their mouse motion, so the axes of a device not known to be a pointer are held back
+// until every event of the batch is in.
+pub struct AxisFilter<D> {
+    pointers: HashSet<D>,
+    pending: Vec<(D, u32, f32)>,
+}
+
+impl<D> Default for AxisFilter<D> {
+    fn default() -> AxisFilter<D> {
+        AxisFilter {
+            pointers: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<D: Copy + Eq + Hash> AxisFilter<D> {
+    pub fn axis_event(&mut self, device: D, axis: u32, value: f32) {
+        if !self.pointers.contains(&device) {
+            self.pending.push((device, axis, value));
+        }
+    }
+
+    // the device moved the mouse or scrolled
+    pub fn pointer_event(&mut self, device: D) {
+        self.pointers.insert(device);
+        self.pending.retain(|(d, _, _)| *d != device);
+    }
+
+    // Axis values of devices that aren't pointers, as (axis, value). Call once the events of a
+    // batch have all come in.
+    pub fn take(&mut self) -> Vec<(u32, f32)> {
+        self.pending
+            .drain(..)
+            .map(|(_, axis, value)| (axis, value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ElementState::{Pressed, Released};
+
+    fn parse_error_line(src: &str) -> Option<usize> {
+        match InputBindings::parse(src) {
+            Err(BindingError::Parse { line, .. }) => Some(line),
+            _ => None,
+        }
+    }
+
+    fn state(src: &str) -> InputState {
+        InputState::new(InputBindings::parse(src).unwrap())
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let bindings = InputBindings::parse(
+            "# movement\n\
+             \n\
+             forward = W, Up,Axis:1-\n\
+             \x20  jump = space , Mouse:Right, Mouse:4\n\
+             slow =\n",
+        )
+        .unwrap();
+        assert_eq!(
+            bindings.bindings(Action::Forward),
+            [
+                Binding::Key(VirtualKeyCode::W),
+                Binding::Key(VirtualKeyCode::Up),
+                Binding::Axis {
+                    axis: 1,
+                    positive: false
+                }
+            ]
+        );
+        assert_eq!(
+            bindings.bindings(Action::Jump),
+            [
+                Binding::Key(VirtualKeyCode::Space),
+                Binding::Mouse(MouseButton::Right),
+                Binding::Mouse(MouseButton::Other(4))
+            ]
+        );
+        assert!(bindings.bindings(Action::Slow).is_empty());
+        assert!(bindings.bindings(Action::Back).is_empty());
+
+        // errors point at the line, counting the comments and blank lines before it
+        assert_eq!(parse_error_line("# bindings\n\nfly = F\n"), Some(3));
+        assert_eq!(parse_error_line("jump = Space\njump = Hyper\n"), Some(2));
+        assert_eq!(parse_error_line("jump Space\n"), Some(1));
+        for axis in ["Axis:1", "Axis:x+", "Axis:+", "Axis:1*"] {
+            assert_eq!(parse_error_line(&format!("up = {}", axis)), Some(1));
+        }
+        assert_eq!(parse_error_line("up = Mouse:Side"), Some(1));
+
+        // what is written reads back
+        for binding in InputBindings::default().bindings.values().flatten() {
+            assert_eq!(Binding::parse(&binding.to_string()), Some(*binding));
+        }
+    }
+
+    #[test]
+    fn tracks_presses_across_updates() {
+        let mut input = state("jump = Space, Mouse:Left\n");
+
+        input.key_event(VirtualKeyCode::Space, Pressed);
+        input.update();
+        assert!(input.held(Action::Jump) && input.just_pressed(Action::Jump));
+
+        // a key repeat is not another press
+        input.key_event(VirtualKeyCode::Space, Pressed);
+        input.update();
+        assert!(input.held(Action::Jump));
+        assert!(!input.just_pressed(Action::Jump) && !input.just_released(Action::Jump));
+
+        input.key_event(VirtualKeyCode::Space, Released);
+        input.update();
+        assert!(!input.held(Action::Jump) && input.just_released(Action::Jump));
+        input.update();
+        assert!(!input.just_released(Action::Jump));
+
+        // a tap between two updates still counts as a press, for one update
+        input.mouse_button_event(MouseButton::Left, Pressed);
+        input.mouse_button_event(MouseButton::Left, Released);
+        assert!(!input.held(Action::Jump));
+        input.update();
+        assert!(input.just_pressed(Action::Jump));
+        input.update();
+        assert!(input.just_released(Action::Jump));
+
+        // unbound inputs do nothing
+        input.key_event(VirtualKeyCode::W, Pressed);
+        input.update();
+        assert!(Action::ALL.iter().all(|a| !input.held(*a)));
+        assert!(input.key_down(VirtualKeyCode::W));
+    }
+
+    #[test]
+    fn holds_axes_past_half_way() {
+        let mut input = state("forward = Axis:1-, W\nback = Axis:1+\n");
+
+        input.axis_event(1, -0.5);
+        input.update();
+        assert_eq!(input.value(Action::Forward), 0.5);
+        assert!(!input.held(Action::Forward));
+
+        input.axis_event(1, -0.6);
+        input.update();
+        assert!(input.just_pressed(Action::Forward));
+        assert!(!input.held(Action::Back));
+        assert_eq!(input.value(Action::Back), 0.0);
+        assert!((input.axis(Action::Forward, Action::Back) - 0.6).abs() < 1e-6);
+
+        // the strongest input wins, and values past the end of the axis are clamped
+        input.key_event(VirtualKeyCode::W, Pressed);
+        input.update();
+        assert_eq!(input.value(Action::Forward), 1.0);
+        input.key_event(VirtualKeyCode::W, Released);
+        input.axis_event(1, 2.0);
+        input.update();
+        assert!(input.just_released(Action::Forward));
+        assert_eq!(input.axis(Action::Forward, Action::Back), -1.0);
+    }
+
+    #[test]
+    fn drops_pointer_axes() {
+        let mut filter = AxisFilter::default();
+
+        // a mouse sends its axes before the motion that gives it away
+        filter.axis_event(1, 0, 3.0);
+        filter.axis_event(1, 1, -2.0);
+        filter.axis_event(2, 0, 0.25);
+        filter.pointer_event(1);
+        assert_eq!(filter.take(), [(0, 0.25)]);
+        assert!(filter.take().is_empty());
+
+        filter.axis_event(1, 3, 1.0);
+        filter.axis_event(2, 1, -0.5);
+        assert_eq!(filter.take(), [(1, -0.5)]);
+    }
+}
@@ -0,0 +1,185 @@
+use notify::{RecursiveMode, Watcher};
+
+use crate::hot_reload::canonical;
+use crate::types::Dimension3;
+use crate::world_loader::{Level, LevelLoaders, LoadError};
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+// A finished load: the level, or why it couldn't be loaded, with the files it was read from
+type Loaded = (Result<Level, LoadError>, Vec<PathBuf>);
+
+// Reloads the level file on a background thread when it or any of its sidecar files change, so
+// the game keeps running while the level is voxelized and its SDF computed
+pub struct LevelReload {
+    pub path: PathBuf,
+    shape: Dimension3,
+
+    // canonical paths of the files the level was loaded from, see `LevelLoader::dependencies`
+    pub files: Vec<PathBuf>,
+
+    // the load in progress, if any
+    loading: Option<Receiver<Loaded>>,
+
+    // the files changed again during the load in progress, so it is already out of date
+    stale: bool,
+
+    // directories of `files` being watched
+    watched: Vec<PathBuf>,
+}
+
+impl LevelReload {
+    pub fn new(path: &Path, shape: Dimension3) -> LevelReload {
+        LevelReload {
+            path: path.to_path_buf(),
+            shape,
+            files: dependencies(path),
+            loading: None,
+            stale: false,
+            watched: Vec::new(),
+        }
+    }
+
+    // Watches the directories of the level's files, where not watched yet. Sidecar files can be
+    // created and renamed, so the directories are watched rather than the files.
+    pub fn watch<W: Watcher>(&mut self, watcher: &mut W) {
+        for dir in self.files.iter().filter_map(|f| f.parent()) {
+            if self.watched.iter().any(|d| d == dir) {
+                continue;
+            }
+            // directories that don't exist yet can't be watched, so they are tried again after
+            // the next reload
+            if watcher.watch(dir, RecursiveMode::NonRecursive).is_ok() {
+                self.watched.push(dir.to_path_buf());
+            }
+        }
+    }
+
+    // Starts loading the level again, or marks the load in progress to be redone once it's done
+    pub fn start(&mut self) {
+        if self.loading.is_some() {
+            self.stale = true;
+            return;
+        }
+
+        let (sender, receiver) = channel();
+        let (path, shape) = (self.path.clone(), self.shape);
+        thread::spawn(move || {
+            // loaders can't be shared between threads, so each load makes its own
+            let loaders = LevelLoaders::default();
+            let result = loaders.load(&path, shape);
+            let files = loaders
+                .dependencies(&path)
+                .iter()
+                .map(|f| canonical(f))
+                .collect();
+            // the receiver is gone if the game quit in the meantime
+            let _ = sender.send((result, files));
+        });
+        self.loading = Some(receiver);
+    }
+
+    // The reloaded level, once a load has finished
+    pub fn poll(&mut self) -> Option<Result<Level, LoadError>> {
+        let (result, files) = match self.loading.as_ref()?.try_recv() {
+            Ok(loaded) => loaded,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => (
+                Err(LoadError::Parse(
+                    "the loader panicked, see the message above".to_string(),
+                )),
+                self.files.clone(),
+            ),
+        };
+        self.loading = None;
+        self.files = files;
+
+        if self.stale {
+            self.stale = false;
+            self.start();
+            return None;
+        }
+        Some(result)
+    }
+}
+
+fn dependencies(path: &Path) -> Vec<PathBuf> {
+    LevelLoaders::default()
+        .dependencies(path)
+        .iter()
+        .map(|f| canonical(f))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hot_reload::Changes;
+    use crate::test_util::temp_dir;
+    use crate::world::Voxel;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    fn wait(reload: &mut LevelReload) -> Result<Level, LoadError> {
+        let start = Instant::now();
+        loop {
+            if let Some(result) = reload.poll() {
+                return result;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "load never finished"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn reloads_in_the_background() {
+        let dir = temp_dir("level_reload_gox");
+        let path = dir.join("level.gox");
+        fs::write(&path, "1 2 3 ff0000\n").unwrap();
+
+        let mut reload = LevelReload::new(&path, (8, 8, 8));
+        assert!(reload.poll().is_none());
+        reload.start();
+        let space = wait(&mut reload).unwrap().space;
+        assert_eq!(space.voxels[(1, 2, 3)], Voxel::Red);
+
+        // a change during the load throws that load away in favour of a new one
+        fs::write(&path, "4 4 4 0000ff\n").unwrap();
+        reload.start();
+        reload.start();
+        let space = wait(&mut reload).unwrap().space;
+        assert_eq!(space.voxels[(1, 2, 3)], Voxel::Empty);
+        assert!(reload.poll().is_none());
+
+        fs::remove_file(&path).unwrap();
+        reload.start();
+        assert!(wait(&mut reload).is_err());
+    }
+
+    #[test]
+    fn watches_sidecar_files() {
+        let dir = temp_dir("level_reload_obj");
+        let path = dir.join("model.obj");
+        fs::write(&path, "mtllib model.mtl\nv 0 0 0\n").unwrap();
+        fs::write(dir.join("model.mtl"), "newmtl a\nmap_Kd -bm 1 tex/a.png\n").unwrap();
+
+        let reload = LevelReload::new(&path, (8, 8, 8));
+        let changed = |name: &str| Changes {
+            paths: vec![dir.join(name)],
+            rescan: false,
+        };
+        assert!(changed("model.obj").affect_any(&reload.files));
+        assert!(changed("model.mtl").affect_any(&reload.files));
+        assert!(changed("tex/a.png").affect_any(&reload.files));
+        assert!(!changed("other.obj").affect_any(&reload.files));
+
+        // a heightmap's color map counts before it exists
+        let reload = LevelReload::new(&dir.join("terrain.png"), (8, 8, 8));
+        assert!(changed("terrain_color.png").affect_any(&reload.files));
+    }
+}
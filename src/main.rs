@@ -1,9 +1,36 @@
+mod brush;
+mod camera_path;
+mod config;
+mod console;
+mod debug_ui;
+mod debug_view;
 mod game;
 mod gfx;
+mod heightmap;
+mod history;
+mod hot_reload;
+mod image;
+mod input;
+mod level_reload;
 mod march;
+mod physics;
+mod post;
+mod prefab;
+mod preprocess;
+mod procgen;
+mod projection;
+mod replay;
+mod resolution;
+mod stats;
+#[cfg(test)]
+mod test_util;
+mod text;
 mod types;
 mod uniforms;
+mod video;
+mod voxelize;
 mod world;
+mod world_exporter;
 mod world_loader;
 
 extern crate derive_more;
@@ -12,26 +39,115 @@ extern crate derive_more;
 extern crate glium;
 extern crate nalgebra as na;
 
-use glium::{glutin, index::PrimitiveType};
+use glium::{glutin, index::PrimitiveType, Surface};
 
 use notify::{watcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use game::Game;
 use gfx::{DenseCartesianRenderer, DenseCartesianUniforms};
+use input::{Action, Binding};
 use uniforms::AsGPUResource;
 
 fn main() {
-    // Initialize watcher which monitors the shader files
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match config::from_args(&args, Some(Path::new(config::CONFIG_PATH_NAME))) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    let config = options.config.clone();
+
+    match options.mode {
+        config::Mode::Help => {
+            println!("{}", config::USAGE);
+            return;
+        }
+        config::Mode::PrintConfig => {
+            print!("{}", config.to_toml());
+            return;
+        }
+        config::Mode::Run => (),
+    }
+    if let Err(err) = config.validate(Path::new(".")) {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    }
+
+    // a seed generates the world procedurally, otherwise the level is loaded with whichever
+    // loader is registered for its format
+    let world = if let Some(seed) = config.seed {
+        let gen = procgen::TerrainGenerator::new(procgen::GenParams::with_seed(seed));
+        gen.generate_space(world::WORLD_DIM)
+    } else {
+        let level = world_loader::LevelLoaders::default()
+            .load(&config.level, world::WORLD_DIM)
+            .unwrap_or_else(|err| panic!("Unable to load level {:?}: {}", config.level, err));
+        for warning in &level.warnings {
+            eprintln!("Warning loading level {:?}: {}", config.level, warning);
+        }
+        level.space
+    };
+    let mut game = Game::with_world(world);
+    game.mouse_speed = config.mouse_speed;
+    game.camera.projection = config.projection();
+
+    // `--export <path>` writes the level out in the format given by the extension and exits
+    if let Some(path) = &options.export {
+        match world_exporter::export(&game.world, path) {
+            Ok(()) => println!("Exported level to {:?}", path),
+            Err(err) => eprintln!("Unable to export level to {:?}: {}", path, err),
+        }
+        return;
+    }
+
+    // the bindings file is optional, the built-in defaults are used without it
+    match input::InputBindings::load(&config.bindings) {
+        Ok(bindings) => game.input.bindings = bindings,
+        Err(err) => eprintln!("Using default input bindings: {}", err),
+    }
+
+    if let Some(path) = &options.play_path {
+        let camera_path = camera_path::CameraPath::load(path)
+            .unwrap_or_else(|err| panic!("Unable to load camera path {:?}: {}", path, err));
+        game.play_path(camera_path);
+    }
+    if options.record_path.is_some() {
+        game.start_recording(0.25);
+    }
+    if options.record_input.is_some() {
+        game.input_recording = Some(replay::InputRecording::default());
+    }
+
+    // while a recording of inputs is replaying, live input is ignored
+    let mut input_replay = options.replay_input.as_ref().map(|path| {
+        let recording = replay::InputRecording::load(path)
+            .unwrap_or_else(|err| panic!("Unable to load input recording {:?}: {}", path, err));
+        replay::InputReplay::new(recording)
+    });
+
+    // Initialize watcher which monitors the shader and level files
     let (sender, receiver) = channel();
-    let mut watcher = watcher(sender, Duration::ZERO).unwrap();
+    let mut watcher = watcher(sender, hot_reload::RELOAD_DELAY).unwrap();
     watcher
-        .watch(gfx::SHADER_PATH_NAME, RecursiveMode::Recursive)
+        .watch(&config.shader_dir, RecursiveMode::Recursive)
         .unwrap();
 
-    // Initialize event loop with shader file monitor
+    // levels loaded from a file are reloaded when it changes, generated ones have nothing to watch
+    let mut level_reload = config
+        .seed
+        .is_none()
+        .then(|| level_reload::LevelReload::new(&config.level, world::WORLD_DIM));
+    if let Some(level_reload) = &mut level_reload {
+        level_reload.watch(&mut watcher);
+    }
+
+    // Initialize event loop with file monitor
     let event_loop = glutin::event_loop::EventLoop::with_user_event();
     let fs_event_proxy: glutin::event_loop::EventLoopProxy<notify::DebouncedEvent> =
         event_loop.create_proxy();
@@ -44,8 +160,14 @@ fn main() {
     });
 
     // Initialize display
-    let wb = glutin::window::WindowBuilder::new();
-    let cb = glutin::ContextBuilder::new().with_vsync(true);
+    // capturing renders offscreen, so the window stays hidden
+    let wb = glutin::window::WindowBuilder::new()
+        .with_inner_size(glutin::dpi::LogicalSize::new(
+            config.window.width,
+            config.window.height,
+        ))
+        .with_visible(options.capture_dir.is_none());
+    let cb = glutin::ContextBuilder::new().with_vsync(config.window.vsync);
     let display = glium::Display::new(wb, cb, &event_loop).unwrap();
 
     // Initialize vertex buffer for triangle which covers the screen
@@ -74,49 +196,270 @@ fn main() {
     let index_buffer =
         glium::IndexBuffer::new(&display, PrimitiveType::TrianglesList, &[0, 1, 2]).unwrap();
 
-    let mut program = gfx::load_shader(&display, "shader").unwrap();
-    let mut game = Game::new();
+    let mut shader = gfx::load_shader(&display, &config.shader_dir, "shader")
+        .unwrap_or_else(|err| panic!("Unable to load shader: {}", err));
     let mut renderer = gfx::DenseCartesianRenderer {
         uniforms: DenseCartesianUniforms {
             sdf: game.world.sdf.as_gpu_resource(&display),
             voxels: game.world.voxels.as_gpu_resource(&display),
+            generation: game.world.generation,
         },
+        post: post::PostPipeline::new(&display, &config.shader_dir, &config.post_passes)
+            .unwrap_or_else(|err| panic!("Unable to load post passes: {}", err)),
+        render_scale: config.render_scale,
+        lighting: gfx::Lighting::default(),
+        debug_view: config.debug_view,
+        gpu_timer: stats::GpuTimer::default(),
     };
+    renderer.post.pixelated = config.pixelated;
+    let mut dynamic_resolution = config
+        .frame_budget
+        .map(|ms| resolution::DynamicResolution::new(config.render_scale, ms / 1000.0));
+
+    // `--capture-dir` renders the camera path at a fixed frame rate into numbered PNGs and exits
+    if let Some(dir) = &options.capture_dir {
+        let size = (config.window.width, config.window.height);
+        let mut capture = video::VideoRecorder::to_dir(dir, options.capture_fps, size)
+            .expect("Unable to create capture directory");
+        loop {
+            game.update(capture.frame_time());
+            let image = renderer.capture(
+                &display,
+                &vertex_buffer,
+                &index_buffer,
+                &shader.program,
+                &game,
+                size,
+            );
+            capture
+                .write(&image)
+                .unwrap_or_else(|err| panic!("Unable to write to {:?}: {}", dir, err));
+            if game.playback_finished() {
+                println!("Captured {} frames to {:?}", capture.frames, dir);
+                return;
+            }
+        }
+    }
+
+    let text_renderer = text::TextRenderer::new(&display);
+    let mut hot_reload = hot_reload::HotReload::default();
+
+    // frame timings, shown over the game while `show_stats` is on
+    let mut frame_stats = stats::FrameStats::default();
+    let mut show_stats = config.show_stats;
+    let mut frame_log = options.stats_csv.as_ref().map(|path| {
+        stats::FrameLog::create(path)
+            .unwrap_or_else(|err| panic!("Unable to create {:?}: {}", path, err))
+    });
+
+    // settings panel, laid out again every frame while it's open
+    let mut debug_ui = debug_ui::DebugUi::default();
+    let mut panel: Option<String> = None;
+    let mut world_stats = (Instant::now(), debug_ui::WorldStats::count(&game));
+
+    // commands typed into the console, run before the next frame is drawn
+    let mut console = console::Console::default();
+    let mut console_line: Option<String> = None;
+
+    // where to save the next screenshot and at what scale, taken when the next frame is drawn
+    let mut screenshot: Option<(PathBuf, u32)> = None;
+
+    // video being recorded, which runs the game at its frame rate instead of in real time, and
+    // whether to start or stop recording before the next frame
+    let mut video: Option<video::VideoRecorder> = None;
+    let mut toggle_video = options.record_video;
+
     let mut window_focused = false;
+    let mut axis_filter = input::AxisFilter::default();
+    let mut last_frame = Instant::now();
 
-    // TODO: Add FPS counter
     // the main loop
     event_loop.run(move |event, _, control_flow| {
-        let old_time = game.begin_time + game.time_elapsed;
         let windowed_context = display.gl_window();
         let window = windowed_context.window();
 
+        // draws the game, with the statistics, any shader errors, the debug panel and the console
+        // over it, returning how long issuing the draw calls took and, when *read_back* is set,
+        // the game as drawn before anything went over it
+        let draw = |renderer: &mut DenseCartesianRenderer,
+                    shader: &gfx::ShaderProgram,
+                    game: &game::Game,
+                    hot_reload: &hot_reload::HotReload,
+                    stats: Option<&str>,
+                    panel: Option<&str>,
+                    console: Option<&str>,
+                    read_back: bool| {
+            let start = Instant::now();
+            let mut frame = display.draw();
+            renderer.draw(
+                &display,
+                &mut frame,
+                &vertex_buffer,
+                &index_buffer,
+                &shader.program,
+                game,
+            );
+            let image = read_back.then(|| gfx::read_frame(&display, &frame));
+            let mut y = 8.0;
+            if let Some(stats) = stats {
+                text_renderer.draw(&display, &mut frame, stats, (8.0, y), [1.0; 4]);
+                y += text_renderer.measure(stats, frame.get_dimensions().0).1 + 8.0;
+            }
+            if let Some(errors) = hot_reload.error_text() {
+                text_renderer.draw(
+                    &display,
+                    &mut frame,
+                    &errors,
+                    (8.0, y),
+                    [1.0, 0.4, 0.4, 1.0],
+                );
+            }
+            if let Some(panel) = panel {
+                let width = text_renderer.measure(panel, frame.get_dimensions().0).0;
+                let x = (frame.get_dimensions().0 as f32 - width - 8.0).max(0.0);
+                text_renderer.draw(&display, &mut frame, panel, (x, 8.0), [1.0; 4]);
+            }
+            if let Some(console) = console {
+                text_renderer.draw(&display, &mut frame, console, (8.0, 8.0), [1.0; 4]);
+            }
+            // without waiting for vsync
+            let draw_time = start.elapsed();
+            frame.finish().unwrap();
+            (draw_time, image)
+        };
+
         match event {
             // Window event
             glutin::event::Event::WindowEvent { event, .. } => match event {
                 // Window closed
                 glutin::event::WindowEvent::CloseRequested => {
+                    if let (Some(path), Some(camera_path)) =
+                        (&options.record_path, game.stop_recording())
+                    {
+                        match camera_path.save(path) {
+                            Ok(()) => println!("Saved camera path to {:?}", path),
+                            Err(err) => eprintln!("Unable to save camera path: {}", err),
+                        }
+                    }
+                    if let (Some(path), Some(recording)) =
+                        (&options.record_input, game.input_recording.take())
+                    {
+                        match recording.save(path) {
+                            Ok(()) => println!("Saved input recording to {:?}", path),
+                            Err(err) => eprintln!("Unable to save input recording: {}", err),
+                        }
+                    }
+                    if let Some(Err(err)) = frame_log.as_mut().map(|log| log.flush()) {
+                        eprintln!("Unable to write frame statistics: {}", err);
+                    }
+                    if let Some(video) = video.take() {
+                        report(&mut console, stop_video(video));
+                    }
                     *control_flow = glutin::event_loop::ControlFlow::Exit;
                     return;
                 }
 
                 // Window resized
                 glutin::event::WindowEvent::Resized { .. } => {
-                    renderer.draw(&display, &vertex_buffer, &index_buffer, &program, &game);
+                    let stats = show_stats.then(|| frame_stats.overlay_text());
+                    draw(
+                        &mut renderer,
+                        &shader,
+                        &game,
+                        &hot_reload,
+                        stats.as_deref(),
+                        panel.as_deref(),
+                        console.open.then(|| console.text()).as_deref(),
+                        false,
+                    );
                     return;
                 }
 
                 // Keyboard input
                 glutin::event::WindowEvent::KeyboardInput { input, .. } => {
-                    game.keyboard_input(input);
+                    // the overlays aren't part of the game, so they respond even during replays
+                    let pressed = |action: Action| {
+                        input.state == glutin::event::ElementState::Pressed
+                            && input.virtual_keycode.is_some_and(|key| {
+                                let bindings = game.input.bindings.bindings(action);
+                                bindings.contains(&Binding::Key(key)) && !game.input.key_down(key)
+                            })
+                    };
+                    if pressed(Action::ToggleStats) {
+                        show_stats = !show_stats;
+                    }
+                    if pressed(Action::CycleDebugView) {
+                        renderer.debug_view = renderer.debug_view.next();
+                        println!("Debug view: {}", renderer.debug_view.name());
+                    }
+                    if pressed(Action::ToggleDebugUi) {
+                        debug_ui.open = !debug_ui.open;
+                    }
+                    if pressed(Action::ToggleConsole) {
+                        console.toggle();
+                    }
+                    if pressed(Action::Screenshot) {
+                        screenshot = Some((screenshot_path(), config.screenshot_scale));
+                    }
+                    if pressed(Action::ToggleVideo) {
+                        toggle_video = !toggle_video;
+                    }
+                    // the open console takes every other key press for itself, and the open
+                    // panel the arrow keys
+                    let overlay_key = input.virtual_keycode.is_some_and(|key| {
+                        let overlay_actions = [
+                            Action::ToggleStats,
+                            Action::CycleDebugView,
+                            Action::ToggleDebugUi,
+                            Action::ToggleConsole,
+                            Action::Screenshot,
+                            Action::ToggleVideo,
+                        ];
+                        overlay_actions.iter().any(|action| {
+                            let bindings = game.input.bindings.bindings(*action);
+                            bindings.contains(&Binding::Key(key))
+                        })
+                    });
+                    let press = input.state == glutin::event::ElementState::Pressed;
+                    let for_console = console.open && press && !overlay_key;
+                    if for_console {
+                        if let Some(line) = input.virtual_keycode.and_then(|key| console.key(key)) {
+                            console_line = Some(line);
+                        }
+                    }
+                    let for_panel = !for_console
+                        && press
+                        && input.virtual_keycode.is_some_and(|key| debug_ui.key(key));
+                    if input_replay.is_none() && !for_console && !for_panel {
+                        game.keyboard_input(input);
+                    }
+                }
+
+                // Text typed into the console
+                glutin::event::WindowEvent::ReceivedCharacter(c) => {
+                    if console.open {
+                        console.character(c);
+                    }
+                }
+
+                // Mouse button input
+                glutin::event::WindowEvent::MouseInput { state, button, .. } => {
+                    if input_replay.is_none() {
+                        game.mouse_button_input(button, state);
+                    }
                 }
 
                 // Window focused
                 glutin::event::WindowEvent::Focused(focused) => {
                     window_focused = focused;
                     window.set_cursor_visible(!focused);
+                    let grab_mode = if focused {
+                        glutin::window::CursorGrabMode::Confined
+                    } else {
+                        glutin::window::CursorGrabMode::None
+                    };
                     window
-                        .set_cursor_grab(focused)
+                        .set_cursor_grab(grab_mode)
                         .expect("Could not grab cursor");
                 }
 
@@ -124,39 +467,224 @@ fn main() {
             },
 
             // Mouse input
-            glutin::event::Event::DeviceEvent {
-                device_id: _,
-                event,
-            } => match event {
-                glutin::event::DeviceEvent::MouseMotion { delta } => {
-                    if window_focused {
+            glutin::event::Event::DeviceEvent { device_id, event }
+                if window_focused && input_replay.is_none() =>
+            {
+                match event {
+                    glutin::event::DeviceEvent::MouseMotion { delta } => {
+                        axis_filter.pointer_event(device_id);
                         game.mouse_input((delta.0 as f32, delta.1 as f32));
                     }
-                }
-                _ => (),
-            },
-
-            // Shader modified, reload
-            glutin::event::Event::UserEvent(notify::DebouncedEvent::Write(_)) => {
-                match gfx::load_shader(&display, "shader") {
-                    Ok(new_program) => {
-                        println!("Successfully loaded new shader.");
-                        program = new_program;
+                    glutin::event::DeviceEvent::MouseWheel { .. } => {
+                        axis_filter.pointer_event(device_id);
                     }
-
-                    Err(err) => {
-                        eprintln!("\nError loading new shader: {}", err);
+                    // mice report their motion as axes too, which the filter drops
+                    glutin::event::DeviceEvent::Motion { axis, value } => {
+                        axis_filter.axis_event(device_id, axis, value as f32);
                     }
+                    _ => (),
                 }
+            }
+
+            // Watched files changed, reloaded once they settle
+            glutin::event::Event::UserEvent(event) => {
+                hot_reload.event(event, Instant::now());
                 return;
             }
 
             glutin::event::Event::MainEventsCleared => {
-                game.tick();
-                renderer.draw(&display, &vertex_buffer, &index_buffer, &program, &game);
-                let time_delta = SystemTime::now().duration_since(old_time).unwrap();
-                //println!("Time Delta: {:?}", time_delta);
-                game.mouse_delta = (0.0, 0.0);
+                for (axis, value) in axis_filter.take() {
+                    game.axis_input(axis, value);
+                }
+                let now = Instant::now();
+                let time_delta = now.duration_since(last_frame);
+                last_frame = now;
+                match &mut input_replay {
+                    // replays run the recorded frame times, not the real ones
+                    Some(replay) => {
+                        if !replay.next_frame(&mut game) {
+                            println!("Input replay finished");
+                            input_replay = None;
+                        }
+                    }
+                    None => game.update(video.as_ref().map_or(time_delta, |v| v.frame_time())),
+                }
+                let update_time = now.elapsed();
+                // hand the camera back to the player once a camera path is over
+                if game.playback_finished() {
+                    game.playback = None;
+                }
+                // recording slows frames down, which shouldn't lower the quality of the video
+                if let (Some(dynamic_resolution), None) = (&mut dynamic_resolution, &video) {
+                    renderer.render_scale = dynamic_resolution.update(time_delta);
+                }
+                if let Some(changes) = hot_reload.due(now) {
+                    if let Some(level_reload) = &mut level_reload {
+                        if changes.affect_any(&level_reload.files) {
+                            println!("Reloading level {:?}", level_reload.path);
+                            level_reload.start();
+                        }
+                    }
+                    if changes.affect(&shader) {
+                        let result = gfx::load_shader(&display, &config.shader_dir, "shader")
+                            .map(|new_shader| shader = new_shader);
+                        hot_reload.report("shader", result);
+                    }
+                    for (name, result) in
+                        renderer.post.reload(&display, &config.shader_dir, &changes)
+                    {
+                        hot_reload.report(&name, result);
+                    }
+                }
+                // the new level replaces the old one under the camera, which stays where it is
+                if let Some(level_reload) = &mut level_reload {
+                    if let Some(result) = level_reload.poll() {
+                        level_reload.watch(&mut watcher);
+                        let result = result.map(|level| {
+                            for warning in level.warnings {
+                                report(&mut console, Ok(format!("Warning: {}", warning)));
+                            }
+                            game.world = level.space;
+                        });
+                        hot_reload.report("level", result);
+                    }
+                }
+
+                if let Some(line) = console_line.take() {
+                    let mut ctx = console::Context {
+                        game: &mut game,
+                        renderer: &mut renderer,
+                        requests: Vec::new(),
+                    };
+                    let output = console::commands().run(&mut ctx, &line);
+                    let requests = ctx.requests;
+                    console.print(output);
+
+                    for request in requests {
+                        match request {
+                            // watched and reloaded like the level given at startup
+                            console::Request::Load(path) => {
+                                let level_reload = level_reload.insert(
+                                    level_reload::LevelReload::new(&path, world::WORLD_DIM),
+                                );
+                                level_reload.start();
+                                level_reload.watch(&mut watcher);
+                            }
+                            console::Request::Screenshot { path, scale } => {
+                                let path = path.unwrap_or_else(screenshot_path);
+                                screenshot = Some((path, scale));
+                            }
+                            console::Request::Video(start) => {
+                                toggle_video = start != video.is_some();
+                            }
+                        }
+                    }
+                }
+
+                // large screenshots are rendered again offscreen, the others read back from the
+                // frame drawn below
+                let tiled = screenshot.take_if(|(_, scale)| *scale > 1);
+                if let Some((path, scale)) = tiled {
+                    let size = window.inner_size();
+                    let image = renderer.capture_tiled(
+                        &display,
+                        &vertex_buffer,
+                        &index_buffer,
+                        &shader.program,
+                        &game,
+                        (size.width, size.height),
+                        scale,
+                    );
+                    report(&mut console, save_screenshot(&image, &path));
+                }
+
+                // every frame of a video is rendered again offscreen, at the size the window was
+                // when recording started
+                if std::mem::take(&mut toggle_video) {
+                    match video.take() {
+                        Some(video) => report(&mut console, stop_video(video)),
+                        None => {
+                            let size = window.inner_size();
+                            let size = (size.width, size.height);
+                            match start_video(&config.video, size) {
+                                Ok((recorder, text)) => {
+                                    video = Some(recorder);
+                                    report(&mut console, Ok(text));
+                                }
+                                Err(err) => report(&mut console, Err(err)),
+                            }
+                        }
+                    }
+                }
+                if let Some(recorder) = &mut video {
+                    // frames are marched at full resolution, whatever scale the window is drawn at
+                    let render_scale = std::mem::replace(&mut renderer.render_scale, 1.0);
+                    let image = renderer.capture(
+                        &display,
+                        &vertex_buffer,
+                        &index_buffer,
+                        &shader.program,
+                        &game,
+                        recorder.size,
+                    );
+                    renderer.render_scale = render_scale;
+                    if let Err(err) = recorder.write(&image) {
+                        let err = console::CommandError::Failed(format!(
+                            "unable to record video, stopped after {} frames: {}",
+                            recorder.frames, err
+                        ));
+                        report(&mut console, Err(err));
+                        // whatever made it into the video is kept
+                        let _ = video.take().map(video::VideoRecorder::finish);
+                    }
+                }
+
+                // counting what the world is made of takes a scan of all of it, so it's only
+                // done every second
+                if debug_ui.open && world_stats.0.elapsed() >= Duration::from_secs(1) {
+                    world_stats = (now, debug_ui::WorldStats::count(&game));
+                }
+                panel = debug_ui.open.then(|| {
+                    let auto_scale = dynamic_resolution.is_some();
+                    debug_ui::build(
+                        &mut debug_ui,
+                        &mut game,
+                        &mut renderer,
+                        &world_stats.1,
+                        auto_scale,
+                    )
+                });
+
+                let gpu_time = renderer.gpu_timer.poll();
+                let stats = show_stats.then(|| frame_stats.overlay_text());
+                let (draw_time, image) = draw(
+                    &mut renderer,
+                    &shader,
+                    &game,
+                    &hot_reload,
+                    stats.as_deref(),
+                    panel.as_deref(),
+                    console.open.then(|| console.text()).as_deref(),
+                    screenshot.is_some(),
+                );
+                if let (Some((path, _)), Some(image)) = (screenshot.take(), image) {
+                    report(&mut console, save_screenshot(&image, &path));
+                }
+                console.frame();
+                let sample = stats::FrameSample {
+                    frame: time_delta,
+                    update: update_time,
+                    draw: draw_time,
+                    gpu: gpu_time,
+                };
+                frame_stats.push(sample);
+                if let Some(Err(err)) = frame_log.as_mut().map(|log| log.write(&sample)) {
+                    eprintln!(
+                        "Unable to write frame statistics, no longer logging: {}",
+                        err
+                    );
+                    frame_log = None;
+                }
                 *control_flow = glutin::event_loop::ControlFlow::Poll
             }
 
@@ -164,3 +692,72 @@ fn main() {
         };
     });
 }
+
+// current time, to name files after
+fn timestamp() -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}_{:03}", time.as_secs(), time.subsec_millis())
+}
+
+// where screenshots are saved when not given a file, named after the time they were taken
+fn screenshot_path() -> PathBuf {
+    PathBuf::from(format!("screenshot_{}.png", timestamp()))
+}
+
+fn save_screenshot(image: &image::Image, path: &Path) -> console::Output {
+    image
+        .save_png(path)
+        .map(|()| format!("saved screenshot to {:?}", path))
+        .map_err(|err| {
+            console::CommandError::Failed(format!(
+                "unable to save screenshot to {:?}: {}",
+                path, err
+            ))
+        })
+}
+
+// Starts recording a video with frames of the given size, into a new directory or through the
+// encoder set in the config, saying where it goes
+fn start_video(
+    config: &config::VideoConfig,
+    size: (u32, u32),
+) -> Result<(video::VideoRecorder, String), console::CommandError> {
+    let (recorder, target) = match &config.encoder {
+        Some(command) => (
+            video::VideoRecorder::to_encoder(command, config.fps, size),
+            format!("to {:?}", command),
+        ),
+        None => {
+            let dir = config.dir.join(format!("video_{}", timestamp()));
+            let target = format!("into {:?}", dir);
+            (video::VideoRecorder::to_dir(&dir, config.fps, size), target)
+        }
+    };
+    let recorder = recorder
+        .map_err(|err| console::CommandError::Failed(format!("unable to record video: {}", err)))?;
+    let text = format!(
+        "recording {}x{} video at {} fps {}",
+        size.0, size.1, config.fps, target
+    );
+    Ok((recorder, text))
+}
+
+fn stop_video(video: video::VideoRecorder) -> console::Output {
+    let fps = video.fps;
+    video
+        .finish()
+        .map(|frames| format!("recorded {} frames of video at {} fps", frames, fps))
+        .map_err(|err| console::CommandError::Failed(format!("unable to finish video: {}", err)))
+}
+
+// Shows what something the console asked for gave back, on the console and the terminal
+fn report(console: &mut console::Console, output: console::Output) {
+    match &output {
+        Ok(text) if !text.is_empty() => println!("{}", text),
+        Ok(_) => (),
+        Err(err) => eprintln!("{}", err),
+    }
+    console.print(output);
+}
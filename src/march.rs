@@ -1,5 +1,5 @@
 use crate::types::{Dimension3, GPUFormat, Idx3};
-use crate::world::{DenseGrid, Voxel};
+use crate::world::{DenseGrid, Region, Voxel};
 
 use std::ops::Index;
 
@@ -15,6 +15,14 @@ impl DenseBinaryCartesianSDF {
     pub fn zeros(shape: Dimension3) -> DenseBinaryCartesianSDF {
         DenseBinaryCartesianSDF(DenseGrid::fill(shape, 0))
     }
+
+    // Updates the field after the voxels in *region* changed. Each value only depends on its own
+    // voxel, so nothing outside the region needs updating.
+    pub fn update_region(&mut self, level: &DenseGrid<Voxel>, region: Region) {
+        for coord in region.iter() {
+            self.0[coord] = if level[coord].is_empty() { 1 } else { 0 };
+        }
+    }
 }
 
 impl Index<Idx3> for DenseBinaryCartesianSDF {
@@ -0,0 +1,306 @@
+use na::{vector, Vector3};
+
+use crate::world::Space;
+
+/********/
+/* Aabb */
+/********/
+
+// Axis-aligned bounding box in world coordinates, where each voxel is a 1x1x1 cube
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn translated(&self, delta: &Vector3<f32>) -> Aabb {
+        Aabb {
+            min: self.min + delta,
+            max: self.max + delta,
+        }
+    }
+
+    // whether the box overlaps any solid voxel (or the outside of the world, see `is_solid`)
+    pub fn intersects_solid(&self, space: &Space) -> bool {
+        // shrink by a hair so boxes resting exactly on a voxel face don't count as touching it
+        let lo = (self.min.add_scalar(SKIN)).map(|c| c.floor() as i64);
+        let hi = (self.max.add_scalar(-SKIN)).map(|c| c.floor() as i64);
+        for x in lo.x..=hi.x {
+            for y in lo.y..=hi.y {
+                for z in lo.z..=hi.z {
+                    if is_solid(space, x, y, z) {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+// gap kept between a body and the voxels it collides with
+const SKIN: f32 = 1e-3;
+
+// Whether the voxel at the given position blocks movement. The floor and sides of the world are
+// walls; the sky above it is open.
+pub fn is_solid(space: &Space, x: i64, y: i64, z: i64) -> bool {
+    let (sx, sy, sz) = *space.voxels.shape();
+    if y < 0 || x < 0 || z < 0 || x >= sx as i64 || z >= sz as i64 {
+        return true;
+    }
+    if y >= sy as i64 {
+        return false;
+    }
+    !space.voxels[(x as usize, y as usize, z as usize)].is_empty()
+}
+
+// Moves the box by *delta*, one axis at a time, stopping it flush against any solid voxel in the
+// way. Returns the distance actually moved and which axes were blocked.
+pub fn sweep(space: &Space, body: &Aabb, delta: Vector3<f32>) -> (Vector3<f32>, [bool; 3]) {
+    let mut moved = Vector3::zeros();
+    let mut blocked = [false; 3];
+
+    for axis in 0..3 {
+        // step at most half a voxel at a time so fast bodies can't tunnel through thin walls
+        let steps = (delta[axis].abs() / 0.5).ceil().max(1.0) as usize;
+        let step = delta[axis] / steps as f32;
+
+        for _ in 0..steps {
+            let mut offset = moved;
+            offset[axis] += step;
+            let candidate = body.translated(&offset);
+            if !candidate.intersects_solid(space) {
+                moved = offset;
+                continue;
+            }
+
+            // snap to just short of the face of the voxel we ran into
+            let current = body.translated(&moved);
+            moved[axis] += if step > 0.0 {
+                let face = (current.max[axis] + step).floor();
+                face - SKIN - current.max[axis]
+            } else {
+                let face = (current.min[axis] + step).floor() + 1.0;
+                face + SKIN - current.min[axis]
+            };
+            blocked[axis] = true;
+            break;
+        }
+    }
+
+    (moved, blocked)
+}
+
+/**********/
+/* Player */
+/**********/
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MovementMode {
+    // free flight through walls, no gravity
+    Fly,
+
+    // gravity, jumping and collision against the world
+    Walk,
+}
+
+pub struct PlayerController {
+    pub mode: MovementMode,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+}
+
+impl PlayerController {
+    // body dimensions, in voxels
+    pub const WIDTH: f32 = 0.6;
+    pub const HEIGHT: f32 = 1.8;
+    pub const EYE_HEIGHT: f32 = 1.6;
+
+    // voxels per second (squared)
+    pub const WALK_SPEED: f32 = 6.0;
+    pub const GROUND_ACCEL: f32 = 60.0;
+    pub const AIR_ACCEL: f32 = 15.0;
+    pub const FLY_SPEED: f32 = 20.0;
+    pub const FLY_ACCEL: f32 = 80.0;
+    pub const GRAVITY: f32 = 30.0;
+    pub const JUMP_SPEED: f32 = 9.0;
+    pub const MAX_FALL_SPEED: f32 = 50.0;
+
+    pub fn new(mode: MovementMode) -> PlayerController {
+        PlayerController {
+            mode,
+            velocity: Vector3::zeros(),
+            on_ground: false,
+        }
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            MovementMode::Fly => MovementMode::Walk,
+            MovementMode::Walk => MovementMode::Fly,
+        };
+        self.velocity = Vector3::zeros();
+        self.on_ground = false;
+    }
+
+    // body of a player whose eyes are at *eye*
+    pub fn body(eye: &Vector3<f32>) -> Aabb {
+        let half = PlayerController::WIDTH / 2.0;
+        let min = eye - vector![half, PlayerController::EYE_HEIGHT, half];
+        Aabb {
+            min,
+            max: min
+                + vector![
+                    PlayerController::WIDTH,
+                    PlayerController::HEIGHT,
+                    PlayerController::WIDTH
+                ],
+        }
+    }
+
+    // Advances free flight by *dt* seconds, ignoring the world. *wish_dir* is the direction the
+    // player wants to fly in, scaled by the fraction of full speed wanted.
+    pub fn fly(&mut self, eye: &mut Vector3<f32>, wish_dir: Vector3<f32>, dt: f32) {
+        self.velocity = approach(
+            self.velocity,
+            wish_dir * PlayerController::FLY_SPEED,
+            PlayerController::FLY_ACCEL * dt,
+        );
+        *eye += self.velocity * dt;
+    }
+
+    // Advances walking physics by *dt* seconds. *wish_dir* is the horizontal direction the player
+    // wants to walk in, scaled by the fraction of full speed wanted; *eye* is moved in place.
+    pub fn walk(
+        &mut self,
+        space: &Space,
+        eye: &mut Vector3<f32>,
+        wish_dir: Vector3<f32>,
+        jump: bool,
+        dt: f32,
+    ) {
+        let accel = if self.on_ground {
+            PlayerController::GROUND_ACCEL
+        } else {
+            PlayerController::AIR_ACCEL
+        };
+        let horizontal = approach(
+            vector![self.velocity.x, 0.0, self.velocity.z],
+            vector![wish_dir.x, 0.0, wish_dir.z] * PlayerController::WALK_SPEED,
+            accel * dt,
+        );
+        self.velocity.x = horizontal.x;
+        self.velocity.z = horizontal.z;
+
+        if jump && self.on_ground {
+            self.velocity.y = PlayerController::JUMP_SPEED;
+        }
+        self.velocity.y = (self.velocity.y - PlayerController::GRAVITY * dt)
+            .max(-PlayerController::MAX_FALL_SPEED);
+
+        let (moved, blocked) = sweep(space, &PlayerController::body(eye), self.velocity * dt);
+        *eye += moved;
+
+        self.on_ground = blocked[1] && self.velocity.y < 0.0;
+        for (axis, &hit) in blocked.iter().enumerate() {
+            if hit {
+                self.velocity[axis] = 0.0;
+            }
+        }
+    }
+}
+
+// moves *current* towards *target* by at most *max_delta*
+fn approach(current: Vector3<f32>, target: Vector3<f32>, max_delta: f32) -> Vector3<f32> {
+    let diff = target - current;
+    let dist = diff.norm();
+    if dist <= max_delta {
+        target
+    } else {
+        current + diff * (max_delta / dist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{DenseGrid, Voxel};
+
+    const DT: f32 = 1.0 / 60.0;
+
+    // 16x16x16 room with a stone floor at y = 0 and a wall at x = 10
+    fn room() -> Space {
+        let mut voxels = DenseGrid::fill((16, 16, 16), Voxel::Empty);
+        for x in 0..16 {
+            for z in 0..16 {
+                voxels[(x, 0, z)] = Voxel::Stone;
+                for y in 0..16 {
+                    voxels[(10, y, z)] = Voxel::Stone;
+                }
+            }
+        }
+        Space::from_voxels(voxels)
+    }
+
+    #[test]
+    fn falls_and_lands_on_floor() {
+        let space = room();
+        let mut player = PlayerController::new(MovementMode::Walk);
+        let mut eye = vector![5.5, 8.0, 5.5];
+
+        for _ in 0..120 {
+            player.walk(&space, &mut eye, Vector3::zeros(), false, DT);
+        }
+
+        assert!(player.on_ground);
+        let feet = PlayerController::body(&eye).min.y;
+        assert!((feet - 1.0).abs() < 0.01, "feet at {}", feet);
+    }
+
+    #[test]
+    fn wall_stops_walking() {
+        let space = room();
+        let mut player = PlayerController::new(MovementMode::Walk);
+        let mut eye = vector![5.5, 1.0 + PlayerController::EYE_HEIGHT, 5.5];
+
+        for _ in 0..120 {
+            player.walk(&space, &mut eye, vector![1.0, 0.0, 0.0], false, DT);
+        }
+
+        let body = PlayerController::body(&eye);
+        assert!(
+            body.max.x <= 10.0 && body.max.x > 9.9,
+            "stopped at {}",
+            body.max.x
+        );
+        assert!(!body.intersects_solid(&space));
+    }
+
+    #[test]
+    fn jumps_only_from_ground() {
+        let space = room();
+        let mut player = PlayerController::new(MovementMode::Walk);
+        let mut eye = vector![5.5, 1.0 + PlayerController::EYE_HEIGHT, 5.5];
+
+        // settle onto the floor, then jump
+        player.walk(&space, &mut eye, Vector3::zeros(), false, DT);
+        assert!(player.on_ground);
+        player.walk(&space, &mut eye, Vector3::zeros(), true, DT);
+        assert!(player.velocity.y > 0.0);
+
+        // jumping again mid-air does nothing
+        let vy = player.velocity.y;
+        player.walk(&space, &mut eye, Vector3::zeros(), true, DT);
+        assert!(player.velocity.y < vy);
+    }
+
+    #[test]
+    fn fast_bodies_do_not_tunnel() {
+        let space = room();
+        let body = PlayerController::body(&vector![5.5, 2.6, 5.5]);
+        let (moved, blocked) = sweep(&space, &body, vector![20.0, 0.0, 0.0]);
+        assert!(blocked[0]);
+        assert!(body.translated(&moved).max.x <= 10.0);
+    }
+}
@@ -0,0 +1,280 @@
+use glium::draw_parameters::TimeElapsedQuery;
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::{MipmapsOption, Texture2d, UncompressedFloatFormat};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler};
+use glium::{DrawParameters, Surface};
+
+use crate::gfx::{self, attrib, ShaderProgram};
+use crate::hot_reload::Changes;
+use crate::preprocess::ShaderError;
+
+use std::path::{Path, PathBuf};
+
+// post pass shaders live in this subdirectory of the shader directory, as <name>.frag
+pub const POST_DIR_NAME: &str = "post";
+
+// default post passes, in the order they run
+pub const DEFAULT_PASSES: [&str; 5] = ["bloom", "tonemap", "fxaa", "vignette", "gamma"];
+
+// upscales the marched image to the size of the window when marching at a lower resolution; lives
+// in the shader directory itself as it always runs first, whichever passes are configured
+pub const UPSCALE_SHADER_NAME: &str = "upscale";
+
+pub fn pass_path(shader_dir: &Path, name: &str) -> PathBuf {
+    shader_dir
+        .join(POST_DIR_NAME)
+        .join(format!("{}.frag", name))
+}
+
+/***********/
+/* Targets */
+/***********/
+
+// Offscreen images the marching pass renders into, at the size it marches at
+pub struct Targets {
+    // size of the final image, and of the marched images, which is smaller at a render scale
+    // below 1
+    pub size: (u32, u32),
+    pub march_size: (u32, u32),
+
+    // linear HDR color
+    pub color: Texture2d,
+
+    // surface normal in xyz and distance along the ray in w, which is negative where nothing was
+    // hit
+    pub normal_depth: Texture2d,
+
+    // id of the voxel hit, 0 for none
+    pub material: Texture2d,
+
+    // color upscaled to the final size, when marching at a smaller one
+    upscaled: Option<Texture2d>,
+
+    // intermediate images post passes read from and write to in turn
+    ping_pong: [Texture2d; 2],
+}
+
+impl Targets {
+    fn new(
+        facade: &dyn glium::backend::Facade,
+        size: (u32, u32),
+        march_size: (u32, u32),
+    ) -> Targets {
+        let texture = |format, (w, h)| {
+            Texture2d::empty_with_format(facade, format, MipmapsOption::NoMipmap, w, h)
+                .expect("Unable to create render target")
+        };
+        let hdr = UncompressedFloatFormat::F16F16F16F16;
+        Targets {
+            size,
+            march_size,
+            color: texture(hdr, march_size),
+            normal_depth: texture(UncompressedFloatFormat::F32F32F32F32, march_size),
+            material: texture(UncompressedFloatFormat::F32, march_size),
+            upscaled: (march_size != size).then(|| texture(hdr, size)),
+            ping_pong: [texture(hdr, size), texture(hdr, size)],
+        }
+    }
+}
+
+fn sampled(texture: &Texture2d) -> Sampler<'_, Texture2d> {
+    texture
+        .sampled()
+        .magnify_filter(MagnifySamplerFilter::Linear)
+        .minify_filter(MinifySamplerFilter::Linear)
+}
+
+/*****************/
+/* Post pipeline */
+/*****************/
+
+// A full screen pass reading the previous pass's image (or the marched image, for the first one)
+pub struct PostPass {
+    pub name: String,
+    pub enabled: bool,
+    shader: ShaderProgram,
+}
+
+// Runs the post passes over the marched image, ping-ponging between two intermediate images, with
+// the last enabled pass drawing into the final target
+pub struct PostPipeline {
+    pub passes: Vec<PostPass>,
+
+    // upscale to blocky pixels rather than smoothly
+    pub pixelated: bool,
+
+    // show the (upscaled) marched image as it is, without running any passes
+    pub skip_passes: bool,
+
+    upscale: ShaderProgram,
+    targets: Option<Targets>,
+}
+
+impl PostPipeline {
+    pub fn new(
+        display: &glium::Display,
+        shader_dir: &Path,
+        names: &[String],
+    ) -> Result<PostPipeline, ShaderError> {
+        let passes = names
+            .iter()
+            .map(|name| {
+                Ok(PostPass {
+                    name: name.clone(),
+                    enabled: true,
+                    shader: load_pass(display, shader_dir, &pass_path(shader_dir, name))?,
+                })
+            })
+            .collect::<Result<Vec<PostPass>, ShaderError>>()?;
+        Ok(PostPipeline {
+            passes,
+            pixelated: false,
+            skip_passes: false,
+            upscale: load_pass(display, shader_dir, &upscale_path(shader_dir))?,
+            targets: None,
+        })
+    }
+
+    // Reloads the passes (and the upscale shader) affected by *changes*, keeping the old program
+    // of any that fail to compile. Gives the name of each program reloaded with how it went.
+    pub fn reload(
+        &mut self,
+        display: &glium::Display,
+        shader_dir: &Path,
+        changes: &Changes,
+    ) -> Vec<(String, Result<(), ShaderError>)> {
+        let mut results = Vec::new();
+        if changes.affect(&self.upscale) {
+            let result = load_pass(display, shader_dir, &upscale_path(shader_dir))
+                .map(|shader| self.upscale = shader);
+            results.push((UPSCALE_SHADER_NAME.to_string(), result));
+        }
+        for pass in &mut self.passes {
+            if changes.affect(&pass.shader) {
+                let result = load_pass(display, shader_dir, &pass_path(shader_dir, &pass.name))
+                    .map(|shader| pass.shader = shader);
+                results.push((format!("{}/{}", POST_DIR_NAME, pass.name), result));
+            }
+        }
+        results
+    }
+
+    // Render targets for a final image of *size* marched at *march_size*, reused from the last
+    // frame when neither has changed
+    pub fn targets(
+        &mut self,
+        facade: &dyn glium::backend::Facade,
+        size: (u32, u32),
+        march_size: (u32, u32),
+    ) -> &Targets {
+        if self.targets.as_ref().map(|t| (t.size, t.march_size)) != Some((size, march_size)) {
+            self.targets = Some(Targets::new(facade, size, march_size));
+        }
+        self.targets.as_ref().unwrap()
+    }
+
+    // Upscales the marched image if needed, then runs the enabled passes over it, drawing the
+    // result into *target*. Every draw call adds the time the GPU spends on it to *gpu_timer*, if
+    // given.
+    pub fn run<S: Surface>(
+        &mut self,
+        facade: &dyn glium::backend::Facade,
+        target: &mut S,
+        vertex_buffer: &glium::VertexBuffer<attrib::Vertex>,
+        index_buffer: &glium::IndexBuffer<u16>,
+        time: f32,
+        gpu_timer: Option<&TimeElapsedQuery>,
+    ) {
+        let targets = self
+            .targets
+            .as_ref()
+            .expect("post passes run before marching");
+        let enabled: Vec<&PostPass> = self
+            .passes
+            .iter()
+            .filter(|p| p.enabled && !self.skip_passes)
+            .collect();
+        let params = DrawParameters {
+            time_elapsed_query: gpu_timer,
+            ..Default::default()
+        };
+
+        let mut input = &targets.color;
+        if let Some(upscaled) = &targets.upscaled {
+            let uniforms = uniform! {
+                color: targets.color.sampled(),
+                normal_depth: targets.normal_depth.sampled(),
+                pixelated: self.pixelated,
+            };
+            SimpleFrameBuffer::new(facade, upscaled)
+                .unwrap()
+                .draw(
+                    vertex_buffer,
+                    index_buffer,
+                    &self.upscale.program,
+                    &uniforms,
+                    &params,
+                )
+                .unwrap();
+            input = upscaled;
+        }
+
+        if enabled.is_empty() {
+            input
+                .as_surface()
+                .fill(target, MagnifySamplerFilter::Nearest);
+            return;
+        }
+
+        let resolution = (targets.size.0 as f32, targets.size.1 as f32);
+        for (i, pass) in enabled.iter().enumerate() {
+            let uniforms = uniform! {
+                color: sampled(input),
+                normal_depth: targets.normal_depth.sampled(),
+                material: targets.material.sampled(),
+                resolution: resolution,
+                time: time,
+            };
+
+            if i + 1 == enabled.len() {
+                target
+                    .draw(
+                        vertex_buffer,
+                        index_buffer,
+                        &pass.shader.program,
+                        &uniforms,
+                        &params,
+                    )
+                    .unwrap();
+            } else {
+                let output = &targets.ping_pong[i % 2];
+                SimpleFrameBuffer::new(facade, output)
+                    .unwrap()
+                    .draw(
+                        vertex_buffer,
+                        index_buffer,
+                        &pass.shader.program,
+                        &uniforms,
+                        &params,
+                    )
+                    .unwrap();
+                input = output;
+            }
+        }
+    }
+}
+
+fn upscale_path(shader_dir: &Path) -> PathBuf {
+    shader_dir.join(format!("{}.frag", UPSCALE_SHADER_NAME))
+}
+
+// Compiles the fragment shader at *frag_path* into a full screen pass, sharing the vertex shader
+// of the marching pass
+fn load_pass(
+    display: &glium::Display,
+    shader_dir: &Path,
+    frag_path: &Path,
+) -> Result<ShaderProgram, ShaderError> {
+    // the passes do their own gamma correction, so the driver must not convert to sRGB again
+    gfx::load_program(display, &shader_dir.join("shader.vert"), frag_path, true)
+}
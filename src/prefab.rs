@@ -0,0 +1,396 @@
+use crate::brush::position;
+use crate::console::{Args, CommandError, Commands};
+use crate::game::Game;
+use crate::types::{Dimension3, Idx3};
+use crate::world::{DenseGrid, Region, Space, Voxel};
+use crate::world_loader::{Level, LevelLoader, LevelLoaders, LoadError};
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/****************/
+/* Prefab files */
+/****************/
+
+// A prefab file is a box of voxels to stamp into levels: "PFAB", a u32 format version and the
+// u32 size of the box along x, y and z, followed by the id of every voxel in x, then y, then z
+// order. Numbers are little endian.
+const MAGIC: &[u8; 4] = b"PFAB";
+const VERSION: u32 = 1;
+
+pub fn write_prefab(voxels: &DenseGrid<Voxel>, w: &mut dyn Write) -> io::Result<()> {
+    let (sx, sy, sz) = *voxels.shape();
+    w.write_all(MAGIC)?;
+    for n in &[VERSION, sx as u32, sy as u32, sz as u32] {
+        w.write_all(&n.to_le_bytes())?;
+    }
+    let ids: Vec<u8> = voxels.iter().map(Voxel::id).collect();
+    w.write_all(&ids)
+}
+
+pub fn read_prefab(data: &[u8]) -> Result<DenseGrid<Voxel>, LoadError> {
+    let invalid = |msg: &str| LoadError::Parse(format!("prefab: {}", msg));
+    if data.get(..4) != Some(&MAGIC[..]) {
+        return Err(invalid("not a prefab file"));
+    }
+    let number = |i: usize| -> Result<usize, LoadError> {
+        let bytes = data
+            .get(4 + 4 * i..8 + 4 * i)
+            .ok_or_else(|| invalid("truncated"))?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+    if number(0)? != VERSION as usize {
+        return Err(invalid("unsupported version"));
+    }
+    let shape = (number(1)?, number(2)?, number(3)?);
+    let ids = &data[20..];
+    if shape.0 == 0 || shape.1 == 0 || shape.2 == 0 {
+        return Err(invalid("empty"));
+    }
+    // the size comes from the file, so the product can be anything
+    let len = shape
+        .0
+        .checked_mul(shape.1)
+        .and_then(|n| n.checked_mul(shape.2))
+        .ok_or_else(|| invalid("too large"))?;
+    if ids.len() != len {
+        return Err(invalid("wrong number of voxels"));
+    }
+    let voxels: Option<Vec<Voxel>> = ids.iter().map(|&id| Voxel::from_id(id)).collect();
+    let voxels = voxels.ok_or_else(|| invalid("unknown voxel id"))?;
+
+    let (sx, sy, _) = shape;
+    Ok(DenseGrid::from_fn(shape, |(x, y, z)| {
+        voxels[x + sx * (y + sy * z)]
+    }))
+}
+
+// Loads prefab files as levels, with the prefab standing in the middle of the floor
+pub struct PrefabLoader;
+
+impl LevelLoader for PrefabLoader {
+    fn extensions(&self) -> &[&str] {
+        &["prefab"]
+    }
+
+    fn load(&self, path: &Path, shape: Dimension3) -> Result<Level, LoadError> {
+        let prefab = self.load_prefab(path, shape)?;
+        let (px, py, pz) = *prefab.shape();
+        if px > shape.0 || py > shape.1 || pz > shape.2 {
+            return Err(LoadError::Parse(format!(
+                "prefab: {}x{}x{} does not fit in a {}x{}x{} level",
+                px, py, pz, shape.0, shape.1, shape.2
+            )));
+        }
+
+        let (ax, az) = ((shape.0 - px) / 2, (shape.2 - pz) / 2);
+        let inside =
+            |(x, y, z): Idx3| (ax..ax + px).contains(&x) && y < py && (az..az + pz).contains(&z);
+        let voxels = DenseGrid::from_fn(shape, |(x, y, z)| {
+            if inside((x, y, z)) {
+                prefab[(x - ax, y, z - az)]
+            } else {
+                Voxel::Empty
+            }
+        });
+        Ok(Space::from_voxels(voxels).into())
+    }
+
+    // prefabs have a size of their own, so they are stamped whole, empty voxels and all
+    fn load_prefab(&self, path: &Path, _: Dimension3) -> Result<DenseGrid<Voxel>, LoadError> {
+        read_prefab(&fs::read(path)?)
+    }
+}
+
+/*************/
+/* Clipboard */
+/*************/
+
+// A region of the world picked with `select`, and voxels copied from it to paste elsewhere
+#[derive(Default)]
+pub struct Clipboard {
+    pub selection: Option<Region>,
+    pub voxels: Option<DenseGrid<Voxel>>,
+}
+
+fn size((x, y, z): Dimension3) -> String {
+    format!("{}x{}x{}", x, y, z)
+}
+
+// Console commands copying and pasting voxels, and saving and stamping prefabs
+pub fn commands() -> Commands<Game> {
+    fn selection(game: &Game) -> Result<Region, CommandError> {
+        game.clipboard
+            .selection
+            .ok_or_else(|| CommandError::Failed("nothing is selected".to_string()))
+    }
+
+    fn nothing_copied() -> CommandError {
+        CommandError::Failed("nothing has been copied".to_string())
+    }
+
+    fn axis(args: &mut Args) -> Result<usize, CommandError> {
+        args.next_with("axis", |word| {
+            ["x", "y", "z"].iter().position(|a| *a == word)
+        })
+    }
+
+    // whether only filled voxels are pasted, leaving what is already there in the empty ones
+    fn solid_only(args: &mut Args) -> Result<bool, CommandError> {
+        let mode: Option<String> = args.optional("solid")?;
+        match mode.as_deref() {
+            None => Ok(false),
+            Some("solid") => Ok(true),
+            Some(word) => Err(CommandError::Usage(format!("unexpected {:?}", word))),
+        }
+    }
+
+    // number of voxels changed by pasting
+    fn paste(space: &mut Space, voxels: &DenseGrid<Voxel>, at: Idx3, solid: bool) -> usize {
+        space
+            .paste(voxels, at, solid)
+            .map_or(0, |edit| edit.changes.len())
+    }
+
+    let mut commands: Commands<Game> = Commands::default();
+    commands.add(
+        "select",
+        "<x0> <y0> <z0> <x1> <y1> <z1>",
+        "select the box between two corners, both included, to copy or save as a prefab",
+        |game, args| {
+            let a = position(&game.world, args, "corner")?;
+            let b = position(&game.world, args, "corner")?;
+            args.end()?;
+            let region = Region::new(a, b);
+            game.clipboard.selection = Some(region);
+            Ok(format!("selected {} voxels", size(region.shape())))
+        },
+    );
+    commands.add(
+        "copy",
+        "",
+        "copy the selected voxels to the clipboard",
+        |game, args| {
+            args.end()?;
+            let region = selection(game)?;
+            game.clipboard.voxels = Some(game.world.voxels.copy_region(region));
+            Ok(format!("copied {} voxels", size(region.shape())))
+        },
+    );
+    commands.add(
+        "rotate",
+        "<x|y|z> [turns]",
+        "turn the clipboard a quarter turn around an axis, or as many as given",
+        |game, args| {
+            let axis = axis(args)?;
+            let turns: i32 = args.optional("turns")?.unwrap_or(1);
+            args.end()?;
+            let voxels = game.clipboard.voxels.as_mut().ok_or_else(nothing_copied)?;
+            *voxels = voxels.rotated(axis, turns);
+            Ok(format!(
+                "rotated the clipboard to {}",
+                size(*voxels.shape())
+            ))
+        },
+    );
+    commands.add(
+        "mirror",
+        "<x|y|z>",
+        "flip the clipboard along an axis",
+        |game, args| {
+            let axis = axis(args)?;
+            args.end()?;
+            let voxels = game.clipboard.voxels.as_mut().ok_or_else(nothing_copied)?;
+            *voxels = voxels.mirrored(axis);
+            Ok("mirrored the clipboard".to_string())
+        },
+    );
+    commands.add(
+        "paste",
+        "<x> <y> <z> [solid]",
+        "paste the clipboard from its first corner, leaving out its empty voxels if solid",
+        |game, args| {
+            let at = position(&game.world, args, "position")?;
+            let solid = solid_only(args)?;
+            args.end()?;
+            let voxels = game.clipboard.voxels.as_ref().ok_or_else(nothing_copied)?;
+            let changed = paste(&mut game.world, voxels, at, solid);
+            Ok(format!("changed {} voxels", changed))
+        },
+    );
+    commands.add(
+        "save_prefab",
+        "<file.prefab>",
+        "save the selected voxels as a prefab, to stamp into any level",
+        |game, args| {
+            let path: PathBuf = args.next("file")?;
+            args.end()?;
+            let region = selection(game)?;
+            let failed = |err: io::Error| CommandError::Failed(err.to_string());
+            let mut file = BufWriter::new(File::create(&path).map_err(failed)?);
+            write_prefab(&game.world.voxels.copy_region(region), &mut file).map_err(failed)?;
+            file.flush().map_err(failed)?;
+            Ok(format!(
+                "saved {} voxels to {:?}",
+                size(region.shape()),
+                path
+            ))
+        },
+    );
+    commands.add(
+        "stamp",
+        "<file> <x> <y> <z> [solid]",
+        "paste a prefab, or the filled part of a level in any format a loader is registered for",
+        |game, args| {
+            let path: PathBuf = args.next("file")?;
+            let at = position(&game.world, args, "position")?;
+            let solid = solid_only(args)?;
+            args.end()?;
+            let shape = *game.world.voxels.shape();
+            let prefab = LevelLoaders::default()
+                .load_prefab(&path, shape)
+                .map_err(|err| CommandError::Failed(err.to_string()))?;
+            let changed = paste(&mut game.world, &prefab, at, solid);
+            Ok(format!("changed {} voxels stamping {:?}", changed, path))
+        },
+    );
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    // a 3x2x1 wall: stone along the bottom, with wood on the left end above it
+    fn wall() -> DenseGrid<Voxel> {
+        DenseGrid::from_fn((3, 2, 1), |(x, y, _)| match (x, y) {
+            (_, 0) => Voxel::Stone,
+            (0, 1) => Voxel::Wood,
+            _ => Voxel::Empty,
+        })
+    }
+
+    fn same(a: &DenseGrid<Voxel>, b: &DenseGrid<Voxel>) -> bool {
+        a.shape() == b.shape() && a.grid() == b.grid()
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let mut data = Vec::new();
+        write_prefab(&wall(), &mut data).unwrap();
+        assert_eq!(data.len(), 20 + 6);
+        assert!(same(&read_prefab(&data).unwrap(), &wall()));
+
+        assert!(read_prefab(b"PFAX").is_err());
+        assert!(read_prefab(&data[..12]).is_err());
+        assert!(read_prefab(&data[..25]).is_err());
+        data[20] = 200;
+        assert!(read_prefab(&data).is_err());
+
+        // a size whose voxel count overflows is rejected rather than wrapping around
+        let mut huge = data[..20].to_vec();
+        for n in 1..4 {
+            huge[4 + 4 * n..8 + 4 * n].copy_from_slice(&u32::MAX.to_le_bytes());
+        }
+        huge.extend_from_slice(&[0; 8]);
+        assert!(read_prefab(&huge).is_err());
+    }
+
+    #[test]
+    fn loads_prefabs_through_the_loaders() {
+        let dir = temp_dir("prefab_loaders");
+        let path = dir.join("wall.prefab");
+        write_prefab(&wall(), &mut File::create(&path).unwrap()).unwrap();
+        let loaders = LevelLoaders::default();
+
+        // as a level it stands in the middle of the floor
+        let level = loaders.load(&path, (7, 4, 5)).unwrap().space;
+        assert_eq!(level.voxels[(2, 1, 2)], Voxel::Wood);
+        assert_eq!(level.voxels[(4, 0, 2)], Voxel::Stone);
+        assert_eq!(level.voxels.iter().filter(|v| !v.is_empty()).count(), 4);
+        assert!(loaders.load(&path, (2, 4, 5)).is_err());
+
+        // as a prefab it keeps its empty voxels, while other formats are cut down to what is filled
+        assert!(same(
+            &loaders.load_prefab(&path, (7, 4, 5)).unwrap(),
+            &wall()
+        ));
+        let gox = dir.join("post.gox");
+        fs::write(&gox, "# two voxels\n3 1 2 ff0000\n3 2 2 ff0000\n").unwrap();
+        let post = loaders.load_prefab(&gox, (8, 8, 8)).unwrap();
+        assert_eq!(*post.shape(), (1, 2, 1));
+        assert!(post.iter().all(|v| *v == Voxel::Red));
+    }
+
+    #[test]
+    fn copies_and_pastes_from_the_console() {
+        let commands = commands();
+        let mut space = Space::from_voxels(DenseGrid::fill((8, 8, 8), Voxel::Empty));
+        space.paste(&wall(), (1, 1, 1), false);
+        let mut game = Game::with_world(space);
+        let mut run = |line: &str| commands.run(&mut game, line);
+
+        assert!(run("copy").is_err());
+        assert_eq!(run("select 3 2 1 1 1 1").unwrap(), "selected 3x2x1 voxels");
+        assert!(run("rotate y").is_err());
+        run("copy").unwrap();
+        assert_eq!(run("rotate y").unwrap(), "rotated the clipboard to 1x2x3");
+        run("mirror z").unwrap();
+        assert_eq!(run("paste 6 1 4 solid").unwrap(), "changed 4 voxels");
+        assert!(run("paste 6 1 4 maybe").is_err());
+
+        let dir = temp_dir("prefab_console");
+        let path = dir.join("wall.prefab");
+        run(&format!("save_prefab {}", path.display())).unwrap();
+        let stamp = format!("stamp {} 0 5 0", path.display());
+        assert_eq!(
+            run(&stamp).unwrap(),
+            format!("changed 4 voxels stamping {:?}", path)
+        );
+        assert!(run("stamp missing.prefab 0 0 0").is_err());
+
+        let world = &mut game.world;
+        // a quarter turn takes the wall from along x to along -z, then mirroring it puts it back
+        // along +z, with the wood at the first corner
+        assert_eq!(world.voxels[(6, 2, 4)], Voxel::Wood);
+        assert_eq!(world.voxels[(6, 1, 6)], Voxel::Stone);
+        assert_eq!(world.voxels[(0, 6, 0)], Voxel::Wood);
+        assert_eq!(world.sdf[(6, 1, 5)], 0);
+
+        // every paste is undone on its own
+        assert!(world.undo().is_some());
+        assert!(world.undo().is_some());
+        assert_eq!(world.voxels[(6, 1, 6)], Voxel::Empty);
+        assert_eq!(world.voxels[(1, 2, 1)], Voxel::Wood);
+    }
+
+    #[test]
+    fn stamping_a_bad_file_fails() {
+        let commands = commands();
+        let mut game =
+            Game::with_world(Space::from_voxels(DenseGrid::fill((8, 8, 8), Voxel::Empty)));
+        let dir = temp_dir("prefab_stamp");
+
+        let gox = dir.join("typo.gox");
+        fs::write(&gox, "1 1 1 ff0000\n1 1 ff0000\n").unwrap();
+        let hills = dir.join("hills.pgm");
+        fs::write(&hills, "P2 1 1 255 255").unwrap();
+        fs::write(dir.join("hills_color.ppm"), "P3 0 0 255").unwrap();
+        let prefab = dir.join("short.prefab");
+        let mut data = Vec::new();
+        write_prefab(&wall(), &mut data).unwrap();
+        fs::write(&prefab, &data[..data.len() - 1]).unwrap();
+
+        for path in [gox, hills, prefab] {
+            let stamp = format!("stamp {} 0 0 0", path.display());
+            assert!(matches!(
+                commands.run(&mut game, &stamp),
+                Err(CommandError::Failed(_))
+            ));
+        }
+        assert!(game.world.voxels.iter().all(|v| v.is_empty()));
+        assert!(game.world.undo().is_none());
+    }
+}
@@ -0,0 +1,297 @@
+use derive_more::Display;
+use glium::program::ShaderType;
+use glium::ProgramCreationError;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Display)]
+pub enum ShaderError {
+    #[display(fmt = "unable to read {:?}: {}", _0, _1)]
+    Io(PathBuf, io::Error),
+
+    #[display(fmt = "{}:{}: {}", "_0.display()", _1, _2)]
+    Include(PathBuf, usize, String),
+
+    // compiler or linker output, with line numbers mapped back to the original files
+    #[display(fmt = "{}", _0)]
+    Compile(String),
+}
+
+// Where a line of preprocessed source came from
+#[derive(Clone, PartialEq, Debug, Display)]
+pub enum Origin {
+    // file and line number, from 1
+    #[display(fmt = "{}:{}", "_0.display()", _1)]
+    File(PathBuf, usize),
+
+    // a `#define` passed in from Rust
+    #[display(fmt = "<defines>")]
+    Define,
+}
+
+/**********/
+/* Source */
+/**********/
+
+// A shader with its includes expanded, ready for the compiler
+#[derive(Default, Debug)]
+pub struct Source {
+    pub text: String,
+
+    // origin of each line of `text`
+    pub lines: Vec<Origin>,
+
+    // canonical paths of every file read, so the shader can be reloaded when any of them changes
+    pub files: Vec<PathBuf>,
+}
+
+impl Source {
+    // Reads the shader at *path*, replacing `#include "file"` lines (relative to the file they
+    // are in) with the contents of that file, and adding `#define NAME VALUE` for each of
+    // *defines* right after the `#version` line. Each file is included at most once, like with
+    // `#pragma once` in C, so shared helpers can be included from anywhere.
+    pub fn load(path: &Path, defines: &[(String, String)]) -> Result<Source, ShaderError> {
+        let mut source = Source::default();
+        source.include(path, defines)?;
+        Ok(source)
+    }
+
+    fn include(&mut self, path: &Path, defines: &[(String, String)]) -> Result<(), ShaderError> {
+        let src = fs::read_to_string(path).map_err(|err| ShaderError::Io(path.into(), err))?;
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.into());
+        if self.files.contains(&canonical) {
+            return Ok(());
+        }
+        self.files.push(canonical);
+
+        // defines have to come after `#version`, which has to come first
+        let has_version = src.lines().any(|l| l.trim_start().starts_with("#version"));
+        if !has_version {
+            self.push_defines(defines);
+        }
+
+        for (i, line) in src.lines().enumerate() {
+            let trimmed = line.trim_start();
+            if let Some(name) = trimmed.strip_prefix("#include") {
+                let name = name.trim();
+                let name = name
+                    .strip_prefix('"')
+                    .and_then(|n| n.strip_suffix('"'))
+                    .ok_or_else(|| {
+                        ShaderError::Include(
+                            path.into(),
+                            i + 1,
+                            format!("expected #include \"file\", got {:?}", trimmed),
+                        )
+                    })?;
+                let included = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+                if !included.is_file() {
+                    return Err(ShaderError::Include(
+                        path.into(),
+                        i + 1,
+                        format!("no such file {:?}", included),
+                    ));
+                }
+                self.include(&included, &[])?;
+                continue;
+            }
+
+            self.push(line, Origin::File(path.into(), i + 1));
+            if trimmed.starts_with("#version") {
+                self.push_defines(defines);
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, line: &str, origin: Origin) {
+        self.text.push_str(line);
+        self.text.push('\n');
+        self.lines.push(origin);
+    }
+
+    fn push_defines(&mut self, defines: &[(String, String)]) {
+        for (name, value) in defines {
+            self.push(&format!("#define {} {}", name, value), Origin::Define);
+        }
+    }
+
+    // Rewrites the line numbers in a compiler log to the files and lines they came from. Drivers
+    // disagree on the format, so this understands the common ones: `0:12(5):` (Mesa),
+    // `0(12) :` (Nvidia) and `0:12:` (AMD and Intel).
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines()
+            .map(|line| self.map_log_line(line))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn map_log_line(&self, line: &str) -> String {
+        for (start, _) in line.match_indices('0') {
+            if line[..start].ends_with(|c: char| c.is_ascii_alphanumeric()) {
+                continue;
+            }
+            let rest = &line[start + 1..];
+            let close = match rest.chars().next() {
+                Some(':') => None,
+                Some('(') => Some(')'),
+                _ => continue,
+            };
+            let digits = rest[1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(&rest[1..], |len| &rest[1..1 + len]);
+            let after = &rest[1 + digits.len()..];
+
+            // end of the line reference, which the closing parenthesis is part of
+            let end = start + 2 + digits.len();
+            let end = match close {
+                Some(c) if after.starts_with(c) => end + 1,
+                None if after.starts_with([':', '(']) => end,
+                _ => continue,
+            };
+            let origin = digits
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| self.lines.get(i));
+            if let Some(origin) = origin {
+                return format!("{}{}{}", &line[..start], origin, &line[end..]);
+            }
+        }
+        line.to_string()
+    }
+}
+
+// Whether *path* is one of *files*, as listed in `Source::files`
+pub fn depends_on(files: &[PathBuf], path: &Path) -> bool {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.into());
+    files.contains(&path)
+}
+
+// Turns a failure to build a program out of *vert* and *frag* into an error pointing at the
+// original files
+pub fn compile_error(err: ProgramCreationError, vert: &Source, frag: &Source) -> ShaderError {
+    let (stage, log) = match &err {
+        ProgramCreationError::CompilationError(log, ShaderType::Vertex) => {
+            ("vertex shader", vert.map_log(log))
+        }
+        ProgramCreationError::CompilationError(log, ShaderType::Fragment) => {
+            ("fragment shader", frag.map_log(log))
+        }
+        _ => return ShaderError::Compile(err.to_string()),
+    };
+    ShaderError::Compile(format!("compilation error in {}:\n{}", stage, log))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    // writes *files* into a fresh directory, returning it
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = temp_dir(&format!("preprocess_{}", test));
+        for (name, src) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, src).unwrap();
+        }
+        dir
+    }
+
+    fn defines() -> Vec<(String, String)> {
+        vec![("VOX_STONE".to_string(), "4".to_string())]
+    }
+
+    #[test]
+    fn expands_includes_once() {
+        let dir = write_files(
+            "includes",
+            &[
+                (
+                    "main.frag",
+                    "#version 450\n#include \"lib/a.glsl\"\n#include \"lib/b.glsl\"\nvoid main() {}\n",
+                ),
+                ("lib/a.glsl", "#include \"b.glsl\"\nfloat a;\n"),
+                ("lib/b.glsl", "float b;\n"),
+            ],
+        );
+        let source = Source::load(&dir.join("main.frag"), &defines()).unwrap();
+
+        assert_eq!(
+            source.text,
+            "#version 450\n#define VOX_STONE 4\nfloat b;\nfloat a;\nvoid main() {}\n"
+        );
+        assert_eq!(source.lines[1], Origin::Define);
+        assert_eq!(source.lines[2], Origin::File(dir.join("lib/b.glsl"), 1));
+        assert_eq!(source.lines[4], Origin::File(dir.join("main.frag"), 4));
+        assert_eq!(source.files.len(), 3);
+        assert!(depends_on(&source.files, &dir.join("lib/../lib/b.glsl")));
+    }
+
+    #[test]
+    fn reports_bad_includes_where_they_are() {
+        let dir = write_files(
+            "bad_includes",
+            &[("main.frag", "#version 450\n\n#include \"missing.glsl\"\n")],
+        );
+        match Source::load(&dir.join("main.frag"), &[]) {
+            Err(ShaderError::Include(path, 3, _)) => assert_eq!(path, dir.join("main.frag")),
+            other => panic!("expected an include error, got {:?}", other),
+        }
+
+        let dir = write_files("unquoted", &[("main.frag", "#include <lib.glsl>\n")]);
+        assert!(Source::load(&dir.join("main.frag"), &[]).is_err());
+    }
+
+    #[test]
+    fn shipped_shaders_preprocess() {
+        let dir = Path::new(crate::gfx::SHADER_PATH_NAME);
+        let source = Source::load(&dir.join("shader.frag"), &crate::gfx::shader_defines()).unwrap();
+
+        assert!(source.text.starts_with("#version"));
+        assert!(source.text.contains("\n#define VOX_STONE 4\n"));
+        assert!(source.text.contains("\n#define PROJ_FISHEYE 3\n"));
+        assert!(source.text.contains("\n#define DEBUG_MATERIAL 5\n"));
+        assert!(source.text.contains("\n#define DEBUG_STEPS_RANGE 256.0\n"));
+        assert!(!source.text.contains("#include"));
+        assert!(depends_on(&source.files, &dir.join("color.glsl")));
+        assert!(depends_on(&source.files, &dir.join("debug.glsl")));
+    }
+
+    #[test]
+    fn maps_compiler_logs_to_files() {
+        let dir = write_files(
+            "logs",
+            &[
+                (
+                    "main.frag",
+                    "#version 450\n#include \"lib.glsl\"\nvoid main() {}\n",
+                ),
+                ("lib.glsl", "float a;\nfloat b\n"),
+            ],
+        );
+        let source = Source::load(&dir.join("main.frag"), &defines()).unwrap();
+        let lib = dir.join("lib.glsl").display().to_string();
+        let main = dir.join("main.frag").display().to_string();
+
+        assert_eq!(
+            source.map_log("0:4(1): error: syntax error"),
+            format!("{}:2(1): error: syntax error", lib)
+        );
+        assert_eq!(
+            source.map_log("0(5) : error C0000: syntax error"),
+            format!("{}:3 : error C0000: syntax error", main)
+        );
+        assert_eq!(
+            source.map_log("ERROR: 0:2: 'VOX_STONE' : redefinition"),
+            "ERROR: <defines>: 'VOX_STONE' : redefinition"
+        );
+
+        // nothing that looks like a line reference is left alone
+        assert_eq!(source.map_log("error: 10 errors"), "error: 10 errors");
+        assert_eq!(source.map_log("0:99: out of range"), "0:99: out of range");
+    }
+}
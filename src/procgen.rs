@@ -0,0 +1,483 @@
+use rand::prelude::*;
+
+use crate::types::{Dimension3, Idx3};
+use crate::world::{DenseGrid, Space, Voxel};
+
+/**************/
+/* Parameters */
+/**************/
+
+// Knobs for the terrain generator. Everything generated from the same parameters (seed included)
+// is identical, no matter how the world is split into chunks.
+#[derive(Clone, Debug)]
+pub struct GenParams {
+    pub seed: u64,
+
+    // height of the water surface; columns below it are flooded
+    pub sea_level: i64,
+
+    // average height of the terrain surface and how far it strays from it
+    pub base_height: i64,
+    pub height_amplitude: f32,
+
+    // horizontal size of terrain features, in voxels
+    pub terrain_scale: f32,
+    pub biome_scale: f32,
+    pub cave_scale: f32,
+
+    // 3D noise values above this are carved out into caves (range -1 to 1, higher = fewer caves)
+    pub cave_threshold: f32,
+
+    // probability that a grass column in a forest grows a tree
+    pub tree_density: f32,
+}
+
+impl Default for GenParams {
+    fn default() -> GenParams {
+        GenParams {
+            seed: 0,
+            sea_level: 9,
+            base_height: 12,
+            height_amplitude: 10.0,
+            terrain_scale: 48.0,
+            biome_scale: 160.0,
+            cave_scale: 16.0,
+            cave_threshold: 0.45,
+            tree_density: 0.04,
+        }
+    }
+}
+
+impl GenParams {
+    pub fn with_seed(seed: u64) -> GenParams {
+        GenParams {
+            seed,
+            ..Default::default()
+        }
+    }
+}
+
+/*********/
+/* Biome */
+/*********/
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Biome {
+    Ocean,
+    Plains,
+    Forest,
+    Desert,
+    Tundra,
+    Mountains,
+}
+
+impl Biome {
+    // material on the very top of a column
+    fn surface(&self) -> Voxel {
+        match self {
+            Biome::Ocean | Biome::Desert => Voxel::Sand,
+            Biome::Plains | Biome::Forest => Voxel::Grass,
+            Biome::Tundra => Voxel::Snow,
+            Biome::Mountains => Voxel::Stone,
+        }
+    }
+
+    // material for the few voxels under the surface, before reaching stone
+    fn subsurface(&self) -> Voxel {
+        match self {
+            Biome::Ocean | Biome::Desert => Voxel::Sand,
+            Biome::Plains | Biome::Forest | Biome::Tundra => Voxel::Dirt,
+            Biome::Mountains => Voxel::Stone,
+        }
+    }
+}
+
+/*********/
+/* Noise */
+/*********/
+
+// Classic gradient ("Perlin") noise over a permutation table shuffled by a seeded rng
+pub struct Noise {
+    perm: [u8; 512],
+}
+
+impl Noise {
+    pub fn new(rng: &mut StdRng) -> Noise {
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(rng);
+
+        let mut perm = [0; 512];
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = table[i % 256];
+        }
+        Noise { perm }
+    }
+
+    fn hash(&self, i: i64) -> usize {
+        self.perm[(i & 255) as usize] as usize
+    }
+
+    fn noise2(&self, x: f32, y: f32) -> f32 {
+        self.noise3(x, y, 0.5)
+    }
+
+    // gradient noise in roughly [-1, 1]
+    pub fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (xi, yi, zi) = (x.floor() as i64, y.floor() as i64, z.floor() as i64);
+        let (xf, yf, zf) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (u, v, w) = (fade(xf), fade(yf), fade(zf));
+
+        let a = self.hash(xi) + (yi & 255) as usize;
+        let aa = self.perm[a] as usize + (zi & 255) as usize;
+        let ab = self.perm[a + 1] as usize + (zi & 255) as usize;
+        let b = self.hash(xi + 1) + (yi & 255) as usize;
+        let ba = self.perm[b] as usize + (zi & 255) as usize;
+        let bb = self.perm[b + 1] as usize + (zi & 255) as usize;
+
+        let p = &self.perm;
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p[aa], xf, yf, zf), grad(p[ba], xf - 1.0, yf, zf)),
+                lerp(
+                    u,
+                    grad(p[ab], xf, yf - 1.0, zf),
+                    grad(p[bb], xf - 1.0, yf - 1.0, zf),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(p[aa + 1], xf, yf, zf - 1.0),
+                    grad(p[ba + 1], xf - 1.0, yf, zf - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(p[ab + 1], xf, yf - 1.0, zf - 1.0),
+                    grad(p[bb + 1], xf - 1.0, yf - 1.0, zf - 1.0),
+                ),
+            ),
+        )
+    }
+
+    // fractal sum of several octaves, normalized back to roughly [-1, 1]
+    fn fbm2(&self, x: f32, y: f32, octaves: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut norm = 0.0;
+        for _ in 0..octaves {
+            sum += amplitude * self.noise2(x * frequency, y * frequency);
+            norm += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        sum / norm
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+// dot product of (x, y, z) with one of the 12 cube edge gradients picked by the hash
+fn grad(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// splitmix64 finalizer, used to make per-column decisions that don't depend on visiting order
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/*************/
+/* Structure */
+/*************/
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Structure {
+    Tree {
+        trunk_height: i64,
+        crown_radius: i64,
+    },
+    Boulder {
+        radius: i64,
+    },
+}
+
+impl Structure {
+    // how far the structure can reach horizontally from the column it grows out of
+    const MAX_REACH: i64 = 3;
+
+    // voxels making up the structure, relative to the first empty voxel above the column
+    fn voxels(&self) -> Vec<((i64, i64, i64), Voxel)> {
+        let mut out = Vec::new();
+        match *self {
+            Structure::Tree {
+                trunk_height,
+                crown_radius,
+            } => {
+                for y in 0..trunk_height {
+                    out.push(((0, y, 0), Voxel::Wood));
+                }
+                let r = crown_radius;
+                for dx in -r..=r {
+                    for dy in -r..=r {
+                        for dz in -r..=r {
+                            if dx * dx + dy * dy + dz * dz <= r * r + 1 {
+                                out.push(((dx, trunk_height + dy, dz), Voxel::Leaves));
+                            }
+                        }
+                    }
+                }
+            }
+            Structure::Boulder { radius } => {
+                let r = radius;
+                for dx in -r..=r {
+                    for dy in -1..=r {
+                        for dz in -r..=r {
+                            if dx * dx + dy * dy + dz * dz <= r * r {
+                                out.push(((dx, dy, dz), Voxel::Stone));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/*********************/
+/* Terrain generator */
+/*********************/
+
+pub struct TerrainGenerator {
+    params: GenParams,
+    height: Noise,
+    ridges: Noise,
+    temperature: Noise,
+    moisture: Noise,
+    caves: Noise,
+}
+
+impl TerrainGenerator {
+    pub fn new(params: GenParams) -> TerrainGenerator {
+        let mut rng = StdRng::seed_from_u64(params.seed);
+        TerrainGenerator {
+            height: Noise::new(&mut rng),
+            ridges: Noise::new(&mut rng),
+            temperature: Noise::new(&mut rng),
+            moisture: Noise::new(&mut rng),
+            caves: Noise::new(&mut rng),
+            params,
+        }
+    }
+
+    // height of the topmost solid voxel of the column at (x, z)
+    pub fn surface_height(&self, x: i64, z: i64) -> i64 {
+        let p = &self.params;
+        let (fx, fz) = (x as f32 / p.terrain_scale, z as f32 / p.terrain_scale);
+        let base = self.height.fbm2(fx, fz, 4);
+
+        // sharp ridges only show up where the base terrain is already high
+        let ridge = 1.0 - self.ridges.noise2(fx * 0.5, fz * 0.5).abs();
+        let mountain = (base.max(0.0) * 2.0).min(1.0) * ridge * ridge;
+
+        p.base_height + ((base + mountain) * p.height_amplitude).round() as i64
+    }
+
+    fn biome_at(&self, x: i64, z: i64, height: i64) -> Biome {
+        let p = &self.params;
+        let (fx, fz) = (x as f32 / p.biome_scale, z as f32 / p.biome_scale);
+        let temperature = self.temperature.fbm2(fx, fz, 2);
+        let moisture = self.moisture.fbm2(fx, fz, 2);
+
+        if height < p.sea_level {
+            Biome::Ocean
+        } else if height as f32 > p.base_height as f32 + 0.8 * p.height_amplitude {
+            Biome::Mountains
+        } else if temperature < -0.2 {
+            Biome::Tundra
+        } else if temperature > 0.2 && moisture < 0.0 {
+            Biome::Desert
+        } else if moisture > 0.05 {
+            Biome::Forest
+        } else {
+            Biome::Plains
+        }
+    }
+
+    fn is_cave(&self, x: i64, y: i64, z: i64) -> bool {
+        let s = self.params.cave_scale;
+        // squash caves vertically so they form tunnels rather than blobs
+        let n = self
+            .caves
+            .noise3(x as f32 / s, y as f32 / (s * 0.6), z as f32 / s);
+        n > self.params.cave_threshold
+    }
+
+    // which structure (if any) grows out of the column at (x, z)
+    fn structure_at(&self, x: i64, z: i64, biome: Biome) -> Option<Structure> {
+        let h = mix(self.params.seed ^ mix((x as u64) ^ mix(z as u64)));
+        let roll = (h >> 40) as f32 / (1u64 << 24) as f32;
+        let variant = (h & 0xff) as i64;
+
+        let density = match biome {
+            Biome::Forest => self.params.tree_density,
+            Biome::Plains => self.params.tree_density * 0.1,
+            Biome::Desert | Biome::Mountains => self.params.tree_density * 0.05,
+            Biome::Ocean | Biome::Tundra => 0.0,
+        };
+        if roll >= density {
+            return None;
+        }
+
+        match biome {
+            Biome::Forest | Biome::Plains => Some(Structure::Tree {
+                trunk_height: 4 + variant % 3,
+                crown_radius: 2,
+            }),
+            _ => Some(Structure::Boulder {
+                radius: 1 + variant % 2,
+            }),
+        }
+    }
+
+    // Generates the block of voxels of the given shape whose lowest corner sits at *origin* in
+    // world coordinates. Adjacent chunks line up seamlessly.
+    pub fn generate_chunk(&self, origin: Idx3, shape: Dimension3) -> DenseGrid<Voxel> {
+        let mut voxels = DenseGrid::fill(shape, Voxel::Empty);
+        let (ox, oy, oz) = (origin.0 as i64, origin.1 as i64, origin.2 as i64);
+        let sea_level = self.params.sea_level;
+
+        // terrain, water and caves
+        for z in 0..shape.2 {
+            for x in 0..shape.0 {
+                let (wx, wz) = (ox + x as i64, oz + z as i64);
+                let height = self.surface_height(wx, wz);
+                let biome = self.biome_at(wx, wz, height);
+
+                for y in 0..shape.1 {
+                    let wy = oy + y as i64;
+                    let depth = height - wy;
+                    let vox = if wy == 0 {
+                        Voxel::Stone
+                    } else if depth < 0 {
+                        if wy <= sea_level {
+                            Voxel::Water
+                        } else {
+                            Voxel::Empty
+                        }
+                    } else if depth < 2 && height < sea_level + 2 {
+                        // keep a solid shell under and around water so it doesn't leak into caves
+                        Voxel::Sand
+                    } else if self.is_cave(wx, wy, wz) {
+                        Voxel::Empty
+                    } else if depth == 0 {
+                        biome.surface()
+                    } else if depth < 4 {
+                        biome.subsurface()
+                    } else {
+                        Voxel::Stone
+                    };
+                    voxels[(x, y, z)] = vox;
+                }
+            }
+        }
+
+        // structures, including those rooted in neighbouring chunks that reach into this one
+        let reach = Structure::MAX_REACH;
+        for wz in (oz - reach)..(oz + shape.2 as i64 + reach) {
+            for wx in (ox - reach)..(ox + shape.0 as i64 + reach) {
+                let height = self.surface_height(wx, wz);
+                let biome = self.biome_at(wx, wz, height);
+                if height < sea_level {
+                    continue;
+                }
+                let structure = match self.structure_at(wx, wz, biome) {
+                    Some(s) => s,
+                    None => continue,
+                };
+
+                for ((dx, dy, dz), vox) in structure.voxels() {
+                    let (x, y, z) = (wx + dx - ox, height + 1 + dy - oy, wz + dz - oz);
+                    if x < 0 || y < 0 || z < 0 {
+                        continue;
+                    }
+                    let idx = (x as usize, y as usize, z as usize);
+                    if idx.0 >= shape.0 || idx.1 >= shape.1 || idx.2 >= shape.2 {
+                        continue;
+                    }
+                    if voxels[idx].is_empty() {
+                        voxels[idx] = vox;
+                    }
+                }
+            }
+        }
+
+        voxels
+    }
+
+    // Generates a whole space starting at the world origin
+    pub fn generate_space(&self, shape: Dimension3) -> Space {
+        Space::from_voxels(self.generate_chunk((0, 0, 0), shape))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHAPE: Dimension3 = (48, 32, 48);
+
+    #[test]
+    fn same_seed_generates_identical_worlds() {
+        let a = TerrainGenerator::new(GenParams::with_seed(42)).generate_space(SHAPE);
+        let b = TerrainGenerator::new(GenParams::with_seed(42)).generate_space(SHAPE);
+        assert!(a.voxels.iter().eq(b.voxels.iter()));
+    }
+
+    #[test]
+    fn different_seeds_generate_different_worlds() {
+        let a = TerrainGenerator::new(GenParams::with_seed(1)).generate_space(SHAPE);
+        let b = TerrainGenerator::new(GenParams::with_seed(2)).generate_space(SHAPE);
+        assert!(!a.voxels.iter().eq(b.voxels.iter()));
+    }
+
+    #[test]
+    fn chunks_match_whole_world() {
+        let gen = TerrainGenerator::new(GenParams::with_seed(7));
+        let whole = gen.generate_chunk((0, 0, 0), SHAPE);
+        let half = (SHAPE.0 / 2, SHAPE.1, SHAPE.2 / 2);
+
+        for &(cx, cz) in &[(0, 0), (half.0, 0), (0, half.2), (half.0, half.2)] {
+            let chunk = gen.generate_chunk((cx, 0, cz), half);
+            for x in 0..half.0 {
+                for y in 0..half.1 {
+                    for z in 0..half.2 {
+                        assert_eq!(chunk[(x, y, z)], whole[(cx + x, y, cz + z)]);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use na::{vector, Vector2, Vector3};
+use std::f32::consts::PI;
+
+// How screen positions map to rays, in camera space: x is right, y is up and the camera looks
+// down +z. Each variant is drawn by the matching branch of `ray` in shader.frag, so the two must
+// be kept in sync.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Projection {
+    // pinhole camera with the given horizontal field of view, in degrees
+    Perspective { fov: f32 },
+
+    // parallel rays leaving a view plane *size* voxels wide
+    Orthographic { size: f32 },
+
+    // the full sphere around the camera, longitude across the screen and latitude up it
+    Equirectangular,
+
+    // equidistant fisheye covering *fov* degrees across the width of the screen, up to 360
+    Fisheye { fov: f32 },
+}
+
+impl Projection {
+    // id of the projection in the shader's `projection` uniform
+    pub fn id(&self) -> i32 {
+        match self {
+            Projection::Perspective { .. } => 0,
+            Projection::Orthographic { .. } => 1,
+            Projection::Equirectangular => 2,
+            Projection::Fisheye { .. } => 3,
+        }
+    }
+
+    // value of the shader's `proj_param` uniform: field of view in radians, or view plane width
+    pub fn param(&self) -> f32 {
+        match self {
+            Projection::Perspective { fov } | Projection::Fisheye { fov } => fov.to_radians(),
+            Projection::Orthographic { size } => *size,
+            Projection::Equirectangular => 0.0,
+        }
+    }
+
+    // Ray through the screen position *pos* (from -1 to 1 on both axes, y up) of a screen
+    // *aspect_ratio* times wider than high, as an origin and a unit direction in camera space.
+    // Positions outside the image circle of a fisheye have no ray.
+    pub fn ray(
+        &self,
+        pos: Vector2<f32>,
+        aspect_ratio: f32,
+    ) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        // screen position scaled so that distances are the same along both axes
+        let p = vector![pos.x, pos.y / aspect_ratio];
+
+        match self {
+            Projection::Perspective { .. } => {
+                let near = 1.0 / (self.param() / 2.0).tan();
+                Some((Vector3::zeros(), vector![p.x, p.y, near].normalize()))
+            }
+
+            Projection::Orthographic { size } => {
+                let origin = vector![p.x, p.y, 0.0] * (size / 2.0);
+                Some((origin, vector![0.0, 0.0, 1.0]))
+            }
+
+            Projection::Equirectangular => {
+                let (lon, lat) = (pos.x * PI, pos.y * PI / 2.0);
+                let dir = vector![lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos()];
+                Some((Vector3::zeros(), dir))
+            }
+
+            Projection::Fisheye { .. } => {
+                let theta = p.norm() * self.param() / 2.0;
+                if theta > PI {
+                    return None;
+                }
+                let phi = p.y.atan2(p.x);
+                let dir = vector![
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos()
+                ];
+                Some((Vector3::zeros(), dir))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORWARD: Vector3<f32> = Vector3::new(0.0, 0.0, 1.0);
+
+    fn angle(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+        a.angle(&b).to_degrees()
+    }
+
+    fn ray(proj: Projection, x: f32, y: f32) -> (Vector3<f32>, Vector3<f32>) {
+        proj.ray(vector![x, y], 16.0 / 9.0).unwrap()
+    }
+
+    #[test]
+    fn perspective_fov_is_in_degrees() {
+        let proj = Projection::Perspective { fov: 90.0 };
+        assert!(angle(ray(proj, 0.0, 0.0).1, FORWARD) < 1e-3);
+        assert!((angle(ray(proj, 1.0, 0.0).1, FORWARD) - 45.0).abs() < 1e-3);
+        assert!((angle(ray(proj, -1.0, 0.0).1, ray(proj, 1.0, 0.0).1) - 90.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let proj = Projection::Orthographic { size: 32.0 };
+        let (left, left_dir) = ray(proj, -1.0, 0.0);
+        let (right, right_dir) = ray(proj, 1.0, 0.0);
+        assert_eq!(left_dir, FORWARD);
+        assert_eq!(right_dir, FORWARD);
+        assert!(((right - left).norm() - 32.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn equirectangular_covers_the_sphere() {
+        let proj = Projection::Equirectangular;
+        assert!(angle(ray(proj, 0.0, 0.0).1, FORWARD) < 1e-3);
+        assert!((angle(ray(proj, 1.0, 0.0).1, FORWARD) - 180.0).abs() < 1e-2);
+        assert!((angle(ray(proj, 0.5, 0.0).1, vector![1.0, 0.0, 0.0]) - 0.0).abs() < 1e-2);
+        assert!((angle(ray(proj, 0.3, 1.0).1, vector![0.0, 1.0, 0.0]) - 0.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn fisheye_is_equidistant() {
+        let proj = Projection::Fisheye { fov: 180.0 };
+        assert!((angle(ray(proj, 1.0, 0.0).1, FORWARD) - 90.0).abs() < 1e-3);
+        assert!((angle(ray(proj, 0.5, 0.0).1, FORWARD) - 45.0).abs() < 1e-3);
+
+        // corners of a wide screen lie outside a 360 degree image circle
+        let full = Projection::Fisheye { fov: 360.0 };
+        assert!((angle(ray(full, 1.0, 0.0).1, FORWARD) - 180.0).abs() < 1e-2);
+        assert!(full.ray(vector![1.0, 1.0], 1.0).is_none());
+    }
+}
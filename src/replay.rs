@@ -0,0 +1,270 @@
+use glium::glutin::event::ElementState;
+
+use crate::game::Game;
+use crate::input::{key_name, Binding};
+use crate::world_loader::LoadError;
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+// One input delivered to the game, as seen by `Game::handle_input`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputEvent {
+    // a key or mouse button going up or down
+    Button(Binding, ElementState),
+
+    // mouse movement, in pixels
+    MouseMotion(f32, f32),
+
+    Axis(u32, f32),
+}
+
+// Everything that happened to the game, in order. Frames carry the frame time passed to
+// `Game::update`, so replaying them runs exactly the same fixed steps as the original.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Entry {
+    Input(InputEvent),
+    Frame(Duration),
+}
+
+/*************/
+/* Recording */
+/*************/
+
+#[derive(Clone, Default, Debug)]
+pub struct InputRecording {
+    // entries with the simulated time they happened at
+    pub entries: Vec<(Duration, Entry)>,
+}
+
+impl InputRecording {
+    pub fn push(&mut self, time: Duration, entry: Entry) {
+        self.entries.push((time, entry));
+    }
+
+    // Text format: one entry per line, starting with its time in nanoseconds:
+    //
+    //   <time> frame <frame time in nanoseconds>
+    //   <time> press|release <binding>
+    //   <time> mouse <dx> <dy>
+    //   <time> axis <axis> <value>
+    pub fn to_text(&self) -> String {
+        let mut out = String::from("# Input recording\n");
+        for (time, entry) in &self.entries {
+            let time = time.as_nanos();
+            match entry {
+                Entry::Frame(frame_time) => {
+                    writeln!(out, "{} frame {}", time, frame_time.as_nanos())
+                }
+                Entry::Input(InputEvent::Button(binding, state)) => {
+                    let verb = match state {
+                        ElementState::Pressed => "press",
+                        ElementState::Released => "release",
+                    };
+                    writeln!(out, "{} {} {}", time, verb, binding)
+                }
+                Entry::Input(InputEvent::MouseMotion(dx, dy)) => {
+                    writeln!(out, "{} mouse {} {}", time, dx, dy)
+                }
+                Entry::Input(InputEvent::Axis(axis, value)) => {
+                    writeln!(out, "{} axis {} {}", time, axis, value)
+                }
+            }
+            .unwrap();
+        }
+        out
+    }
+
+    pub fn parse(src: &str) -> Result<InputRecording, LoadError> {
+        let mut recording = InputRecording::default();
+
+        for (line_no, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || LoadError::Parse(format!("recording line {}: {:?}", line_no + 1, line));
+            fn num<T: std::str::FromStr>(
+                s: &str,
+                err: impl Fn() -> LoadError,
+            ) -> Result<T, LoadError> {
+                s.parse().map_err(|_| err())
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let entry = match words[..] {
+                [_, "frame", nanos] => Entry::Frame(Duration::from_nanos(num(nanos, err)?)),
+                [_, verb @ ("press" | "release"), binding] => {
+                    let binding = Binding::parse(binding).ok_or_else(err)?;
+                    let state = if verb == "press" {
+                        ElementState::Pressed
+                    } else {
+                        ElementState::Released
+                    };
+                    Entry::Input(InputEvent::Button(binding, state))
+                }
+                [_, "mouse", dx, dy] => {
+                    Entry::Input(InputEvent::MouseMotion(num(dx, err)?, num(dy, err)?))
+                }
+                [_, "axis", axis, value] => {
+                    Entry::Input(InputEvent::Axis(num(axis, err)?, num(value, err)?))
+                }
+                _ => return Err(err()),
+            };
+            let time = Duration::from_nanos(num(words[0], err)?);
+            recording.push(time, entry);
+        }
+
+        Ok(recording)
+    }
+
+    pub fn load(path: &Path) -> Result<InputRecording, LoadError> {
+        InputRecording::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+}
+
+// Whether an event can be written to a recording and read back
+pub fn is_recordable(event: &InputEvent) -> bool {
+    match event {
+        InputEvent::Button(Binding::Key(key), _) => key_name(*key).is_some(),
+        _ => true,
+    }
+}
+
+/**********/
+/* Replay */
+/**********/
+
+// Feeds a recording back into a game, frame by frame
+pub struct InputReplay {
+    recording: InputRecording,
+    next: usize,
+}
+
+impl InputReplay {
+    pub fn new(recording: InputRecording) -> InputReplay {
+        InputReplay { recording, next: 0 }
+    }
+
+    // Delivers the inputs up to the next recorded frame and runs that frame. Returns false once
+    // the recording is over.
+    pub fn next_frame(&mut self, game: &mut Game) -> bool {
+        while let Some((_, entry)) = self.recording.entries.get(self.next) {
+            self.next += 1;
+            match *entry {
+                Entry::Input(event) => game.handle_input(event),
+                Entry::Frame(frame_time) => {
+                    game.update(frame_time);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{DenseGrid, Space, Voxel};
+    use glium::glutin::event::VirtualKeyCode;
+    use na::vector;
+
+    fn replay_all(src: &str, game: &mut Game) {
+        let mut replay = InputReplay::new(InputRecording::parse(src).unwrap());
+        while replay.next_frame(game) {}
+    }
+
+    fn empty_game() -> Game {
+        Game::with_world(Space::from_voxels(DenseGrid::fill(
+            (16, 16, 16),
+            Voxel::Empty,
+        )))
+    }
+
+    // half a second of looking right, then flying forward
+    const RECORDING: &str = "\
+# Input recording
+0 mouse 600 0
+0 frame 16666666
+16666666 mouse 400 0
+16666666 press W
+";
+
+    #[test]
+    fn replays_a_recording() {
+        let mut src = RECORDING.to_string();
+        for i in 1..31u64 {
+            writeln!(src, "{} frame 16666666", i * 16666666).unwrap();
+        }
+        src.push_str("516666666 release W\n516666666 frame 16666666\n");
+
+        let mut game = empty_game();
+        let start = game.camera.pos;
+        replay_all(&src, &mut game);
+
+        // 1000 pixels of mouse motion is one radian of yaw
+        assert!((game.camera.yaw - 1.0).abs() < 1e-6);
+        assert_eq!(game.camera.pitch, 0.0);
+        assert_eq!(game.time_elapsed, Duration::from_nanos(32 * 16666666));
+
+        // W was held for 30 steps, so the camera flew some way along its view direction
+        let moved = game.camera.pos - start;
+        let forward = vector![1.0f32.sin(), 0.0, 1.0f32.cos()];
+        assert!(moved.norm() > 3.0, "moved {:?}", moved);
+        assert!(
+            (moved.normalize() - forward).norm() < 1e-4,
+            "moved {:?}",
+            moved
+        );
+    }
+
+    #[test]
+    fn replay_matches_the_recorded_session() {
+        let mut game = empty_game();
+        game.input_recording = Some(InputRecording::default());
+
+        let frames: [u64; 5] = [16, 7, 33, 16, 40];
+        for (i, &ms) in frames.iter().cycle().take(50).enumerate() {
+            if i % 9 == 0 {
+                let state = if i % 18 == 0 {
+                    ElementState::Pressed
+                } else {
+                    ElementState::Released
+                };
+                game.handle_input(InputEvent::Button(Binding::Key(VirtualKeyCode::D), state));
+            }
+            if i == 20 {
+                game.handle_input(InputEvent::Button(
+                    Binding::Key(VirtualKeyCode::F),
+                    ElementState::Pressed,
+                ));
+            }
+            game.mouse_input((i as f32 * 1.7, -0.3));
+            game.update(Duration::from_millis(ms));
+        }
+
+        let text = game.input_recording.take().unwrap().to_text();
+        let mut replayed = empty_game();
+        replay_all(&text, &mut replayed);
+
+        assert_eq!(replayed.camera.pos, game.camera.pos);
+        assert_eq!(replayed.camera.yaw, game.camera.yaw);
+        assert_eq!(replayed.camera.pitch, game.camera.pitch);
+        assert_eq!(replayed.player.mode, game.player.mode);
+        assert_eq!(replayed.time_elapsed, game.time_elapsed);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(InputRecording::parse("0 press NotAKey").is_err());
+        assert!(InputRecording::parse("0 mouse 1").is_err());
+        assert!(InputRecording::parse("soon frame 1").is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+// lowest render scale the automatic mode will go down to
+pub const MIN_SCALE: f32 = 0.25;
+
+// Size of the image marched for a window of *size* at the given render scale
+pub fn scaled_size((w, h): (u32, u32), scale: f32) -> (u32, u32) {
+    let scaled = |n: u32| ((n as f32 * scale).round() as u32).clamp(1, n.max(1));
+    (scaled(w), scaled(h))
+}
+
+/**********************/
+/* Dynamic resolution */
+/**********************/
+
+// Picks the render scale from recent frame times, lowering it when frames take longer than the
+// budget and raising it again when there is time to spare
+pub struct DynamicResolution {
+    // the scale to render at, which moves in steps of `STEP`
+    pub scale: f32,
+
+    // seconds a frame may take
+    pub budget: f32,
+
+    // exponential moving average of the frame time, in seconds
+    average: f32,
+
+    // the scale the frame times call for, before it is rounded to a step
+    target: f32,
+}
+
+impl DynamicResolution {
+    // weight of the newest frame in the moving average
+    const SMOOTHING: f32 = 0.1;
+
+    // frame times within this fraction below the budget are left alone, so that the scale
+    // doesn't hunt around a frame rate capped by vsync
+    const HEADROOM: f32 = 0.15;
+
+    // every change of scale changes the size of the render targets, which are then made again,
+    // so the scale only moves in steps of this, and only once the target is a whole step away
+    const STEP: f32 = 1.0 / 32.0;
+
+    pub fn new(scale: f32, budget: f32) -> DynamicResolution {
+        let scale = scale.clamp(MIN_SCALE, 1.0);
+        DynamicResolution {
+            scale,
+            budget,
+            average: budget,
+            target: scale,
+        }
+    }
+
+    // Takes the time the last frame took and gives the scale to render the next one at
+    pub fn update(&mut self, frame_time: Duration) -> f32 {
+        let frame_time = frame_time.as_secs_f32();
+        self.average += (frame_time - self.average) * Self::SMOOTHING;
+
+        // marching cost goes with the pixel count, which goes with the square of the scale
+        if self.average > self.budget || self.average < self.budget * (1.0 - Self::HEADROOM) {
+            let target = self.scale * (self.budget / self.average).sqrt();
+            self.target += (target - self.target) * Self::SMOOTHING;
+            self.target = self.target.clamp(MIN_SCALE, 1.0);
+        }
+
+        // the limits are always reached, even when they are less than a step away
+        let at_limit = self.target == MIN_SCALE || self.target == 1.0;
+        if (self.target - self.scale).abs() >= Self::STEP || at_limit {
+            let step = (self.target / Self::STEP).round() * Self::STEP;
+            self.scale = step.clamp(MIN_SCALE, 1.0);
+        }
+        self.scale
+    }
+}
+
+/*********/
+/* Tiles */
+/*********/
+
+// most tiles along each axis an image can be rendered in, which makes it this many times larger
+// than the window along each axis
+pub const MAX_TILE_FACTOR: u32 = 8;
+
+// Part of the screen rendered on its own, so that an image larger than the window can be made of
+// window sized pieces
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tile {
+    // column and row of the tile from the top left, in tiles
+    pub col: u32,
+    pub row: u32,
+
+    // screen position (-1 to 1 on both axes, y up) of the center of the tile, and its size as a
+    // fraction of the screen along each axis; see `view_offset` and `view_scale` in shader.frag
+    pub offset: (f32, f32),
+    pub scale: f32,
+}
+
+impl Tile {
+    // the whole screen in one tile
+    pub const FULL: Tile = Tile {
+        col: 0,
+        row: 0,
+        offset: (0.0, 0.0),
+        scale: 1.0,
+    };
+
+    // The tiles of an image *factor* times the size of the screen along each axis, row by row
+    pub fn grid(factor: u32) -> Vec<Tile> {
+        let n = factor as f32;
+        let center = |i: u32| -1.0 + (2 * i + 1) as f32 / n;
+        (0..factor)
+            .flat_map(|row| (0..factor).map(move |col| (col, row)))
+            .map(|(col, row)| Tile {
+                col,
+                row,
+                offset: (center(col), -center(row)),
+                scale: 1.0 / n,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(res: &mut DynamicResolution, frame_time: f32, frames: usize) {
+        for _ in 0..frames {
+            res.update(Duration::from_secs_f32(frame_time));
+        }
+    }
+
+    #[test]
+    fn scales_sizes() {
+        assert_eq!(scaled_size((1024, 768), 1.0), (1024, 768));
+        assert_eq!(scaled_size((1024, 768), 0.5), (512, 384));
+        assert_eq!(scaled_size((3, 1), 0.01), (1, 1));
+    }
+
+    #[test]
+    fn lowers_scale_over_budget() {
+        let mut res = DynamicResolution::new(1.0, 1.0 / 60.0);
+        run(&mut res, 1.0 / 30.0, 200);
+        assert!(res.scale < 0.8, "scale {}", res.scale);
+
+        // a hopeless frame time bottoms out instead of vanishing
+        run(&mut res, 1.0, 1000);
+        assert_eq!(res.scale, MIN_SCALE);
+    }
+
+    #[test]
+    fn recovers_with_time_to_spare() {
+        let mut res = DynamicResolution::new(MIN_SCALE, 1.0 / 60.0);
+        run(&mut res, 1.0 / 240.0, 500);
+        assert_eq!(res.scale, 1.0);
+    }
+
+    #[test]
+    fn holds_steady_at_the_budget() {
+        let mut res = DynamicResolution::new(0.6, 1.0 / 60.0);
+        run(&mut res, 1.0 / 62.0, 500);
+        assert_eq!(res.scale, 0.6);
+    }
+
+    #[test]
+    fn settles_on_a_stable_size() {
+        // frames take longer with more pixels, so the scale settles where they fit the budget
+        let mut res = DynamicResolution::new(1.0, 1.0 / 60.0);
+        let mut sizes = Vec::new();
+        for _ in 0..2000 {
+            let frame_time = res.scale * res.scale / 40.0;
+            res.update(Duration::from_secs_f32(frame_time));
+            let size = scaled_size((1920, 1080), res.scale);
+            if sizes.last() != Some(&size) {
+                sizes.push(size);
+            }
+        }
+        // a handful of steps down from full size, and none back and forth
+        assert!(sizes.len() <= 8, "{:?}", sizes);
+        assert!(sizes.windows(2).all(|w| w[1].0 < w[0].0), "{:?}", sizes);
+        assert!(res.scale < 0.85 && res.scale > 0.7, "scale {}", res.scale);
+    }
+
+    #[test]
+    fn tiles_cover_the_screen() {
+        assert_eq!(Tile::grid(1), [Tile::FULL]);
+
+        let tiles = Tile::grid(4);
+        assert_eq!(tiles.len(), 16);
+        for tile in &tiles {
+            // the corners of the tile, in screen positions
+            let left = tile.offset.0 - tile.scale;
+            let top = tile.offset.1 + tile.scale;
+            assert!((left - (-1.0 + tile.col as f32 * 0.5)).abs() < 1e-6);
+            assert!((top - (1.0 - tile.row as f32 * 0.5)).abs() < 1e-6);
+        }
+        assert_eq!((tiles[1].col, tiles[1].row), (1, 0));
+    }
+}
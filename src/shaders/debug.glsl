@@ -0,0 +1,47 @@
+////////////////////////////////////////////////////////////////////////////////
+// debug views                                                                //
+////////////////////////////////////////////////////////////////////////////////
+
+// Colors that show what the marcher did, in place of the shaded scene. See `DebugView` in
+// debug_view.rs; the DEBUG_* view ids and ranges are defined by `gfx::shader_defines`.
+
+#include "color.glsl"
+
+uniform int debug_view;
+
+// blue through cyan, green and yellow to red as t goes from 0 to 1
+vec3 heat(float t) {
+    t = clamp(t, 0.0, 1.0);
+    return clamp(vec3(4 * t - 2, 2 - abs(4 * t - 2), 2 - 4 * t), 0.0, 1.0);
+}
+
+// a color for each voxel id, spread around the hue circle so that neighbouring ids stand apart;
+// white for id 0, where the ray left the world
+vec3 id_color(int id) {
+    if (id == 0) {
+        return vec3(1);
+    }
+    return hsv2rgb(vec3(fract(float(id) * 0.618034), 0.75, 1.0));
+}
+
+// Color of a pixel in the current debug view, for a ray that took *steps* SDF samples and, if it
+// *hit* anything, found voxel *vox* at *dist* with *normal*, after a last sample of *sdf_before*
+vec3 debug_color(bool hit, int steps, int sdf_before, float dist, vec3 normal, int vox) {
+    // misses are often the most expensive pixels, so they are shown too
+    if (debug_view == DEBUG_STEPS) {
+        return heat(float(steps) / DEBUG_STEPS_RANGE);
+    }
+    if (!hit) {
+        return vec3(0);
+    }
+    if (debug_view == DEBUG_SDF) {
+        return heat(float(sdf_before) / DEBUG_SDF_RANGE);
+    }
+    if (debug_view == DEBUG_DEPTH) {
+        return vec3(1.0 - clamp(dist / DEBUG_DEPTH_RANGE, 0.0, 1.0));
+    }
+    if (debug_view == DEBUG_NORMALS) {
+        return normal * 0.5 + 0.5;
+    }
+    return id_color(vox);
+}
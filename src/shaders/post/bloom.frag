@@ -0,0 +1,46 @@
+#version 450
+
+// Makes bright parts of the HDR image bleed into their surroundings, by adding a blurred copy of
+// everything brighter than THRESHOLD
+
+uniform sampler2D color;
+uniform vec2 resolution;
+
+in vec2 vPos;
+
+out vec4 f_color;
+
+#define THRESHOLD 1.0
+#define INTENSITY 0.3
+#define RADIUS    12.0
+#define RINGS     3
+#define TAPS      8
+
+#define PI 3.14159265358979
+
+vec3 bright(vec2 uv) {
+    vec3 c = texture(color, uv).rgb;
+    return max(c - THRESHOLD, 0.0);
+}
+
+void main() {
+    vec2 uv = vPos * 0.5 + 0.5;
+    vec2 texel = 1.0 / resolution;
+    vec3 base = texture(color, uv).rgb;
+
+    // gaussian weighted rings of taps around the pixel, rotated so they don't line up
+    vec3 glow = bright(uv);
+    float total = 1.0;
+    for (int ring = 1; ring <= RINGS; ring++) {
+        float r = RADIUS * float(ring) / float(RINGS);
+        float weight = exp(-2.0 * float(ring * ring) / float(RINGS * RINGS));
+        for (int i = 0; i < TAPS; i++) {
+            float angle = (float(i) + 0.5 * float(ring)) * (2.0 * PI / float(TAPS));
+            vec2 offset = vec2(cos(angle), sin(angle)) * r * texel;
+            glow += bright(uv + offset) * weight;
+            total += weight;
+        }
+    }
+
+    f_color = vec4(base + INTENSITY * glow / total, 1);
+}
@@ -0,0 +1,49 @@
+#version 450
+
+// Fast approximate anti-aliasing: finds edges from luma contrast and blurs along them. Runs on
+// tonemapped colors.
+
+uniform sampler2D color;
+uniform vec2 resolution;
+
+in vec2 vPos;
+
+out vec4 f_color;
+
+#define REDUCE_MIN (1.0 / 128.0)
+#define REDUCE_MUL (1.0 / 8.0)
+#define SPAN_MAX   8.0
+
+float luma(vec3 c) {
+    return dot(c, vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+    vec2 uv = vPos * 0.5 + 0.5;
+    vec2 texel = 1.0 / resolution;
+
+    float nw = luma(texture(color, uv + vec2(-1, -1) * texel).rgb);
+    float ne = luma(texture(color, uv + vec2( 1, -1) * texel).rgb);
+    float sw = luma(texture(color, uv + vec2(-1,  1) * texel).rgb);
+    float se = luma(texture(color, uv + vec2( 1,  1) * texel).rgb);
+    vec3 center = texture(color, uv).rgb;
+    float m = luma(center);
+
+    float luma_min = min(m, min(min(nw, ne), min(sw, se)));
+    float luma_max = max(m, max(max(nw, ne), max(sw, se)));
+
+    // direction along the edge
+    vec2 dir = vec2(-((nw + ne) - (sw + se)), (nw + sw) - (ne + se));
+    float reduce = max((nw + ne + sw + se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * scale, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel;
+
+    vec3 a = 0.5 * (texture(color, uv + dir * (1.0 / 3.0 - 0.5)).rgb
+                  + texture(color, uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 b = a * 0.5 + 0.25 * (texture(color, uv - dir * 0.5).rgb
+                             + texture(color, uv + dir * 0.5).rgb);
+
+    // the wider sample may cross into another edge; fall back to the narrow one then
+    float luma_b = luma(b);
+    f_color = vec4((luma_b < luma_min || luma_b > luma_max) ? a : b, 1);
+}
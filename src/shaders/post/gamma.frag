@@ -0,0 +1,17 @@
+#version 450
+
+// Encodes linear color for display
+
+uniform sampler2D color;
+
+in vec2 vPos;
+
+out vec4 f_color;
+
+#define GAMMA 2.2
+
+void main() {
+    vec2 uv = vPos * 0.5 + 0.5;
+    vec3 c = texture(color, uv).rgb;
+    f_color = vec4(pow(max(c, 0.0), vec3(1.0 / GAMMA)), 1);
+}
@@ -0,0 +1,26 @@
+#version 450
+
+// Maps HDR color into [0, 1] with a filmic curve (Narkowicz's ACES fit)
+
+uniform sampler2D color;
+
+in vec2 vPos;
+
+out vec4 f_color;
+
+#define EXPOSURE 1.0
+
+vec3 aces(vec3 x) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+void main() {
+    vec2 uv = vPos * 0.5 + 0.5;
+    vec3 c = texture(color, uv).rgb * EXPOSURE;
+    f_color = vec4(aces(c), 1);
+}
@@ -0,0 +1,25 @@
+#version 450
+
+// Darkens the corners of the image
+
+uniform sampler2D color;
+uniform vec2 resolution;
+
+in vec2 vPos;
+
+out vec4 f_color;
+
+#define STRENGTH 0.35
+#define INNER    0.45
+#define OUTER    1.1
+
+void main() {
+    vec2 uv = vPos * 0.5 + 0.5;
+
+    // distance from the center, with the same scale along both axes
+    vec2 p = vPos * vec2(resolution.x / resolution.y, 1);
+    float d = length(p) / length(vec2(resolution.x / resolution.y, 1));
+    float shade = 1.0 - STRENGTH * smoothstep(INNER, OUTER, d);
+
+    f_color = vec4(texture(color, uv).rgb * shade, 1);
+}
@@ -1,12 +1,26 @@
 #version 450
 
-uniform float near;
+// VOX_* voxel ids, PROJ_* projection ids, DEBUG_* debug views and WORLD_DIM_* are defined by
+// `gfx::shader_defines`
+
+// see `Projection` in projection.rs
+uniform int projection;
+uniform float proj_param;
 uniform mat3 cam_rot;
 uniform vec3 cam_pos;
 uniform float aspect_ratio;
 
+// part of the screen this frame covers, to render large images in tiles (see `Tile` in
+// resolution.rs)
+uniform vec2 view_offset;
+uniform float view_scale;
+
 uniform float time;
 
+// see `Lighting` in gfx.rs
+uniform float light_intensity;
+uniform float saturation;
+
 uniform usampler3D sdf_data;
 uniform usampler3D voxels;
 
@@ -17,45 +31,56 @@ uniform usampler3D voxels;
 in vec3 vColor;
 in vec2 vPos;
 
+// linear HDR color, then the geometry the post passes can use
 out vec4 f_color;
+out vec4 f_normal_depth;
+out float f_material;
 
 #define PI 3.14159265358979
 
-#define VOX_RED   1
-#define VOX_BLUE  2
-#define VOX_GREEN 3
-
 const ivec3 sdf_size = textureSize(sdf_data, 0);
 
 int sdf(vec3 p);
 int voxel(vec3 p);
 
 vec3 next_point(vec3 pos, vec3 dir);
+bool ray(vec2 pos, out vec3 origin, out vec3 dir);
 
-vec3 hsv2rgb(vec3 c);
-vec3 rgb2hsv(vec3 c);
+#include "color.glsl"
+#include "debug.glsl"
 
 void main() {
-    //float res = 200;
-    //vec2 pos = floor(vPos * (res / 2)) / (res / 2);
-    vec2 pos = vPos;
-    vec3 dir_rel = vec3(pos.x, pos.y / aspect_ratio, near);
-    vec3 dir = normalize(cam_rot * dir_rel);
+    // for a pixelated look, march at a low render scale and upscale without smoothing (see
+    // `render_scale` and `pixelated` in config.rs)
+    vec2 pos = vPos * view_scale + view_offset;
+    vec3 origin, dir;
+    f_normal_depth = vec4(0, 0, 0, -1);
+    f_material = 0;
+    if (!ray(pos, origin, dir)) {
+        f_color = vec4(0, 0, 0, 1);
+        return;
+    }
+
+    vec3 v = origin;
+    vec3 prev = origin;
 
-    vec3 v = cam_pos;
+    // SDF value of the previous sample, for the debug views
+    int prev_dist = 0;
 
     for (int step = 0; step < MAX_STEPS; step++) {
         // l1 distance from current voxel to the nearest filled voxel
         int l1_dist = int(sdf(v));
         if (l1_dist > 0) {
             for (int i = 0; i < l1_dist; i++) {
+                prev = v;
                 v = next_point(v, dir);
             }
+            prev_dist = l1_dist;
         } else {
             // hit a voxel! give it a pretty color for now
-            vec3 rel = v - cam_pos;
+            vec3 rel = v - origin;
             float dist = dot(rel, rel);
-            float light_factor = ((STEP_SIZE * MAX_STEPS) / (dist/10));
+            float light_factor = light_intensity / dist;
             //vec4 fun_color = abs(0.2 * vec4(normalize(v - cam_pos), 1));
             vec4 base_color = vec4(0,0,0,0);
             unsigned int vox = voxel(v);
@@ -66,23 +91,87 @@ void main() {
                 base_color = vec4(0, 0, 1, 1);
             else if (vox == VOX_GREEN)
                 base_color = vec4(0, 1, 0, 1);
+            else if (vox == VOX_STONE)
+                base_color = vec4(0.50, 0.50, 0.50, 1);
+            else if (vox == VOX_DIRT)
+                base_color = vec4(0.42, 0.29, 0.17, 1);
+            else if (vox == VOX_GRASS)
+                base_color = vec4(0.30, 0.63, 0.24, 1);
+            else if (vox == VOX_SAND)
+                base_color = vec4(0.88, 0.82, 0.56, 1);
+            else if (vox == VOX_SNOW)
+                base_color = vec4(0.94, 0.96, 0.97, 1);
+            else if (vox == VOX_WOOD)
+                base_color = vec4(0.55, 0.35, 0.17, 1);
+            else if (vox == VOX_LEAVES)
+                base_color = vec4(0.18, 0.49, 0.20, 1);
+            else if (vox == VOX_WATER)
+                base_color = vec4(0.16, 0.38, 0.75, 1);
             else
                 base_color = vec4(1, 0, 1, 1);
 
             f_color = abs(light_factor *  base_color);
 
             vec3 hsv = rgb2hsv(f_color.rgb);
-            hsv.y = 0.99;
+            hsv.y = saturation;
             f_color.rgb = hsv2rgb(hsv);
+
+            // the face we came in through is across the cell boundary from the last point
+            vec3 normal = vec3(notEqual(floor(v), floor(prev))) * -sign(dir);
+            if (dot(normal, normal) == 0) {
+                normal = -dir;
+            }
+            f_normal_depth = vec4(normalize(normal), length(v - origin));
+            f_material = float(vox);
+
+            if (debug_view != DEBUG_OFF) {
+                vec3 c = debug_color(true, step + 1, prev_dist, f_normal_depth.w,
+                                     f_normal_depth.xyz, int(vox));
+                f_color = vec4(c, 1);
+            }
             return;
         }
     }
 
     // found nothing after MAX_STEPS steps
     f_color = vec4(0.01,0,0,1);
+    if (debug_view != DEBUG_OFF) {
+        f_color = vec4(debug_color(false, MAX_STEPS, prev_dist, 0.0, vec3(0), 0), 1);
+    }
 }
 
 
+// Gives the world space ray through screen position *pos* (-1 to 1 on both axes), mirroring
+// `Projection::ray`. Returns false outside the image circle of a fisheye.
+bool ray(vec2 pos, out vec3 origin, out vec3 dir) {
+    vec2 p = vec2(pos.x, pos.y / aspect_ratio);
+    vec3 origin_rel = vec3(0);
+    vec3 dir_rel;
+
+    if (projection == PROJ_ORTHOGRAPHIC) {
+        origin_rel = vec3(p, 0) * (proj_param / 2);
+        dir_rel = vec3(0, 0, 1);
+    } else if (projection == PROJ_EQUIRECTANGULAR) {
+        float lon = pos.x * PI;
+        float lat = pos.y * PI / 2;
+        dir_rel = vec3(cos(lat) * sin(lon), sin(lat), cos(lat) * cos(lon));
+    } else if (projection == PROJ_FISHEYE) {
+        float theta = length(p) * proj_param / 2;
+        if (theta > PI) {
+            return false;
+        }
+        float phi = atan(p.y, p.x);
+        dir_rel = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+    } else {
+        float near = 1.0 / tan(proj_param / 2);
+        dir_rel = normalize(vec3(p, near));
+    }
+
+    origin = cam_pos + cam_rot * origin_rel;
+    dir = cam_rot * dir_rel;
+    return true;
+}
+
 // Gives the next point after stepping through one voxel (to the boundary), starting at *pos*, in direction *dir*
 vec3 next_point(vec3 pos, vec3 dir) {
     // next_pts[i] is the ith component of the vector obtained after stepping from pos in direction dir until we hit a voxel boundary where component i is 0
@@ -114,28 +203,3 @@ int voxel(vec3 p) {
     }
     return int(texelFetch(voxels, ivec3(p), 0).r);
 }
-
-
-////////////////////////////////////////////////////////////////////////////////
-// color util functions                                                       //
-////////////////////////////////////////////////////////////////////////////////
-
-// All components are in the range [0…1], including hue.
-vec3 rgb2hsv(vec3 c)
-{
-    vec4 K = vec4(0.0, -1.0 / 3.0, 2.0 / 3.0, -1.0);
-    vec4 p = mix(vec4(c.bg, K.wz), vec4(c.gb, K.xy), step(c.b, c.g));
-    vec4 q = mix(vec4(p.xyw, c.r), vec4(c.r, p.yzx), step(p.x, c.r));
-
-    float d = q.x - min(q.w, q.y);
-    float e = 1.0e-10;
-    return vec3(abs(q.z + (q.w - q.y) / (6.0 * d + e)), d / (q.x + e), q.x);
-}
-
-// All components are in the range [0…1], including hue.
-vec3 hsv2rgb(vec3 c)
-{
-    vec4 K = vec4(1.0, 2.0 / 3.0, 1.0 / 3.0, 3.0);
-    vec3 p = abs(fract(c.xxx + K.xyz) * 6.0 - K.www);
-    return c.z * mix(K.xxx, clamp(p - K.xxx, 0.0, 1.0), c.y);
-}
@@ -0,0 +1,14 @@
+#version 450
+
+// Built into the binary, see `TextRenderer`
+
+uniform sampler2D font;
+
+in vec2 vUv;
+in vec4 vColor;
+
+out vec4 f_color;
+
+void main() {
+    f_color = vColor * vec4(1, 1, 1, texture(font, vUv).r);
+}
@@ -0,0 +1,16 @@
+#version 450
+
+// Built into the binary, see `TextRenderer`
+
+in vec2 position;
+in vec2 uv;
+in vec4 color;
+
+out vec2 vUv;
+out vec4 vColor;
+
+void main() {
+    vUv = uv;
+    vColor = color;
+    gl_Position = vec4(position, 0, 1);
+}
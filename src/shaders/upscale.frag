@@ -0,0 +1,61 @@
+#version 450
+
+// Upscales the marched image to the size of the window. Blends the four nearest marched pixels
+// like bilinear filtering would, but leaves out those far in front of or behind the nearest one,
+// so edges against the sky or other geometry stay sharp instead of bleeding into each other.
+
+uniform sampler2D color;
+uniform sampler2D normal_depth;
+
+// nearest marched pixel only, for blocky pixels
+uniform bool pixelated;
+
+in vec2 vPos;
+
+out vec4 f_color;
+
+// depth difference, relative to the nearest pixel's depth, beyond which pixels stop blending
+#define DEPTH_TOLERANCE 0.1
+
+// stands in for the depth of pixels where nothing was hit
+#define FAR 1e6
+
+float depth_at(ivec2 p) {
+    float d = texelFetch(normal_depth, p, 0).w;
+    return d < 0 ? FAR : d;
+}
+
+void main() {
+    vec2 uv = vPos * 0.5 + 0.5;
+    ivec2 size = textureSize(color, 0);
+
+    // position in marched pixels, measured from pixel centers
+    vec2 p = uv * vec2(size) - 0.5;
+    ivec2 nearest = clamp(ivec2(round(p)), ivec2(0), size - 1);
+
+    if (pixelated) {
+        f_color = texelFetch(color, nearest, 0);
+        return;
+    }
+
+    ivec2 base = ivec2(floor(p));
+    vec2 f = p - vec2(base);
+    float ref_depth = depth_at(nearest);
+
+    vec4 sum = vec4(0);
+    float total = 0;
+    for (int i = 0; i < 4; i++) {
+        ivec2 offset = ivec2(i & 1, i >> 1);
+        ivec2 q = clamp(base + offset, ivec2(0), size - 1);
+
+        vec2 w2 = mix(1 - f, f, vec2(offset));
+        float w = w2.x * w2.y;
+        if (abs(depth_at(q) - ref_depth) > DEPTH_TOLERANCE * ref_depth) {
+            w *= 1e-3;
+        }
+
+        sum += texelFetch(color, q, 0) * w;
+        total += w;
+    }
+    f_color = sum / total;
+}
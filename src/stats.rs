@@ -0,0 +1,296 @@
+use glium::backend::Facade;
+use glium::draw_parameters::TimeElapsedQuery;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+// number of frames the rolling statistics are taken over
+pub const WINDOW: usize = 120;
+
+// Timings of one frame
+#[derive(Clone, Copy, Default, Debug)]
+pub struct FrameSample {
+    // time since the previous frame
+    pub frame: Duration,
+
+    // CPU time spent advancing the game, and issuing the draw calls (without waiting for vsync)
+    pub update: Duration,
+    pub draw: Duration,
+
+    // GPU time of the latest frame the GPU reported on during this one, which lags a frame or
+    // two behind, if any
+    pub gpu: Option<Duration>,
+}
+
+fn ms(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
+
+/***************/
+/* Frame stats */
+/***************/
+
+// Statistics over the last `WINDOW` frames
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Summary {
+    pub fps: f32,
+
+    // frame times
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    pub p99: Duration,
+
+    // average CPU and GPU times
+    pub update: Duration,
+    pub draw: Duration,
+    pub gpu: Option<Duration>,
+}
+
+#[derive(Default)]
+pub struct FrameStats {
+    samples: VecDeque<FrameSample>,
+}
+
+impl FrameStats {
+    pub fn push(&mut self, sample: FrameSample) {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn summary(&self) -> Option<Summary> {
+        let n = self.samples.len() as u32;
+        if n == 0 {
+            return None;
+        }
+        let mut frames: Vec<Duration> = self.samples.iter().map(|s| s.frame).collect();
+        frames.sort();
+        let total: Duration = frames.iter().sum();
+
+        // nearest rank: the smallest frame time at least 99% of the frames are within
+        let p99 = frames[(frames.len() * 99).div_ceil(100) - 1];
+
+        let gpu: Vec<Duration> = self.samples.iter().filter_map(|s| s.gpu).collect();
+        let gpu = (!gpu.is_empty()).then(|| gpu.iter().sum::<Duration>() / gpu.len() as u32);
+
+        Some(Summary {
+            fps: if total.is_zero() {
+                0.0
+            } else {
+                n as f32 / total.as_secs_f32()
+            },
+            min: frames[0],
+            avg: total / n,
+            max: frames[frames.len() - 1],
+            p99,
+            update: self.samples.iter().map(|s| s.update).sum::<Duration>() / n,
+            draw: self.samples.iter().map(|s| s.draw).sum::<Duration>() / n,
+            gpu,
+        })
+    }
+
+    // Text for the statistics overlay
+    pub fn overlay_text(&self) -> String {
+        let s = match self.summary() {
+            Some(summary) => summary,
+            None => return "Waiting for frames...".to_string(),
+        };
+        let gpu = s
+            .gpu
+            .map_or("n/a".to_string(), |gpu| format!("{:.2} ms", ms(gpu)));
+        format!(
+            "{:.0} fps\n\
+             frame  min {:.1}  avg {:.1}  max {:.1}  p99 {:.1} ms\n\
+             cpu    update {:.2}  draw {:.2} ms\n\
+             gpu    {}",
+            s.fps,
+            ms(s.min),
+            ms(s.avg),
+            ms(s.max),
+            ms(s.p99),
+            ms(s.update),
+            ms(s.draw),
+            gpu
+        )
+    }
+}
+
+/*************/
+/* GPU timer */
+/*************/
+
+// Measures how long the GPU spends drawing each frame with timer queries. The GPU runs behind the
+// CPU, so a frame's query is only read once the GPU is done with it, a frame or more later.
+pub struct GpuTimer {
+    // queries handed out and not read yet, oldest first
+    pending: VecDeque<TimeElapsedQuery>,
+
+    // cleared when the driver can't create timer queries, so it isn't asked every frame
+    supported: bool,
+}
+
+impl Default for GpuTimer {
+    fn default() -> GpuTimer {
+        GpuTimer {
+            pending: VecDeque::new(),
+            supported: true,
+        }
+    }
+}
+
+impl GpuTimer {
+    // queries kept waiting at most; a query no draw call used never becomes ready, so the oldest
+    // ones are given up on
+    const MAX_PENDING: usize = 4;
+
+    // A new query for the draw calls of this frame to add their time to
+    pub fn query<F: Facade + ?Sized>(&mut self, facade: &F) -> Option<&TimeElapsedQuery> {
+        if !self.supported {
+            return None;
+        }
+        match TimeElapsedQuery::new(facade) {
+            Ok(query) => {
+                if self.pending.len() == GpuTimer::MAX_PENDING {
+                    self.pending.pop_front();
+                }
+                self.pending.push_back(query);
+                self.pending.back()
+            }
+            Err(err) => {
+                eprintln!("GPU frame times unavailable: {:?}", err);
+                self.supported = false;
+                None
+            }
+        }
+    }
+
+    // GPU time of the latest frame the GPU finished since the last call, without waiting for it
+    pub fn poll(&mut self) -> Option<Duration> {
+        let mut latest = None;
+        while self.pending.front().is_some_and(|query| query.is_ready()) {
+            let query = self.pending.pop_front().unwrap();
+            latest = Some(Duration::from_nanos(query.get().into()));
+        }
+        latest
+    }
+}
+
+/*************/
+/* Frame log */
+/*************/
+
+// Writes the timings of every frame as CSV, one line per frame, times in milliseconds
+pub struct FrameLog<W: Write> {
+    out: W,
+    frames: u64,
+}
+
+impl FrameLog<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<FrameLog<BufWriter<File>>> {
+        FrameLog::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> FrameLog<W> {
+    pub const HEADER: &'static str = "frame,frame_ms,update_ms,draw_ms,gpu_ms";
+
+    pub fn new(mut out: W) -> io::Result<FrameLog<W>> {
+        writeln!(out, "{}", Self::HEADER)?;
+        Ok(FrameLog { out, frames: 0 })
+    }
+
+    // Appends a line for *sample*, leaving the GPU time empty when there is none
+    pub fn write(&mut self, sample: &FrameSample) -> io::Result<()> {
+        let gpu = sample
+            .gpu
+            .map_or(String::new(), |gpu| format!("{:.3}", ms(gpu)));
+        writeln!(
+            self.out,
+            "{},{:.3},{:.3},{:.3},{}",
+            self.frames,
+            ms(sample.frame),
+            ms(sample.update),
+            ms(sample.draw),
+            gpu
+        )?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(frame_ms: u64) -> FrameSample {
+        FrameSample {
+            frame: Duration::from_millis(frame_ms),
+            update: Duration::from_millis(1),
+            draw: Duration::from_millis(2),
+            gpu: None,
+        }
+    }
+
+    #[test]
+    fn summarizes_the_last_frames() {
+        let mut stats = FrameStats::default();
+        assert!(stats.summary().is_none());
+
+        // a hitch long ago falls out of the window
+        stats.push(sample(500));
+        for i in 0..WINDOW as u64 {
+            stats.push(sample(if i == 7 { 40 } else { 10 + i % 3 }));
+        }
+        let summary = stats.summary().unwrap();
+        assert_eq!(summary.min, Duration::from_millis(10));
+        assert_eq!(summary.max, Duration::from_millis(40));
+        assert_eq!(summary.p99, Duration::from_millis(12));
+        assert!(
+            (summary.fps - 1000.0 / 11.24).abs() < 0.5,
+            "{}",
+            summary.fps
+        );
+        assert_eq!(summary.draw, Duration::from_millis(2));
+        assert_eq!(summary.gpu, None);
+        assert!(stats.overlay_text().contains("gpu    n/a"));
+    }
+
+    #[test]
+    fn averages_reported_gpu_times() {
+        let mut stats = FrameStats::default();
+        for gpu in [None, Some(4), None, Some(6)] {
+            stats.push(FrameSample {
+                gpu: gpu.map(Duration::from_millis),
+                ..sample(16)
+            });
+        }
+        assert_eq!(stats.summary().unwrap().gpu, Some(Duration::from_millis(5)));
+        assert!(stats.overlay_text().contains("gpu    5.00 ms"));
+    }
+
+    #[test]
+    fn logs_csv() {
+        let mut log = FrameLog::new(Vec::new()).unwrap();
+        log.write(&sample(16)).unwrap();
+        log.write(&FrameSample {
+            gpu: Some(Duration::from_micros(2500)),
+            ..sample(17)
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(log.out).unwrap(),
+            "frame,frame_ms,update_ms,draw_ms,gpu_ms\n\
+             0,16.000,1.000,2.000,\n\
+             1,17.000,1.000,2.000,2.500\n"
+        );
+    }
+}
@@ -0,0 +1,14 @@
+// Helpers shared by the tests of several modules
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+// A fresh, empty directory for the test called *name*. The process id keeps test runs going at the
+// same time from sharing it.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ray_{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
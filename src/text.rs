@@ -0,0 +1,308 @@
+use glium::index::{NoIndices, PrimitiveType};
+use glium::texture::{ClientFormat, MipmapsOption, RawImage2d, UncompressedFloatFormat};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
+use glium::{Blend, DrawParameters, Surface, Texture2d};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+pub const GLYPH_WIDTH: usize = 5;
+pub const GLYPH_HEIGHT: usize = 7;
+
+// glyphs are laid out with a pixel of space to the right and below
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+// pixels between the edge of a text box and the text in it
+const PADDING: f32 = 4.0;
+
+/********/
+/* Font */
+/********/
+
+// A fixed width bitmap font
+pub struct Font {
+    // rows of each glyph from the top, with the leftmost pixel in the highest of the low
+    // `GLYPH_WIDTH` bits
+    glyphs: HashMap<char, [u8; GLYPH_HEIGHT]>,
+}
+
+impl Default for Font {
+    fn default() -> Font {
+        Font::parse(include_str!("../res/font.txt")).expect("built in font is valid")
+    }
+}
+
+impl Font {
+    // Text format: `;` starts a comment line, and each glyph is a line of `:` followed by the
+    // character, then `GLYPH_HEIGHT` rows of `GLYPH_WIDTH` pixels, `#` for set and `.` for clear
+    pub fn parse(src: &str) -> Result<Font, String> {
+        let mut glyphs = HashMap::new();
+        let mut lines = src
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty() && !l.starts_with(';'));
+
+        while let Some((line_no, header)) = lines.next() {
+            let c = match header.strip_prefix(':') {
+                // editors like to strip the trailing space off the header of the space glyph
+                Some(c) => c.chars().next().unwrap_or(' '),
+                None => return Err(format!("line {}: expected a glyph header", line_no + 1)),
+            };
+
+            let mut rows = [0u8; GLYPH_HEIGHT];
+            for row in rows.iter_mut() {
+                let (line_no, line) = lines
+                    .next()
+                    .ok_or_else(|| format!("glyph {:?} is cut short", c))?;
+                if line.len() != GLYPH_WIDTH || !line.chars().all(|p| p == '#' || p == '.') {
+                    return Err(format!(
+                        "line {}: invalid glyph row {:?}",
+                        line_no + 1,
+                        line
+                    ));
+                }
+                *row = line
+                    .chars()
+                    .fold(0, |bits, p| (bits << 1) | (p == '#') as u8);
+            }
+            glyphs.insert(c, rows);
+        }
+
+        Ok(Font { glyphs })
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&[u8; GLYPH_HEIGHT]> {
+        self.glyphs.get(&c)
+    }
+
+    // Whether pixel *x*, *y* (from the top left) of the glyph for *c* is set
+    pub fn pixel(&self, c: char, x: usize, y: usize) -> bool {
+        self.glyph(c)
+            .is_some_and(|rows| rows[y] & (1 << (GLYPH_WIDTH - 1 - x)) != 0)
+    }
+}
+
+// Splits *text* into lines at most *max_cols* characters long, breaking at newlines and wherever
+// a line runs out of room. Tabs become four spaces.
+pub fn wrap(text: &str, max_cols: usize) -> Vec<String> {
+    let max_cols = max_cols.max(1);
+    let mut out = Vec::new();
+    for line in text.replace('\t', "    ").lines() {
+        let chars: Vec<char> = line.chars().collect();
+        if chars.is_empty() {
+            out.push(String::new());
+        }
+        for chunk in chars.chunks(max_cols) {
+            out.push(chunk.iter().collect());
+        }
+    }
+    out
+}
+
+/*****************/
+/* Text renderer */
+/*****************/
+
+#[derive(Copy, Clone)]
+struct TextVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+implement_vertex!(TextVertex, position, uv, color);
+
+// Draws text in boxes over whatever has been rendered. Its shaders are built in rather than
+// loaded from the shader directory, so that it keeps working to show errors in the others.
+pub struct TextRenderer {
+    pub font: Font,
+
+    // size of a font pixel on screen, in pixels
+    pub scale: f32,
+
+    program: glium::Program,
+
+    // every printable ASCII glyph side by side, after a solid cell used for box backgrounds
+    atlas: Texture2d,
+}
+
+impl TextRenderer {
+    // characters in the atlas after the solid cell, in order
+    const FIRST_CHAR: u8 = b' ';
+    const LAST_CHAR: u8 = b'~';
+
+    pub fn new(display: &glium::Display) -> TextRenderer {
+        let font = Font::default();
+
+        let cells = (Self::LAST_CHAR - Self::FIRST_CHAR) as usize + 2;
+        let (width, height) = (cells * CELL_WIDTH, CELL_HEIGHT);
+        let mut data = vec![0u8; width * height];
+        for y in 0..height {
+            for x in 0..CELL_WIDTH {
+                data[y * width + x] = 255;
+            }
+        }
+        for (i, c) in (Self::FIRST_CHAR..=Self::LAST_CHAR).enumerate() {
+            for y in 0..GLYPH_HEIGHT {
+                for x in 0..GLYPH_WIDTH {
+                    if font.pixel(c as char, x, y) {
+                        data[y * width + (i + 1) * CELL_WIDTH + x] = 255;
+                    }
+                }
+            }
+        }
+        let image = RawImage2d {
+            data: Cow::Owned(data),
+            width: width as u32,
+            height: height as u32,
+            format: ClientFormat::U8,
+        };
+        let atlas = Texture2d::with_format(
+            display,
+            image,
+            UncompressedFloatFormat::U8,
+            MipmapsOption::NoMipmap,
+        )
+        .expect("Unable to create font texture");
+
+        let program = glium::Program::from_source(
+            display,
+            include_str!("shaders/text.vert"),
+            include_str!("shaders/text.frag"),
+            None,
+        )
+        .expect("Unable to compile text shader");
+
+        TextRenderer {
+            font,
+            scale: 2.0,
+            program,
+            atlas,
+        }
+    }
+
+    // Size in pixels of the box `draw` would put *text* in on a target of the given width
+    pub fn measure(&self, text: &str, target_width: u32) -> (f32, f32) {
+        let lines = wrap(text, self.max_cols(target_width));
+        let cols = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        (
+            cols as f32 * CELL_WIDTH as f32 * self.scale + 2.0 * PADDING,
+            lines.len() as f32 * CELL_HEIGHT as f32 * self.scale + 2.0 * PADDING,
+        )
+    }
+
+    fn max_cols(&self, target_width: u32) -> usize {
+        ((target_width as f32 - 2.0 * PADDING) / (CELL_WIDTH as f32 * self.scale)) as usize
+    }
+
+    // Draws *text* in *color* on a translucent box with its top left corner at *pos*, in pixels
+    // from the top left of *target*, wrapping lines at the right edge of the target
+    pub fn draw<S: Surface>(
+        &self,
+        display: &glium::Display,
+        target: &mut S,
+        text: &str,
+        pos: (f32, f32),
+        color: [f32; 4],
+    ) {
+        let (width, height) = target.get_dimensions();
+        let lines = wrap(text, self.max_cols(width));
+        let (box_w, box_h) = self.measure(text, width);
+
+        let mut vertices = Vec::new();
+        let mut quad = |(x, y): (f32, f32), (w, h): (f32, f32), cell: usize, color: [f32; 4]| {
+            // to normalized device coordinates, which go bottom to top
+            let ndc = |px: f32, py: f32| {
+                [
+                    px / width as f32 * 2.0 - 1.0,
+                    1.0 - py / height as f32 * 2.0,
+                ]
+            };
+            let cells = (Self::LAST_CHAR - Self::FIRST_CHAR) as f32 + 2.0;
+            let (u0, u1) = (cell as f32 / cells, (cell + 1) as f32 / cells);
+            let corners = [
+                ((x, y), [u0, 0.0]),
+                ((x + w, y), [u1, 0.0]),
+                ((x, y + h), [u0, 1.0]),
+                ((x + w, y + h), [u1, 1.0]),
+            ];
+            for &i in &[0, 1, 2, 2, 1, 3] {
+                let ((px, py), uv) = corners[i];
+                vertices.push(TextVertex {
+                    position: ndc(px, py),
+                    uv,
+                    color,
+                });
+            }
+        };
+
+        quad(pos, (box_w, box_h), 0, [0.0, 0.0, 0.0, 0.6]);
+        let (cell_w, cell_h) = (
+            CELL_WIDTH as f32 * self.scale,
+            CELL_HEIGHT as f32 * self.scale,
+        );
+        for (row, line) in lines.iter().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                let c = if self.font.glyph(c).is_some() { c } else { '?' };
+                if c == ' ' || !(Self::FIRST_CHAR..=Self::LAST_CHAR).contains(&(c as u8)) {
+                    continue;
+                }
+                let cell = (c as u8 - Self::FIRST_CHAR) as usize + 1;
+                let x = pos.0 + PADDING + col as f32 * cell_w;
+                let y = pos.1 + PADDING + row as f32 * cell_h;
+                quad((x, y), (cell_w, cell_h), cell, color);
+            }
+        }
+
+        let vertex_buffer =
+            glium::VertexBuffer::new(display, &vertices).expect("Unable to upload text");
+        let uniforms = uniform! {
+            font: self.atlas
+                .sampled()
+                .magnify_filter(MagnifySamplerFilter::Nearest)
+                .minify_filter(MinifySamplerFilter::Nearest),
+        };
+        let params = DrawParameters {
+            blend: Blend::alpha_blending(),
+            ..Default::default()
+        };
+        target
+            .draw(
+                &vertex_buffer,
+                NoIndices(PrimitiveType::TrianglesList),
+                &self.program,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_font_covers_ascii() {
+        let font = Font::default();
+        for c in ' '..='~' {
+            assert!(font.glyph(c).is_some(), "no glyph for {:?}", c);
+        }
+        // the crossbar of an A, and nothing in a space
+        assert!((0..GLYPH_WIDTH).all(|x| font.pixel('A', x, 3)));
+        assert!(font.glyph(' ').unwrap().iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn rejects_malformed_fonts() {
+        assert!(Font::parse(":A\n#####\n").is_err());
+        assert!(Font::parse(":A\n#####\n#...#\n#...#\n#...#\n#...#\n#...#\n#..x#\n").is_err());
+        assert!(Font::parse("#####\n").is_err());
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        assert_eq!(wrap("abcdefg\n\nhi", 3), ["abc", "def", "g", "", "hi"]);
+        assert_eq!(wrap("\tx", 80), ["    x"]);
+    }
+}
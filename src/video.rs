@@ -0,0 +1,173 @@
+use crate::console;
+use crate::image::Image;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::Duration;
+
+// Where the frames of a recording go
+enum Sink {
+    // numbered PNGs in a directory
+    Frames(PathBuf),
+
+    // raw RGB frames written to the standard input of an encoder
+    Encoder(Child, ChildStdin),
+}
+
+// Records frames at a fixed frame rate, whatever the rate they are rendered at: the game is
+// advanced by `frame_time` for every frame recorded, so recordings are smooth even when every
+// frame takes longer than that to render
+pub struct VideoRecorder {
+    pub fps: u32,
+
+    // size of every frame, in pixels
+    pub size: (u32, u32),
+
+    // frames recorded so far
+    pub frames: u32,
+
+    sink: Sink,
+}
+
+impl VideoRecorder {
+    // Records into numbered PNGs in *dir*, which is created if needed
+    pub fn to_dir(dir: &Path, fps: u32, size: (u32, u32)) -> io::Result<VideoRecorder> {
+        fs::create_dir_all(dir)?;
+        Ok(VideoRecorder {
+            fps,
+            size,
+            frames: 0,
+            sink: Sink::Frames(dir.to_path_buf()),
+        })
+    }
+
+    // Records by piping raw 8-bit RGB frames, top row first, to the standard input of the
+    // *command*, split into words like a console command. `{width}`, `{height}` and `{fps}` in
+    // it are replaced by the size and rate of the frames, e.g.
+    //
+    //   ffmpeg -f rawvideo -pix_fmt rgb24 -s {width}x{height} -r {fps} -i - level.mp4
+    pub fn to_encoder(command: &str, fps: u32, size: (u32, u32)) -> io::Result<VideoRecorder> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let words = console::parse(command).map_err(|err| invalid(err.to_string()))?;
+        let words: Vec<String> = words
+            .iter()
+            .map(|word| {
+                word.replace("{width}", &size.0.to_string())
+                    .replace("{height}", &size.1.to_string())
+                    .replace("{fps}", &fps.to_string())
+            })
+            .collect();
+        let (program, args) = words
+            .split_first()
+            .ok_or_else(|| invalid("empty encoder command".to_string()))?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        Ok(VideoRecorder {
+            fps,
+            size,
+            frames: 0,
+            sink: Sink::Encoder(child, stdin),
+        })
+    }
+
+    // simulated time between two frames
+    pub fn frame_time(&self) -> Duration {
+        Duration::from_secs(1) / self.fps
+    }
+
+    pub fn write(&mut self, image: &Image) -> io::Result<()> {
+        if (image.width as u32, image.height as u32) != self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "frame is {}x{}, not {}x{}",
+                    image.width, image.height, self.size.0, self.size.1
+                ),
+            ));
+        }
+        match &mut self.sink {
+            Sink::Frames(dir) => {
+                image.save_png(&dir.join(format!("frame_{:05}.png", self.frames)))?
+            }
+            Sink::Encoder(_, stdin) => {
+                let data: Vec<u8> = image
+                    .pixels
+                    .iter()
+                    .flat_map(|&(r, g, b)| [r, g, b])
+                    .collect();
+                stdin.write_all(&data)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Ends the recording, waiting for the encoder to finish, and returns how many frames it has
+    pub fn finish(self) -> io::Result<u32> {
+        if let Sink::Encoder(mut child, stdin) = self.sink {
+            // closing its input tells the encoder there are no more frames
+            drop(stdin);
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("encoder exited with {}", status)));
+            }
+        }
+        Ok(self.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn frame(shade: u8) -> Image {
+        Image {
+            width: 2,
+            height: 1,
+            pixels: vec![(shade, 0, 0), (0, shade, 0)],
+        }
+    }
+
+    #[test]
+    fn writes_numbered_frames() {
+        let dir = temp_dir("video_frames");
+        let mut video = VideoRecorder::to_dir(&dir, 30, (2, 1)).unwrap();
+        assert_eq!(video.frame_time(), Duration::from_secs(1) / 30);
+        video.write(&frame(10)).unwrap();
+        video.write(&frame(20)).unwrap();
+        assert!(video.write(&Image::blank(3, 1)).is_err());
+        assert_eq!(video.finish().unwrap(), 2);
+
+        let second = Image::load(&dir.join("frame_00001.png")).unwrap();
+        assert_eq!(second.pixels, frame(20).pixels);
+        assert!(!dir.join("frame_00002.png").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pipes_raw_frames_to_an_encoder() {
+        let dir = temp_dir("video_encoder");
+        // the placeholders are filled in wherever they are in the command
+        let out = dir.join("{width}x{height}_{fps}.raw");
+        let command = format!("sh -c \"cat > '{}'\"", out.display());
+
+        let mut video = VideoRecorder::to_encoder(&command, 24, (2, 1)).unwrap();
+        video.write(&frame(1)).unwrap();
+        video.write(&frame(2)).unwrap();
+        assert_eq!(video.finish().unwrap(), 2);
+        let written = fs::read(dir.join("2x1_24.raw")).unwrap();
+        assert_eq!(written, [1, 0, 0, 0, 1, 0, 2, 0, 0, 0, 2, 0]);
+
+        assert!(VideoRecorder::to_encoder("sh -c \"exit 3\"", 24, (2, 1))
+            .unwrap()
+            .finish()
+            .is_err());
+    }
+}
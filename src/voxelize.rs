@@ -0,0 +1,778 @@
+use na::{vector, Vector3};
+
+use crate::image::Image;
+use crate::types::{Color, Dimension3};
+use crate::world::{DenseGrid, Space, Voxel};
+use crate::world_loader::{Level, LevelLoader, LoadError};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/********/
+/* Mesh */
+/********/
+
+pub struct Triangle {
+    pub verts: [Vector3<f32>; 3],
+
+    // color of the face, if the file had one for it
+    pub color: Option<Color>,
+}
+
+pub struct Mesh {
+    pub triangles: Vec<Triangle>,
+}
+
+// Surface material of an OBJ file, as read from its .mtl library
+#[derive(Default)]
+struct ObjMaterial {
+    diffuse: Option<Color>,
+    texture: Option<Image>,
+}
+
+fn float_to_channel(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+impl Mesh {
+    // Parses a Wavefront OBJ file. Faces take their color from the texture of their material
+    // (sampled at the face's center), the material's diffuse color, or the vertex colors, in that
+    // order of preference. *dir* is the directory material libraries are looked up in.
+    pub fn from_obj(src: &str, dir: Option<&Path>) -> Result<Mesh, LoadError> {
+        let parse_err = |line_no: usize, msg: &str| {
+            LoadError::Parse(format!("obj line {}: {}", line_no + 1, msg))
+        };
+
+        let mut positions: Vec<Vector3<f32>> = Vec::new();
+        let mut vertex_colors: Vec<Option<[f32; 3]>> = Vec::new();
+        let mut tex_coords: Vec<(f32, f32)> = Vec::new();
+        let mut materials: HashMap<String, ObjMaterial> = HashMap::new();
+        let mut current_material: Option<String> = None;
+        let mut triangles = Vec::new();
+
+        for (line_no, line) in src.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let floats = |tokens: std::str::SplitWhitespace| {
+                tokens
+                    .map(|t| t.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| parse_err(line_no, "invalid number"))
+            };
+
+            match tokens.next() {
+                Some("v") => {
+                    let v = floats(tokens)?;
+                    if v.len() < 3 {
+                        return Err(parse_err(line_no, "vertex needs 3 coordinates"));
+                    }
+                    positions.push(vector![v[0], v[1], v[2]]);
+                    // some exporters append an rgb color to each vertex
+                    vertex_colors.push(if v.len() >= 6 {
+                        Some([v[3], v[4], v[5]])
+                    } else {
+                        None
+                    });
+                }
+                Some("vt") => {
+                    let vt = floats(tokens)?;
+                    if vt.len() < 2 {
+                        return Err(parse_err(line_no, "texture coordinate needs 2 components"));
+                    }
+                    tex_coords.push((vt[0], vt[1]));
+                }
+                Some("mtllib") => {
+                    for name in tokens {
+                        let path = dir.map(|d| d.join(name)).unwrap_or_else(|| name.into());
+                        // a missing material library only costs us the colors
+                        if let Ok(mtl_src) = fs::read_to_string(&path) {
+                            materials.extend(parse_mtl(&mtl_src, path.parent()));
+                        }
+                    }
+                }
+                Some("usemtl") => current_material = tokens.next().map(str::to_string),
+                Some("f") => {
+                    // each corner is "v", "v/vt", "v//vn" or "v/vt/vn", with 1-based or negative
+                    // (relative to the end) indices
+                    let resolve = |idx: &str, len: usize| -> Option<usize> {
+                        let i = idx.parse::<i64>().ok()?;
+                        let i = if i < 0 { len as i64 + i } else { i - 1 };
+                        if i >= 0 && (i as usize) < len {
+                            Some(i as usize)
+                        } else {
+                            None
+                        }
+                    };
+                    let corners = tokens
+                        .map(|corner| {
+                            let mut parts = corner.split('/');
+                            let v = parts.next().and_then(|p| resolve(p, positions.len()))?;
+                            let vt = parts.next().and_then(|p| resolve(p, tex_coords.len()));
+                            Some((v, vt))
+                        })
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| parse_err(line_no, "invalid face index"))?;
+                    if corners.len() < 3 {
+                        return Err(parse_err(line_no, "face needs at least 3 vertices"));
+                    }
+
+                    let material = current_material.as_ref().and_then(|m| materials.get(m));
+
+                    // fan triangulation of the polygon
+                    for i in 1..corners.len() - 1 {
+                        let tri = [corners[0], corners[i], corners[i + 1]];
+                        let verts = [
+                            positions[tri[0].0],
+                            positions[tri[1].0],
+                            positions[tri[2].0],
+                        ];
+
+                        let texture_color =
+                            material
+                                .and_then(|m| m.texture.as_ref())
+                                .and_then(|texture| {
+                                    let uvs = [tri[0].1?, tri[1].1?, tri[2].1?];
+                                    Some(sample_face_texture(texture, uvs.map(|t| tex_coords[t])))
+                                });
+                        let vertex_color = || {
+                            let colors = [
+                                vertex_colors[tri[0].0]?,
+                                vertex_colors[tri[1].0]?,
+                                vertex_colors[tri[2].0]?,
+                            ];
+                            let avg = |c: usize| (colors[0][c] + colors[1][c] + colors[2][c]) / 3.0;
+                            Some((
+                                float_to_channel(avg(0)),
+                                float_to_channel(avg(1)),
+                                float_to_channel(avg(2)),
+                            ))
+                        };
+
+                        triangles.push(Triangle {
+                            verts,
+                            color: texture_color
+                                .or_else(|| material.and_then(|m| m.diffuse))
+                                .or_else(vertex_color),
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(Mesh { triangles })
+    }
+
+    // Parses an ASCII or binary STL file. Binary files may carry a 15-bit color per face in the
+    // attribute word (VisCAM/SolidView convention: bit 15 set, then red, green, blue from the top).
+    pub fn from_stl(data: &[u8]) -> Result<Mesh, LoadError> {
+        let is_ascii = data.starts_with(b"solid")
+            && std::str::from_utf8(&data[..data.len().min(1024)])
+                .map(|head| head.contains("facet"))
+                .unwrap_or(false);
+
+        if is_ascii {
+            let src = std::str::from_utf8(data)
+                .map_err(|_| LoadError::Parse("stl: invalid utf-8".to_string()))?;
+            let mut verts = Vec::new();
+            let mut triangles = Vec::new();
+            for line in src.lines() {
+                let mut tokens = line.split_whitespace();
+                if tokens.next() != Some("vertex") {
+                    continue;
+                }
+                let v = tokens
+                    .map(|t| t.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()
+                    .filter(|v| v.len() == 3)
+                    .ok_or_else(|| LoadError::Parse("stl: invalid vertex".to_string()))?;
+                verts.push(vector![v[0], v[1], v[2]]);
+                if verts.len() == 3 {
+                    triangles.push(Triangle {
+                        verts: [verts[0], verts[1], verts[2]],
+                        color: None,
+                    });
+                    verts.clear();
+                }
+            }
+            return Ok(Mesh { triangles });
+        }
+
+        let truncated = || LoadError::Parse("stl: truncated file".to_string());
+        let count_bytes = data.get(80..84).ok_or_else(truncated)?;
+        let count = u32::from_le_bytes([
+            count_bytes[0],
+            count_bytes[1],
+            count_bytes[2],
+            count_bytes[3],
+        ]) as usize;
+
+        let read_f32 = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        let read_vec = |b: &[u8]| vector![read_f32(&b[0..]), read_f32(&b[4..]), read_f32(&b[8..])];
+
+        // the count comes from the file, so it is checked against the data before it is trusted
+        let size = count.checked_mul(50).and_then(|n| n.checked_add(84));
+        if size.is_none_or(|size| data.len() < size) {
+            return Err(truncated());
+        }
+
+        let mut triangles = Vec::with_capacity(count);
+        for i in 0..count {
+            let start = 84 + 50 * i;
+            let facet = data.get(start..start + 50).ok_or_else(truncated)?;
+            // the first 12 bytes are the facet normal, which we recompute when needed
+            let attribute = u16::from_le_bytes([facet[48], facet[49]]);
+            let color = if attribute & 0x8000 != 0 {
+                let channel = |shift: u16| (((attribute >> shift) & 0x1f) * 255 / 31) as u8;
+                Some((channel(10), channel(5), channel(0)))
+            } else {
+                None
+            };
+            triangles.push(Triangle {
+                verts: [
+                    read_vec(&facet[12..]),
+                    read_vec(&facet[24..]),
+                    read_vec(&facet[36..]),
+                ],
+                color,
+            });
+        }
+        Ok(Mesh { triangles })
+    }
+
+    // whether every edge is shared by exactly two faces, i.e. the mesh has an inside
+    pub fn is_closed(&self) -> bool {
+        let key = |v: &Vector3<f32>| (v.x.to_bits(), v.y.to_bits(), v.z.to_bits());
+        let mut edges = HashMap::new();
+        for tri in &self.triangles {
+            for i in 0..3 {
+                let (a, b) = (key(&tri.verts[i]), key(&tri.verts[(i + 1) % 3]));
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        !edges.is_empty() && edges.values().all(|&count| count == 2)
+    }
+
+    // axis-aligned bounds of all vertices, as (min, max)
+    pub fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let mut verts = self.triangles.iter().flat_map(|t| t.verts.iter());
+        let first = *verts.next()?;
+        Some(verts.fold((first, first), |(lo, hi), v| (lo.inf(v), hi.sup(v))))
+    }
+}
+
+// color of a texture at the center of a face with the given texture coordinates
+fn sample_face_texture(texture: &Image, uvs: [(f32, f32); 3]) -> Color {
+    let u = (uvs[0].0 + uvs[1].0 + uvs[2].0) / 3.0;
+    let v = (uvs[0].1 + uvs[1].1 + uvs[2].1) / 3.0;
+    // texture coordinates start at the bottom left, images at the top left
+    texture.sample(u.rem_euclid(1.0), 1.0 - v.rem_euclid(1.0))
+}
+
+// Reads the diffuse color and texture of each material in an OBJ material library
+fn parse_mtl(src: &str, dir: Option<&Path>) -> HashMap<String, ObjMaterial> {
+    let mut materials = HashMap::new();
+    let mut current: Option<String> = None;
+
+    for line in src.lines() {
+        let mut tokens = line.split_whitespace();
+        match (tokens.next(), &current) {
+            (Some("newmtl"), _) => {
+                current = tokens.next().map(str::to_string);
+                if let Some(name) = &current {
+                    materials.insert(name.clone(), ObjMaterial::default());
+                }
+            }
+            (Some("Kd"), Some(name)) => {
+                let rgb: Vec<f32> = tokens.filter_map(|t| t.parse().ok()).collect();
+                if let (Some(mat), [r, g, b]) = (materials.get_mut(name), rgb.as_slice()) {
+                    mat.diffuse = Some((
+                        float_to_channel(*r),
+                        float_to_channel(*g),
+                        float_to_channel(*b),
+                    ));
+                }
+            }
+            (Some("map_Kd"), Some(name)) => {
+                // the file name comes last, after any options
+                if let Some(file) = tokens.last() {
+                    let path = dir.map(|d| d.join(file)).unwrap_or_else(|| file.into());
+                    if let (Some(mat), Ok(texture)) = (materials.get_mut(name), Image::load(&path))
+                    {
+                        mat.texture = Some(texture);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    materials
+}
+
+/*************/
+/* Voxelizer */
+/*************/
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VoxelizeMode {
+    // only voxels touched by the mesh's faces are filled
+    Surface,
+
+    // everything inside the mesh is filled too; the mesh should be closed
+    Solid,
+}
+
+pub struct Voxelizer {
+    pub mode: VoxelizeMode,
+
+    // empty voxels left between the mesh and the sides of the world
+    pub margin: usize,
+
+    // material of faces without a color of their own
+    pub default_material: Voxel,
+}
+
+impl Default for Voxelizer {
+    fn default() -> Voxelizer {
+        Voxelizer {
+            mode: VoxelizeMode::Solid,
+            margin: 1,
+            default_material: Voxel::Stone,
+        }
+    }
+}
+
+impl Voxelizer {
+    fn material(&self, tri: &Triangle) -> Voxel {
+        tri.color
+            .map(Voxel::nearest_to_color)
+            .unwrap_or(self.default_material)
+    }
+
+    // Scales the mesh uniformly to fit inside the world, centered horizontally and resting on
+    // the floor, and fills the voxels it covers
+    pub fn voxelize(&self, mesh: &Mesh, shape: Dimension3) -> DenseGrid<Voxel> {
+        let mut voxels = DenseGrid::fill(shape, Voxel::Empty);
+        let (lo, hi) = match mesh.bounds() {
+            Some(bounds) => bounds,
+            None => return voxels,
+        };
+
+        let room = vector![
+            shape.0.saturating_sub(2 * self.margin) as f32,
+            shape.1.saturating_sub(self.margin) as f32,
+            shape.2.saturating_sub(2 * self.margin) as f32
+        ];
+        let size = hi - lo;
+        let scale = (0..3)
+            .filter(|&i| size[i] > 0.0)
+            .map(|i| room[i] / size[i])
+            .fold(f32::INFINITY, f32::min);
+        let scale = if scale.is_finite() { scale } else { 1.0 };
+        let offset = vector![
+            (shape.0 as f32 - size.x * scale) / 2.0,
+            0.0,
+            (shape.2 as f32 - size.z * scale) / 2.0
+        ];
+        // shrink ever so slightly so faces lying exactly on the far bounds don't spill into the
+        // next voxel over
+        let to_world = |v: &Vector3<f32>| (v - lo) * (scale * 0.9999) + offset;
+
+        let triangles: Vec<([Vector3<f32>; 3], Voxel)> = mesh
+            .triangles
+            .iter()
+            .map(|t| {
+                (
+                    [
+                        to_world(&t.verts[0]),
+                        to_world(&t.verts[1]),
+                        to_world(&t.verts[2]),
+                    ],
+                    self.material(t),
+                )
+            })
+            .collect();
+
+        if self.mode == VoxelizeMode::Solid {
+            fill_interior(&mut voxels, &triangles);
+        }
+
+        // the surface is drawn last so faces keep their own colors over the interior fill
+        for (verts, vox) in &triangles {
+            rasterize_triangle(&mut voxels, verts, *vox);
+        }
+
+        voxels
+    }
+}
+
+fn voxel_at(shape: &Dimension3, p: &Vector3<f32>) -> Option<(usize, usize, usize)> {
+    if p.x < 0.0 || p.y < 0.0 || p.z < 0.0 {
+        return None;
+    }
+    let idx = (p.x as usize, p.y as usize, p.z as usize);
+    if idx.0 < shape.0 && idx.1 < shape.1 && idx.2 < shape.2 {
+        Some(idx)
+    } else {
+        None
+    }
+}
+
+// Marks every voxel the triangle passes through, by sampling it at under half a voxel spacing
+fn rasterize_triangle(voxels: &mut DenseGrid<Voxel>, [a, b, c]: &[Vector3<f32>; 3], vox: Voxel) {
+    let shape = *voxels.shape();
+    let longest = (b - a).norm().max((c - a).norm()).max((c - b).norm());
+    let n = (longest * 2.0).ceil().max(1.0) as usize;
+
+    for i in 0..=n {
+        for j in 0..=(n - i) {
+            let p = a + (b - a) * (i as f32 / n as f32) + (c - a) * (j as f32 / n as f32);
+            if let Some(idx) = voxel_at(&shape, &p) {
+                voxels[idx] = vox;
+            }
+        }
+    }
+}
+
+// Fills the inside of a closed mesh by casting a ray along +x through the center of each row of
+// voxels and toggling between inside and outside at every face it crosses
+fn fill_interior(voxels: &mut DenseGrid<Voxel>, triangles: &[([Vector3<f32>; 3], Voxel)]) {
+    let shape = *voxels.shape();
+
+    for z in 0..shape.2 {
+        for y in 0..shape.1 {
+            let (py, pz) = (y as f32 + 0.5, z as f32 + 0.5);
+
+            let mut crossings: Vec<(f32, Voxel)> = triangles
+                .iter()
+                .filter_map(|([a, b, c], vox)| {
+                    let x = ray_crossing(py, pz, [a, b, c])?;
+                    Some((x, *vox))
+                })
+                .collect();
+            // a NaN vertex makes for NaN crossings, which sort after the rest rather than panic
+            crossings.sort_by(|l, r| l.0.total_cmp(&r.0));
+
+            for pair in crossings.chunks(2) {
+                if let [(enter, vox), (exit, _)] = pair {
+                    let start = (enter - 0.5).ceil().max(0.0) as usize;
+                    let end = ((exit - 0.5).floor() + 1.0).max(0.0) as usize;
+                    for x in start..end.min(shape.0) {
+                        voxels[(x, y, z)] = *vox;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Where a ray along +x at the given y and z crosses the triangle, if it does. A ray through an edge
+// or corner shared by several faces counts as crossing exactly one of them that lies on the same
+// side, as two would cancel out and leave the row unfilled.
+fn ray_crossing(py: f32, pz: f32, [a, b, c]: [&Vector3<f32>; 3]) -> Option<f32> {
+    // twice the signed area of the triangle projected onto yz, counterclockwise being positive
+    let det = (b.y - a.y) * (c.z - a.z) - (c.y - a.y) * (b.z - a.z);
+    if det.abs() < 1e-9 {
+        return None;
+    }
+    let (b, c) = if det > 0.0 { (b, c) } else { (c, b) };
+
+    // which side of the edge from p to q the ray is on, positive being inside. Both faces along an
+    // edge work it out from the same end, so they agree exactly on where it lies.
+    let side = |p: &Vector3<f32>, q: &Vector3<f32>| {
+        let (from, to, sign) = if (p.y, p.z) < (q.y, q.z) {
+            (p, q, 1.0)
+        } else {
+            (q, p, -1.0)
+        };
+        let e = (to.y - from.y) * (pz - from.z) - (to.z - from.z) * (py - from.y);
+        if e != 0.0 {
+            sign * e > 0.0
+        } else {
+            // on the edge itself, which belongs to the face it is the top or left edge of
+            let (dy, dz) = (q.y - p.y, q.z - p.z);
+            dz < 0.0 || (dz == 0.0 && dy < 0.0)
+        }
+    };
+    if !(side(a, b) && side(b, c) && side(c, a)) {
+        return None;
+    }
+
+    // barycentric coordinates of the ray, to find how far along it the triangle is
+    let det = det.abs();
+    let u = ((py - a.y) * (c.z - a.z) - (c.y - a.y) * (pz - a.z)) / det;
+    let v = ((b.y - a.y) * (pz - a.z) - (py - a.y) * (b.z - a.z)) / det;
+    Some(a.x + u * (b.x - a.x) + v * (c.x - a.x))
+}
+
+/**********/
+/* Loader */
+/**********/
+
+pub struct MeshLoader {
+    pub voxelizer: Voxelizer,
+}
+
+impl LevelLoader for MeshLoader {
+    fn extensions(&self) -> &[&str] {
+        &["obj", "stl"]
+    }
+
+    fn load(&self, path: &Path, shape: Dimension3) -> Result<Level, LoadError> {
+        let data = fs::read(path)?;
+        let is_stl = path
+            .extension()
+            .map(|e| e.eq_ignore_ascii_case("stl"))
+            .unwrap_or(false);
+
+        let mesh = if is_stl {
+            Mesh::from_stl(&data)?
+        } else {
+            let src = String::from_utf8(data)
+                .map_err(|_| LoadError::Parse("obj: invalid utf-8".to_string()))?;
+            Mesh::from_obj(&src, path.parent())?
+        };
+
+        if self.voxelizer.mode == VoxelizeMode::Solid && !mesh.is_closed() {
+            // an open mesh has no inside to fill, so only its surface can be kept
+            let voxelizer = Voxelizer {
+                mode: VoxelizeMode::Surface,
+                ..self.voxelizer
+            };
+            return Ok(Level {
+                space: Space::from_voxels(voxelizer.voxelize(&mesh, shape)),
+                warnings: vec![format!(
+                    "mesh {:?} is not closed, voxelizing its surface only",
+                    path
+                )],
+            });
+        }
+
+        Ok(Space::from_voxels(self.voxelizer.voxelize(&mesh, shape)).into())
+    }
+
+    fn dependencies(&self, path: &Path) -> Vec<PathBuf> {
+        let mut files = vec![path.to_path_buf()];
+        // binary STL files aren't text, and neither kind refers to other files
+        let src = match fs::read_to_string(path) {
+            Ok(src) => src,
+            Err(_) => return files,
+        };
+
+        // material libraries, and the textures they use
+        let resolve = |dir: Option<&Path>, name: &str| -> PathBuf {
+            dir.map(|d| d.join(name)).unwrap_or_else(|| name.into())
+        };
+        for line in src.lines() {
+            let mut tokens = line.split_whitespace();
+            if tokens.next() != Some("mtllib") {
+                continue;
+            }
+            for name in tokens {
+                let mtl_path = resolve(path.parent(), name);
+                let mtl_src = fs::read_to_string(&mtl_path).unwrap_or_default();
+                for line in mtl_src.lines() {
+                    let mut tokens = line.split_whitespace();
+                    if let (Some("map_Kd"), Some(file)) = (tokens.next(), tokens.last()) {
+                        files.push(resolve(mtl_path.parent(), file));
+                    }
+                }
+                files.push(mtl_path);
+            }
+        }
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    // a unit cube of 8 corners and 6 quads, with *faces* left out of the list
+    fn cube_obj(faces: usize) -> String {
+        let mut src = String::new();
+        for i in 0..8 {
+            src += &format!("v {} {} {}\n", i & 1, (i >> 1) & 1, (i >> 2) & 1);
+        }
+        let quads = [
+            "1 3 4 2", "5 6 8 7", "1 2 6 5", "1 5 7 3", "2 4 8 6", "3 7 8 4",
+        ];
+        for quad in &quads[..faces] {
+            src += &format!("f {}\n", quad);
+        }
+        src
+    }
+
+    fn filled(voxels: &DenseGrid<Voxel>) -> usize {
+        voxels.iter().filter(|v| !v.is_empty()).count()
+    }
+
+    #[test]
+    fn parses_obj_faces() {
+        let mesh = Mesh::from_obj(&cube_obj(6), None).unwrap();
+        assert_eq!(mesh.triangles.len(), 12);
+        assert!(mesh.is_closed());
+        assert_eq!(
+            mesh.bounds(),
+            Some((vector![0.0, 0.0, 0.0], vector![1.0, 1.0, 1.0]))
+        );
+        assert!(!Mesh::from_obj(&cube_obj(5), None).unwrap().is_closed());
+
+        // "v/vt/vn" corners and negative indices name the same vertices as plain ones
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nf 1/1/1 -2//1 -1\n";
+        let mesh = Mesh::from_obj(src, None).unwrap();
+        assert_eq!(mesh.triangles[0].verts[1], vector![1.0, 0.0, 0.0]);
+        assert_eq!(mesh.triangles[0].verts[2], vector![0.0, 1.0, 0.0]);
+
+        assert!(Mesh::from_obj("v 0 0\n", None).is_err());
+        assert!(Mesh::from_obj("v 0 0 x\n", None).is_err());
+        assert!(Mesh::from_obj("v 0 0 0\nf 1 2 3\n", None).is_err());
+        assert!(Mesh::from_obj("v 0 0 0\nv 1 0 0\nf 1 2\n", None).is_err());
+    }
+
+    #[test]
+    fn colors_obj_faces() {
+        let dir = temp_dir("voxelize_obj");
+        fs::write(
+            dir.join("colors.mtl"),
+            "newmtl brick\nKd 1 0 0\nnewmtl plain\n",
+        )
+        .unwrap();
+        let src = "mtllib colors.mtl\n\
+                   v 0 0 0 0 0 1\nv 1 0 0 0 0 1\nv 0 1 0 0 0 1\n\
+                   f 1 2 3\n\
+                   usemtl brick\nf 1 2 3\n\
+                   usemtl plain\nf 1 2 3\n\
+                   usemtl missing\nf 1 2 3\n";
+        let mesh = Mesh::from_obj(src, Some(&dir)).unwrap();
+        let colors: Vec<_> = mesh.triangles.iter().map(|t| t.color).collect();
+        // the material's diffuse color wins over the vertex colors, which fill in without one
+        assert_eq!(
+            colors,
+            [
+                Some((0, 0, 255)),
+                Some((255, 0, 0)),
+                Some((0, 0, 255)),
+                Some((0, 0, 255))
+            ]
+        );
+
+        // without the library the material is unknown, which leaves the faces uncolored
+        let mesh = Mesh::from_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl brick\nf 1 2 3\n", None);
+        assert_eq!(mesh.unwrap().triangles[0].color, None);
+    }
+
+    #[test]
+    fn parses_stl() {
+        let ascii = "solid tri\n\
+                     facet normal 0 0 1\n outer loop\n\
+                     vertex 0 0 0\n vertex 2 0 0\n vertex 0 3 0\n\
+                     endloop\n endfacet\nendsolid tri\n";
+        let mesh = Mesh::from_stl(ascii.as_bytes()).unwrap();
+        assert_eq!(mesh.triangles.len(), 1);
+        assert_eq!(mesh.triangles[0].verts[2], vector![0.0, 3.0, 0.0]);
+        assert!(Mesh::from_stl(b"solid bad\nfacet\nvertex 0 0\n").is_err());
+
+        // header, facet count, then a normal, 3 corners and a red attribute word per facet
+        let mut binary = vec![0; 80];
+        binary.extend_from_slice(&2u32.to_le_bytes());
+        for color in [0x8000 | 0x1f << 10, 0] {
+            for v in [
+                0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 3.0, 0.0,
+            ] {
+                binary.extend_from_slice(&v.to_le_bytes());
+            }
+            binary.extend_from_slice(&(color as u16).to_le_bytes());
+        }
+        let mesh = Mesh::from_stl(&binary).unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+        assert_eq!(mesh.triangles[0].verts[1], vector![2.0, 0.0, 0.0]);
+        assert_eq!(mesh.triangles[0].color, Some((255, 0, 0)));
+        assert_eq!(mesh.triangles[1].color, None);
+
+        // files too short for their facet count are refused, however large the count
+        assert!(Mesh::from_stl(&binary[..binary.len() - 1]).is_err());
+        assert!(Mesh::from_stl(&binary[..82]).is_err());
+        binary[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Mesh::from_stl(&binary).is_err());
+    }
+
+    #[test]
+    fn fills_closed_meshes() {
+        let mesh = Mesh::from_obj(&cube_obj(6), None).unwrap();
+        let shape = (10, 10, 10);
+
+        // the cube is scaled to 8 voxels a side, resting on the floor between the margins
+        let solid = Voxelizer::default().voxelize(&mesh, shape);
+        assert_eq!(filled(&solid), 8 * 8 * 8);
+        assert_eq!(solid[(1, 0, 1)], Voxel::Stone);
+        assert_eq!(solid[(5, 4, 5)], Voxel::Stone);
+        assert_eq!(solid[(0, 0, 0)], Voxel::Empty);
+        assert_eq!(solid[(5, 8, 5)], Voxel::Empty);
+
+        let surface = Voxelizer {
+            mode: VoxelizeMode::Surface,
+            ..Voxelizer::default()
+        }
+        .voxelize(&mesh, shape);
+        assert_eq!(filled(&surface), 8 * 8 * 8 - 6 * 6 * 6);
+        assert_eq!(surface[(1, 0, 1)], Voxel::Stone);
+        assert_eq!(surface[(5, 4, 5)], Voxel::Empty);
+    }
+
+    #[test]
+    fn keeps_the_surface_of_open_meshes() {
+        let dir = temp_dir("voxelize_open");
+        let loader = MeshLoader {
+            voxelizer: Voxelizer::default(),
+        };
+
+        let closed = dir.join("closed.obj");
+        fs::write(&closed, cube_obj(6)).unwrap();
+        let level = loader.load(&closed, (10, 10, 10)).unwrap();
+        assert!(level.warnings.is_empty());
+        assert_eq!(level.space.voxels[(5, 4, 5)], Voxel::Stone);
+
+        // without its top the cube has no inside, so it is left hollow and the loader says why
+        let open = dir.join("open.obj");
+        fs::write(&open, cube_obj(5)).unwrap();
+        let level = loader.load(&open, (10, 10, 10)).unwrap();
+        assert_eq!(level.warnings.len(), 1);
+        assert!(level.warnings[0].contains("not closed"));
+        assert_eq!(level.space.voxels[(5, 4, 5)], Voxel::Empty);
+        assert_eq!(level.space.voxels[(5, 0, 5)], Voxel::Stone);
+        assert_eq!(level.space.voxels[(5, 8, 5)], Voxel::Empty);
+    }
+
+    #[test]
+    fn assigns_materials_by_color() {
+        let voxelizer = Voxelizer {
+            default_material: Voxel::Wood,
+            ..Voxelizer::default()
+        };
+        let face = |color| Triangle {
+            verts: [vector![0.0, 0.0, 0.0]; 3],
+            color,
+        };
+        assert_eq!(voxelizer.material(&face(None)), Voxel::Wood);
+        assert_eq!(voxelizer.material(&face(Some((250, 10, 0)))), Voxel::Red);
+        assert_eq!(
+            voxelizer.material(&face(Some((0x4c, 0xa0, 0x3c)))),
+            Voxel::Grass
+        );
+
+        // the colored side of a cube keeps its color over the default filling the inside
+        let src = cube_obj(6).replacen(
+            "f 1 3 4 2",
+            "v 0 0 0 0 0 1\nv 0 1 0 0 0 1\nv 1 1 0 0 0 1\nv 1 0 0 0 0 1\nf -4 -3 -2 -1",
+            1,
+        );
+        let mesh = Mesh::from_obj(&src, None).unwrap();
+        let voxels = voxelizer.voxelize(&mesh, (10, 10, 10));
+        assert_eq!(voxels[(5, 4, 1)], Voxel::Blue);
+        assert_eq!(voxels[(5, 4, 5)], Voxel::Wood);
+    }
+}
@@ -1,13 +1,16 @@
+use crate::brush::Brush;
+use crate::history::{Change, Edit, History};
 use crate::march::{DenseBinaryCartesianSDF, SDF};
 use crate::types::{Color, Dimension3, GPUFormat, Idx3};
+use crate::world_loader::LoadError;
 
 use std::borrow::Cow;
 use std::default::Default;
 use std::ops::{Deref, DerefMut, Index, IndexMut};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 // Size of the world in voxels
 pub const WORLD_DIM: Dimension3 = (128, 32, 128);
-pub const WORLD_NUM_VOXELS: usize = WORLD_DIM.0 * WORLD_DIM.1 * WORLD_DIM.2;
 
 use std::io::Read;
 
@@ -20,6 +23,14 @@ pub enum Voxel {
     Red = 1,
     Blue = 2,
     Green = 3,
+    Stone = 4,
+    Dirt = 5,
+    Grass = 6,
+    Sand = 7,
+    Snow = 8,
+    Wood = 9,
+    Leaves = 10,
+    Water = 11,
 }
 
 impl Default for Voxel {
@@ -29,15 +40,70 @@ impl Default for Voxel {
 }
 
 impl Voxel {
+    // every solid material, in id order
+    pub const MATERIALS: [Voxel; 11] = [
+        Voxel::Red,
+        Voxel::Blue,
+        Voxel::Green,
+        Voxel::Stone,
+        Voxel::Dirt,
+        Voxel::Grass,
+        Voxel::Sand,
+        Voxel::Snow,
+        Voxel::Wood,
+        Voxel::Leaves,
+        Voxel::Water,
+    ];
+
     pub fn from_color(color: Color) -> Voxel {
         match color {
             (0xff, 0x00, 0x00) => Voxel::Red,
             (0x00, 0xff, 0x00) => Voxel::Green,
             (0x00, 0x00, 0xff) => Voxel::Blue,
+            (0x80, 0x80, 0x80) => Voxel::Stone,
+            (0x6b, 0x4a, 0x2b) => Voxel::Dirt,
+            (0x4c, 0xa0, 0x3c) => Voxel::Grass,
+            (0xe0, 0xd0, 0x90) => Voxel::Sand,
+            (0xf0, 0xf4, 0xf8) => Voxel::Snow,
+            (0x8b, 0x5a, 0x2b) => Voxel::Wood,
+            (0x2e, 0x7d, 0x32) => Voxel::Leaves,
+            (0x28, 0x60, 0xc0) => Voxel::Water,
             _ => Voxel::Empty,
         }
     }
 
+    // inverse of from_color; Empty has no color of its own and maps to black
+    pub fn color(&self) -> Color {
+        match self {
+            Voxel::Empty => (0x00, 0x00, 0x00),
+            Voxel::Red => (0xff, 0x00, 0x00),
+            Voxel::Green => (0x00, 0xff, 0x00),
+            Voxel::Blue => (0x00, 0x00, 0xff),
+            Voxel::Stone => (0x80, 0x80, 0x80),
+            Voxel::Dirt => (0x6b, 0x4a, 0x2b),
+            Voxel::Grass => (0x4c, 0xa0, 0x3c),
+            Voxel::Sand => (0xe0, 0xd0, 0x90),
+            Voxel::Snow => (0xf0, 0xf4, 0xf8),
+            Voxel::Wood => (0x8b, 0x5a, 0x2b),
+            Voxel::Leaves => (0x2e, 0x7d, 0x32),
+            Voxel::Water => (0x28, 0x60, 0xc0),
+        }
+    }
+
+    // the solid material whose color is closest to the given one
+    pub fn nearest_to_color((r, g, b): Color) -> Voxel {
+        let dist = |vox: &Voxel| {
+            let (vr, vg, vb) = vox.color();
+            let (dr, dg, db) = (
+                r as i32 - vr as i32,
+                g as i32 - vg as i32,
+                b as i32 - vb as i32,
+            );
+            dr * dr + dg * dg + db * db
+        };
+        *Voxel::MATERIALS.iter().min_by_key(|v| dist(v)).unwrap()
+    }
+
     pub fn is_empty(&self) -> bool {
         return *self == Voxel::Empty;
     }
@@ -45,6 +111,36 @@ impl Voxel {
     pub fn id(&self) -> u8 {
         return *self as u8;
     }
+
+    // name of the voxel in console commands
+    pub fn name(&self) -> &'static str {
+        match self {
+            Voxel::Empty => "empty",
+            Voxel::Red => "red",
+            Voxel::Blue => "blue",
+            Voxel::Green => "green",
+            Voxel::Stone => "stone",
+            Voxel::Dirt => "dirt",
+            Voxel::Grass => "grass",
+            Voxel::Sand => "sand",
+            Voxel::Snow => "snow",
+            Voxel::Wood => "wood",
+            Voxel::Leaves => "leaves",
+            Voxel::Water => "water",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Voxel> {
+        std::iter::once(Voxel::Empty)
+            .chain(Voxel::MATERIALS)
+            .find(|v| v.name() == name)
+    }
+
+    pub fn from_id(id: u8) -> Option<Voxel> {
+        std::iter::once(Voxel::Empty)
+            .chain(Voxel::MATERIALS)
+            .find(|v| v.id() == id)
+    }
 }
 
 /*************/
@@ -60,7 +156,7 @@ impl<T: Copy> DenseGrid<T> {
     pub fn fill((x, y, z): Dimension3, val: T) -> DenseGrid<T> {
         DenseGrid {
             shape: (x, y, z),
-            grid: vec![val; WORLD_NUM_VOXELS].into_boxed_slice(),
+            grid: vec![val; x * y * z].into_boxed_slice(),
         }
     }
 
@@ -71,6 +167,68 @@ impl<T: Copy> DenseGrid<T> {
     pub fn grid(&self) -> &Box<[T]> {
         &self.grid
     }
+
+    // A grid holding *f* of each position
+    pub fn from_fn(shape: Dimension3, f: impl FnMut(Idx3) -> T) -> DenseGrid<T> {
+        let all = Region::new((0, 0, 0), (shape.0 - 1, shape.1 - 1, shape.2 - 1));
+        DenseGrid {
+            shape,
+            grid: all.iter().map(f).collect(),
+        }
+    }
+
+    // A copy of the voxels in *region*, which has to be within the grid
+    pub fn copy_region(&self, region: Region) -> DenseGrid<T> {
+        DenseGrid {
+            shape: region.shape(),
+            grid: region.iter().map(|idx| self[idx]).collect(),
+        }
+    }
+
+    // A copy turned by *turns* quarter turns around an axis (0, 1 or 2 for x, y or z). Looking
+    // down the axis from its positive end, turns go counterclockwise.
+    pub fn rotated(&self, axis: usize, turns: i32) -> DenseGrid<T> {
+        // the two axes across the one turned around, ordered so that a turn takes u to v
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let turns = turns.rem_euclid(4);
+        let old = [self.shape.0, self.shape.1, self.shape.2];
+        let mut shape = old;
+        if turns % 2 == 1 {
+            shape.swap(u, v);
+        }
+
+        DenseGrid::from_fn((shape[0], shape[1], shape[2]), |(x, y, z)| {
+            let new = [x, y, z];
+            // the position turned back to where it came from
+            let mut p = new;
+            match turns {
+                1 => {
+                    p[u] = new[v];
+                    p[v] = old[v] - 1 - new[u];
+                }
+                2 => {
+                    p[u] = old[u] - 1 - new[u];
+                    p[v] = old[v] - 1 - new[v];
+                }
+                3 => {
+                    p[u] = old[u] - 1 - new[v];
+                    p[v] = new[u];
+                }
+                _ => (),
+            }
+            self[(p[0], p[1], p[2])]
+        })
+    }
+
+    // A copy flipped along an axis (0, 1 or 2 for x, y or z)
+    pub fn mirrored(&self, axis: usize) -> DenseGrid<T> {
+        let size = [self.shape.0, self.shape.1, self.shape.2];
+        DenseGrid::from_fn(self.shape, |(x, y, z)| {
+            let mut p = [x, y, z];
+            p[axis] = size[axis] - 1 - p[axis];
+            self[(p[0], p[1], p[2])]
+        })
+    }
 }
 
 impl<T> Index<Idx3> for DenseGrid<T> {
@@ -106,6 +264,73 @@ impl<'a> GPUFormat for &'a DenseGrid<Voxel> {
     }
 }
 
+/**********/
+/* Region */
+/**********/
+
+// A box of voxels between two corners, both included
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Region {
+    pub min: Idx3,
+    pub max: Idx3,
+}
+
+impl Region {
+    // The box between two corners given in any order
+    pub fn new(a: Idx3, b: Idx3) -> Region {
+        Region {
+            min: (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+            max: (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+        }
+    }
+
+    pub fn shape(&self) -> Dimension3 {
+        (
+            self.max.0 - self.min.0 + 1,
+            self.max.1 - self.min.1 + 1,
+            self.max.2 - self.min.2 + 1,
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        let (x, y, z) = self.shape();
+        x * y * z
+    }
+
+    // The smallest region holding this one and the voxel at *idx*
+    pub fn including(&self, idx: Idx3) -> Region {
+        Region {
+            min: (
+                self.min.0.min(idx.0),
+                self.min.1.min(idx.1),
+                self.min.2.min(idx.2),
+            ),
+            max: (
+                self.max.0.max(idx.0),
+                self.max.1.max(idx.1),
+                self.max.2.max(idx.2),
+            ),
+        }
+    }
+
+    // The smallest region holding all the voxels at *idxs*, if there are any
+    pub fn around(idxs: impl IntoIterator<Item = Idx3>) -> Option<Region> {
+        let mut idxs = idxs.into_iter();
+        let first = idxs.next()?;
+        Some(idxs.fold(Region::new(first, first), |region, idx| {
+            region.including(idx)
+        }))
+    }
+
+    // Every voxel in the region, in the order `DenseGrid` stores them: x first, then y, then z
+    pub fn iter(&self) -> impl Iterator<Item = Idx3> {
+        let (min, max) = (self.min, self.max);
+        (min.2..=max.2).flat_map(move |z| {
+            (min.1..=max.1).flat_map(move |y| (min.0..=max.0).map(move |x| (x, y, z)))
+        })
+    }
+}
+
 /*********/
 /* Space */
 /*********/
@@ -116,89 +341,202 @@ pub struct Space {
 
     // Distance field used to determine step sizes for ray marching
     pub sdf: DenseBinaryCartesianSDF,
+
+    // Edits made by painting brushes, to undo and redo
+    pub history: History,
+
+    // Changes whenever the voxels do, and is never the same for two spaces, so copies of the
+    // voxels (like the renderer's textures) can tell when they are out of date
+    pub generation: u64,
+}
+
+// a generation no space has had yet
+fn next_generation() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
 }
 
 impl Space {
-    pub fn new(shape: Dimension3) -> Self {
-        let mut voxels = DenseGrid::fill(shape, Voxel::Empty);
-        let mut sdf = DenseBinaryCartesianSDF::zeros(shape);
-        for x in 1..shape.0 {
-            for y in 1..shape.1 {
-                for z in 1..shape.2 {
-                    voxels[(x, y, z)] = {
-                        if x % 3 == 0 && y % 3 == 0 && z % 5 == 0 && x >= 10 {
-                            Voxel::Red
-                        } else {
-                            Voxel::Empty
-                        }
-                    };
-                }
-            }
-        }
+    // builds a space around an existing voxel grid, computing its distance field
+    pub fn from_voxels(voxels: DenseGrid<Voxel>) -> Space {
+        let mut sdf = DenseBinaryCartesianSDF::zeros(*voxels.shape());
         sdf.update(&voxels);
-        Space { voxels, sdf }
+        Space {
+            voxels,
+            sdf,
+            history: History::default(),
+            generation: next_generation(),
+        }
     }
 
-    pub fn from_gox(shape: Dimension3, src: &mut dyn Read) -> Space {
-        // goxel format consists of in each line, either a comment beginning with # or a voxel of the format:
-        // "posX posY posZ color"
-        // example: "111 78 36 ff00ff"
-
-        let mut src_str: String = "".to_string();
-        src.read_to_string(&mut src_str).unwrap();
-        let lines = src_str.lines();
+    // Paints the voxels *brush* covers with *material* as one edit, updating the distance field
+    // once for all of them. The edit is added to the history, and given back if anything changed.
+    pub fn paint(&mut self, brush: &Brush, material: Voxel) -> Option<Edit> {
+        let changes = brush
+            .voxels(self)
+            .into_iter()
+            .filter(|&idx| self.voxels[idx] != material)
+            .map(|idx| Change {
+                idx,
+                before: self.voxels[idx],
+                after: material,
+            })
+            .collect();
+        self.commit(changes)
+    }
 
-        // determines if a line is not a comment
-        fn is_not_comment(line: &str) -> bool {
-            let char = line.chars().next();
-            println!("first char of line {} is {:?}", line, char);
-            return line.chars().next() != Some('#');
+    // Copies *grid* into the space with its first corner at *at*, as one edit like `paint`. Empty
+    // voxels are left out when *solid_only*, and whatever sticks out of the space is cut off.
+    pub fn paste(&mut self, grid: &DenseGrid<Voxel>, at: Idx3, solid_only: bool) -> Option<Edit> {
+        let (sx, sy, sz) = *self.voxels.shape();
+        let (gx, gy, gz) = *grid.shape();
+        if at.0 >= sx || at.1 >= sy || at.2 >= sz || gx == 0 || gy == 0 || gz == 0 {
+            return None;
         }
+        let end = |a: usize, g: usize, s: usize| (a + g).min(s) - 1;
+        let region = Region::new(
+            at,
+            (end(at.0, gx, sx), end(at.1, gy, sy), end(at.2, gz, sz)),
+        );
+
+        let changes = region
+            .iter()
+            .map(|idx| Change {
+                idx,
+                before: self.voxels[idx],
+                after: grid[(idx.0 - at.0, idx.1 - at.1, idx.2 - at.2)],
+            })
+            .filter(|c| c.before != c.after && !(solid_only && c.after.is_empty()))
+            .collect();
+        self.commit(changes)
+    }
+
+    // Makes the changes, updating the distance field and recording them in the history
+    fn commit(&mut self, changes: Vec<Change>) -> Option<Edit> {
+        let edit = Edit::new(changes)?;
+        edit.apply(&mut self.voxels, false);
+        self.changed(edit.region);
+        self.history.record(edit.clone());
+        Some(edit)
+    }
+
+    // Brings the distance field up to date after the voxels in *region* changed
+    pub fn changed(&mut self, region: Region) {
+        self.sdf.update_region(&self.voxels, region);
+        self.generation = next_generation();
+    }
+
+    // Reads a level in goxel's text format: one voxel per line, as "posX posY posZ RRGGBB" (for
+    // example "111 78 36 ff00ff"), with comments starting with #. Colors other than those of our
+    // materials are left empty.
+    pub fn from_gox(shape: Dimension3, src: &mut dyn Read) -> Result<Space, LoadError> {
+        let mut src_str = String::new();
+        src.read_to_string(&mut src_str)?;
 
         // parses a hex string into a Color
         fn parse_hex_str(hex_str: &str) -> Option<Color> {
-            // if successful, this is a vector containing integers (0 - 255) representing RGB value
-            // of the color
-            let vals = (0..hex_str.len())
-                .step_by(2)
-                .map(|i| usize::from_str_radix(&hex_str[i..i + 2], 16))
-                .collect::<Vec<_>>();
-            Some((
-                *vals.get(0).unwrap().as_ref().unwrap() as u8,
-                *vals.get(1).unwrap().as_ref().unwrap() as u8,
-                *vals.get(2).unwrap().as_ref().unwrap() as u8,
-            ))
-        }
-
-        // parses a line from gox file into a voxel with a position
-        fn process_line(line: &str) -> Option<(Idx3, Voxel)> {
-            let tokens = line.split_whitespace().collect::<Vec<&str>>();
-            let pos: Idx3 = (
-                tokens.get(0).unwrap().parse::<usize>().unwrap(),
-                tokens.get(1).unwrap().parse::<usize>().unwrap(),
-                tokens.get(2).unwrap().parse::<usize>().unwrap(),
-            );
-            let color = parse_hex_str(tokens.get(3).unwrap()).unwrap();
-            let vox = Voxel::from_color(color);
-            println!("{:?} -> {:?}", color, vox);
-            Some((pos, vox))
+            if hex_str.len() != 6 || !hex_str.is_ascii() {
+                return None;
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex_str[i..i + 2], 16).ok();
+            Some((channel(0)?, channel(2)?, channel(4)?))
         }
 
+        // parses a line from gox file into a voxel with a position inside the space
+        let process_line = |line: &str| -> Option<(Idx3, Voxel)> {
+            let tokens = line.split_whitespace().collect::<Vec<&str>>();
+            let (x, y, z, color) = match tokens.as_slice() {
+                [x, y, z, color] => (x.parse().ok()?, y.parse().ok()?, z.parse().ok()?, color),
+                _ => return None,
+            };
+            if x >= shape.0 || y >= shape.1 || z >= shape.2 {
+                return None;
+            }
+            Some(((x, y, z), Voxel::from_color(parse_hex_str(color)?)))
+        };
+
         let mut voxels = DenseGrid::fill(shape, Voxel::Empty);
-        let mut sdf = DenseBinaryCartesianSDF::zeros(shape);
-
-        // TODO: make this fail if there are any Nones
-        // loop through all valid voxel lines and add them to voxel grid
-        for (pos, vox) in lines
-            .filter(|x| is_not_comment(&x))
-            .map(process_line)
-            .filter(Option::is_some)
-            .map(Option::unwrap)
-        {
+        for (line_no, line) in src_str.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pos, vox) = process_line(line).ok_or_else(|| {
+                LoadError::Parse(format!(
+                    "gox line {}: expected a voxel inside {:?}, found {:?}",
+                    line_no + 1,
+                    shape,
+                    line
+                ))
+            })?;
             voxels[pos] = vox;
         }
-        sdf.update(&voxels);
 
-        Space { voxels, sdf }
+        Ok(Space::from_voxels(voxels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_every_voxel() {
+        for vox in std::iter::once(Voxel::Empty).chain(Voxel::MATERIALS) {
+            assert_eq!(Voxel::from_name(vox.name()), Some(vox));
+        }
+        assert_eq!(Voxel::from_name("lava"), None);
+    }
+
+    #[test]
+    fn turns_and_flips_grids() {
+        let grid = DenseGrid::from_fn((2, 3, 4), |(x, y, z)| (x, y, z));
+        let turned = grid.rotated(1, 1);
+        assert_eq!(*turned.shape(), (4, 3, 2));
+        // a quarter turn around y takes +z to +x, and +x to -z
+        assert_eq!(turned[(3, 0, 1)], (0, 0, 3));
+        assert_eq!(turned[(0, 2, 0)], (1, 2, 0));
+        assert_eq!(*grid.rotated(0, 2).shape(), (2, 3, 4));
+
+        for axis in 0..3 {
+            assert!(grid.rotated(axis, 4).grid() == grid.grid());
+            assert!(grid.rotated(axis, 3).rotated(axis, 1).grid() == grid.grid());
+            assert!(grid.rotated(axis, -1).grid() == grid.rotated(axis, 3).grid());
+            assert!(grid.mirrored(axis).mirrored(axis).grid() == grid.grid());
+        }
+        assert_eq!(grid.mirrored(2)[(1, 2, 0)], (1, 2, 3));
+    }
+
+    #[test]
+    fn copies_regions() {
+        let mut grid = DenseGrid::fill((4, 4, 4), 0);
+        for (i, v) in grid.iter_mut().enumerate() {
+            *v = i;
+        }
+        let region = Region::new((2, 3, 1), (1, 2, 1));
+        assert_eq!(region.shape(), (2, 2, 1));
+        assert_eq!(region.including((0, 2, 3)).len(), 3 * 2 * 3);
+
+        let copy = grid.copy_region(region);
+        assert_eq!(*copy.shape(), (2, 2, 1));
+        assert_eq!(copy[(1, 0, 0)], grid[(2, 2, 1)]);
+        assert_eq!(copy[(0, 1, 0)], grid[(1, 3, 1)]);
+    }
+
+    #[test]
+    fn pastes_grids_cut_to_the_space() {
+        let mut space = Space::from_voxels(DenseGrid::fill((4, 4, 4), Voxel::Empty));
+        let post = DenseGrid::fill((1, 3, 1), Voxel::Wood);
+
+        let edit = space.paste(&post, (1, 2, 1), false).unwrap();
+        assert_eq!(edit.changes.len(), 2);
+        assert_eq!(space.voxels[(1, 3, 1)], Voxel::Wood);
+
+        // nothing lands outside the space, and an empty grid changes nothing
+        assert!(space.paste(&post, (4, 0, 0), false).is_none());
+        for shape in [(0, 2, 2), (2, 0, 2), (2, 2, 0)] {
+            let empty = DenseGrid::fill(shape, Voxel::Wood);
+            assert!(space.paste(&empty, (0, 0, 0), false).is_none());
+        }
     }
 }
@@ -0,0 +1,551 @@
+use derive_more::{Display, From};
+
+use crate::console::{CommandError, Commands};
+use crate::prefab;
+use crate::types::Idx3;
+use crate::world::{DenseGrid, Space, Voxel};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Display, From)]
+pub enum ExportError {
+    #[display(fmt = "I/O error: {}", _0)]
+    Io(io::Error),
+
+    #[display(fmt = "cannot export: {}", _0)]
+    #[from(ignore)]
+    Unsupported(String),
+}
+
+// Writes the space to a file, in the format given by the path's extension: .gox (Goxel text, as
+// read by `Space::from_gox`), .vox (MagicaVoxel), .obj (plus a .mtl next to it), .ply or .prefab
+pub fn export(space: &Space, path: &Path) -> Result<(), ExportError> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let create = |path: &Path| File::create(path).map(BufWriter::new);
+
+    match ext.as_str() {
+        "gox" | "txt" => write_gox(&space.voxels, &mut create(path)?)?,
+        "vox" => write_vox(&space.voxels, &mut create(path)?)?,
+        "ply" => write_ply(&space.voxels, &mut create(path)?)?,
+        "prefab" => prefab::write_prefab(&space.voxels, &mut create(path)?)?,
+        "obj" => {
+            let mtl_path = path.with_extension("mtl");
+            let mtl_name = mtl_path.file_name().and_then(|n| n.to_str());
+            write_obj(&space.voxels, &mut create(path)?, mtl_name)?;
+            write_mtl(&mut create(&mtl_path)?)?;
+        }
+        _ => {
+            return Err(ExportError::Unsupported(format!(
+                "unknown level format {:?}",
+                path
+            )))
+        }
+    }
+    Ok(())
+}
+
+// Console commands writing the space out
+pub fn commands() -> Commands<Space> {
+    let mut commands: Commands<Space> = Commands::default();
+    commands.add(
+        "save",
+        "<file>",
+        "save the level as .gox, .vox, .obj, .ply or .prefab, by the file's extension",
+        |space, args| {
+            let path: PathBuf = args.next("file")?;
+            args.end()?;
+            export(space, &path).map_err(|err| CommandError::Failed(err.to_string()))?;
+            Ok(format!("saved {:?}", path))
+        },
+    );
+    commands
+}
+
+// every filled voxel with its position, in x, then y, then z order
+fn filled_voxels(voxels: &DenseGrid<Voxel>) -> impl Iterator<Item = (Idx3, Voxel)> + '_ {
+    let (sx, sy, sz) = *voxels.shape();
+    (0..sz)
+        .flat_map(move |z| (0..sy).flat_map(move |y| (0..sx).map(move |x| (x, y, z))))
+        .map(move |idx| (idx, voxels[idx]))
+        .filter(|(_, vox)| !vox.is_empty())
+}
+
+/*********/
+/* Goxel */
+/*********/
+
+pub fn write_gox(voxels: &DenseGrid<Voxel>, w: &mut dyn Write) -> io::Result<()> {
+    writeln!(w, "# Goxel 0.10.8")?;
+    writeln!(w, "# One line per voxel")?;
+    writeln!(w, "# X Y Z RRGGBB")?;
+    for ((x, y, z), vox) in filled_voxels(voxels) {
+        let (r, g, b) = vox.color();
+        writeln!(w, "{} {} {} {:02x}{:02x}{:02x}", x, y, z, r, g, b)?;
+    }
+    Ok(())
+}
+
+/***************/
+/* MagicaVoxel */
+/***************/
+
+// MagicaVoxel's .vox format: a MAIN chunk holding a SIZE, XYZI (voxel list) and RGBA (palette)
+// chunk. Voxel ids are used directly as palette indices. MagicaVoxel is z-up, so our y and z
+// axes are swapped on the way out.
+pub fn write_vox(voxels: &DenseGrid<Voxel>, w: &mut dyn Write) -> io::Result<()> {
+    let (sx, sy, sz) = *voxels.shape();
+    if sx > 256 || sy > 256 || sz > 256 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            ".vox models are limited to 256 voxels per side",
+        ));
+    }
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(12 + content.len() + children.len());
+        out.extend_from_slice(id);
+        out.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        out.extend_from_slice(content);
+        out.extend_from_slice(children);
+        out
+    }
+
+    let mut size = Vec::new();
+    for dim in &[sx, sz, sy] {
+        size.extend_from_slice(&(*dim as u32).to_le_bytes());
+    }
+
+    let filled: Vec<(Idx3, Voxel)> = filled_voxels(voxels).collect();
+    let mut xyzi = Vec::with_capacity(4 + 4 * filled.len());
+    xyzi.extend_from_slice(&(filled.len() as u32).to_le_bytes());
+    for ((x, y, z), vox) in filled {
+        xyzi.extend_from_slice(&[x as u8, z as u8, y as u8, vox.id()]);
+    }
+
+    // palette entry i holds the color of index i + 1
+    let mut rgba = vec![0u8; 256 * 4];
+    for vox in Voxel::MATERIALS.iter() {
+        let (r, g, b) = vox.color();
+        let i = (vox.id() as usize - 1) * 4;
+        rgba[i..i + 4].copy_from_slice(&[r, g, b, 0xff]);
+    }
+
+    let mut children = chunk(b"SIZE", &size, &[]);
+    children.extend(chunk(b"XYZI", &xyzi, &[]));
+    children.extend(chunk(b"RGBA", &rgba, &[]));
+
+    w.write_all(b"VOX ")?;
+    w.write_all(&150u32.to_le_bytes())?;
+    w.write_all(&chunk(b"MAIN", &[], &children))
+}
+
+/***************/
+/* Greedy mesh */
+/***************/
+
+// One rectangular face of the mesh, made of same-material voxel faces merged together
+pub struct Quad {
+    // corners in counter-clockwise order when seen from outside
+    pub corners: [[f32; 3]; 4],
+    pub normal: [f32; 3],
+    pub material: Voxel,
+}
+
+// Builds the surface of the voxels as few quads as possible, by greedily merging visible voxel
+// faces of the same material and orientation within each slice of the grid
+pub fn greedy_mesh(voxels: &DenseGrid<Voxel>) -> Vec<Quad> {
+    let (sx, sy, sz) = *voxels.shape();
+    let dims = [sx, sy, sz];
+    let mut quads = Vec::new();
+
+    let get = |p: [usize; 3]| voxels[(p[0], p[1], p[2])];
+
+    for d in 0..3 {
+        let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+        // visible face at each position of the slice, as (material, faces towards +d)
+        let mut mask: Vec<Option<(Voxel, bool)>> = vec![None; dims[u] * dims[v]];
+
+        // planes between slices, including both outer boundaries
+        for plane in 0..=dims[d] {
+            for j in 0..dims[v] {
+                for i in 0..dims[u] {
+                    let mut p = [0; 3];
+                    p[u] = i;
+                    p[v] = j;
+                    p[d] = plane;
+                    let front = if plane < dims[d] {
+                        get(p)
+                    } else {
+                        Voxel::Empty
+                    };
+                    let back = if plane > 0 {
+                        p[d] = plane - 1;
+                        get(p)
+                    } else {
+                        Voxel::Empty
+                    };
+
+                    mask[i + dims[u] * j] = match (back.is_empty(), front.is_empty()) {
+                        (false, true) => Some((back, true)),
+                        (true, false) => Some((front, false)),
+                        _ => None,
+                    };
+                }
+            }
+
+            // merge the mask into rectangles, growing first along u, then along v
+            for j in 0..dims[v] {
+                let mut i = 0;
+                while i < dims[u] {
+                    let face = match mask[i + dims[u] * j] {
+                        Some(face) => face,
+                        None => {
+                            i += 1;
+                            continue;
+                        }
+                    };
+
+                    let mut width = 1;
+                    while i + width < dims[u] && mask[i + width + dims[u] * j] == Some(face) {
+                        width += 1;
+                    }
+                    let mut height = 1;
+                    'grow: while j + height < dims[v] {
+                        for k in 0..width {
+                            if mask[i + k + dims[u] * (j + height)] != Some(face) {
+                                break 'grow;
+                            }
+                        }
+                        height += 1;
+                    }
+                    for l in 0..height {
+                        for k in 0..width {
+                            mask[i + k + dims[u] * (j + l)] = None;
+                        }
+                    }
+
+                    let corner = |du: usize, dv: usize| {
+                        let mut c = [0.0; 3];
+                        c[d] = plane as f32;
+                        c[u] = (i + du) as f32;
+                        c[v] = (j + dv) as f32;
+                        c
+                    };
+                    let (material, positive) = face;
+                    let mut normal = [0.0; 3];
+                    normal[d] = if positive { 1.0 } else { -1.0 };
+                    // u x v = d, so going around u then v is counter-clockwise seen from +d
+                    let corners = if positive {
+                        [
+                            corner(0, 0),
+                            corner(width, 0),
+                            corner(width, height),
+                            corner(0, height),
+                        ]
+                    } else {
+                        [
+                            corner(0, 0),
+                            corner(0, height),
+                            corner(width, height),
+                            corner(width, 0),
+                        ]
+                    };
+                    quads.push(Quad {
+                        corners,
+                        normal,
+                        material,
+                    });
+
+                    i += width;
+                }
+            }
+        }
+    }
+
+    quads
+}
+
+fn material_name(vox: Voxel) -> String {
+    format!("{:?}", vox).to_lowercase()
+}
+
+/*******/
+/* OBJ */
+/*******/
+
+// Writes the greedy mesh as OBJ. Colors are written both as materials (when a material library
+// name is given) and as vertex colors, which is what `Mesh::from_obj` reads back.
+pub fn write_obj(
+    voxels: &DenseGrid<Voxel>,
+    w: &mut dyn Write,
+    mtl_name: Option<&str>,
+) -> io::Result<()> {
+    let quads = greedy_mesh(voxels);
+
+    if let Some(name) = mtl_name {
+        writeln!(w, "mtllib {}", name)?;
+    }
+    for quad in &quads {
+        let (r, g, b) = quad.material.color();
+        for [x, y, z] in &quad.corners {
+            writeln!(
+                w,
+                "v {} {} {} {:.4} {:.4} {:.4}",
+                x,
+                y,
+                z,
+                r as f32 / 255.0,
+                g as f32 / 255.0,
+                b as f32 / 255.0
+            )?;
+        }
+    }
+    for quad in &quads {
+        let [x, y, z] = quad.normal;
+        writeln!(w, "vn {} {} {}", x, y, z)?;
+    }
+
+    let mut current = None;
+    for (i, quad) in quads.iter().enumerate() {
+        if mtl_name.is_some() && current != Some(quad.material) {
+            writeln!(w, "usemtl {}", material_name(quad.material))?;
+            current = Some(quad.material);
+        }
+        let base = 4 * i + 1;
+        writeln!(
+            w,
+            "f {}//{n} {}//{n} {}//{n} {}//{n}",
+            base,
+            base + 1,
+            base + 2,
+            base + 3,
+            n = i + 1
+        )?;
+    }
+    Ok(())
+}
+
+// Material library for every voxel material, named after the materials
+pub fn write_mtl(w: &mut dyn Write) -> io::Result<()> {
+    for vox in Voxel::MATERIALS.iter() {
+        let (r, g, b) = vox.color();
+        writeln!(w, "newmtl {}", material_name(*vox))?;
+        writeln!(
+            w,
+            "Kd {:.4} {:.4} {:.4}",
+            r as f32 / 255.0,
+            g as f32 / 255.0,
+            b as f32 / 255.0
+        )?;
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+/*******/
+/* PLY */
+/*******/
+
+// Writes the greedy mesh as an ASCII PLY with a color on every face
+pub fn write_ply(voxels: &DenseGrid<Voxel>, w: &mut dyn Write) -> io::Result<()> {
+    let quads = greedy_mesh(voxels);
+
+    writeln!(w, "ply")?;
+    writeln!(w, "format ascii 1.0")?;
+    writeln!(w, "element vertex {}", 4 * quads.len())?;
+    writeln!(w, "property float x")?;
+    writeln!(w, "property float y")?;
+    writeln!(w, "property float z")?;
+    writeln!(w, "element face {}", quads.len())?;
+    writeln!(w, "property list uchar int vertex_indices")?;
+    writeln!(w, "property uchar red")?;
+    writeln!(w, "property uchar green")?;
+    writeln!(w, "property uchar blue")?;
+    writeln!(w, "end_header")?;
+
+    for quad in &quads {
+        for [x, y, z] in &quad.corners {
+            writeln!(w, "{} {} {}", x, y, z)?;
+        }
+    }
+    for (i, quad) in quads.iter().enumerate() {
+        let (r, g, b) = quad.material.color();
+        let base = 4 * i;
+        writeln!(
+            w,
+            "4 {} {} {} {} {} {} {}",
+            base,
+            base + 1,
+            base + 2,
+            base + 3,
+            r,
+            g,
+            b
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+    use crate::voxelize::Mesh;
+    use crate::world::Region;
+
+    use std::fs;
+
+    // a bit of every material, scattered through a 5x4x3 grid
+    fn mixed() -> DenseGrid<Voxel> {
+        DenseGrid::from_fn((5, 4, 3), |(x, y, z)| match (x + 2 * y + 3 * z) % 13 {
+            n if n < 11 => Voxel::MATERIALS[n],
+            _ => Voxel::Empty,
+        })
+    }
+
+    fn read_u32(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    }
+
+    #[test]
+    fn gox_reads_back() {
+        let voxels = mixed();
+        let mut data = Vec::new();
+        write_gox(&voxels, &mut data).unwrap();
+
+        let read = Space::from_gox(*voxels.shape(), &mut data.as_slice()).unwrap();
+        for idx in Region::new((0, 0, 0), (4, 3, 2)).iter() {
+            assert_eq!(read.voxels[idx], voxels[idx], "at {:?}", idx);
+        }
+    }
+
+    #[test]
+    fn writes_vox_chunks() {
+        let voxels = mixed();
+        let filled = voxels.iter().filter(|v| !v.is_empty()).count();
+        let mut data = Vec::new();
+        write_vox(&voxels, &mut data).unwrap();
+
+        assert_eq!(&data[..4], b"VOX ");
+        assert_eq!(read_u32(&data, 4), 150);
+        assert_eq!(&data[8..12], b"MAIN");
+        assert_eq!(read_u32(&data, 12), 0);
+        assert_eq!(read_u32(&data, 16) as usize, data.len() - 20);
+
+        // SIZE is z-up, so the height comes last
+        assert_eq!(&data[20..24], b"SIZE");
+        assert_eq!((read_u32(&data, 24), read_u32(&data, 28)), (12, 0));
+        let size = [32, 36, 40].map(|at| read_u32(&data, at));
+        assert_eq!(size, [5, 3, 4]);
+
+        let xyzi = 44;
+        assert_eq!(&data[xyzi..xyzi + 4], b"XYZI");
+        assert_eq!(read_u32(&data, xyzi + 4) as usize, 4 + 4 * filled);
+        assert_eq!(read_u32(&data, xyzi + 12) as usize, filled);
+        for entry in data[xyzi + 16..xyzi + 16 + 4 * filled].chunks(4) {
+            let (x, y, z) = (entry[0] as usize, entry[2] as usize, entry[1] as usize);
+            assert_eq!(entry[3], voxels[(x, y, z)].id());
+        }
+
+        // palette indices are voxel ids, entry i holding index i + 1
+        let rgba = xyzi + 16 + 4 * filled;
+        assert_eq!(&data[rgba..rgba + 4], b"RGBA");
+        assert_eq!(read_u32(&data, rgba + 4), 1024);
+        assert_eq!(rgba + 12 + 1024, data.len());
+        for vox in Voxel::MATERIALS.iter() {
+            let i = rgba + 12 + 4 * (vox.id() as usize - 1);
+            let (r, g, b) = vox.color();
+            assert_eq!(data[i..i + 4], [r, g, b, 0xff]);
+        }
+
+        let too_big = DenseGrid::fill((257, 1, 1), Voxel::Stone);
+        assert!(write_vox(&too_big, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn merges_faces_into_quads() {
+        // a solid box is one quad per side, facing outwards
+        let quads = greedy_mesh(&DenseGrid::fill((3, 2, 4), Voxel::Stone));
+        assert_eq!(quads.len(), 6);
+        assert!(quads.iter().all(|q| q.material == Voxel::Stone));
+        for quad in &quads {
+            let d = (0..3).find(|&d| quad.normal[d] != 0.0).unwrap();
+            let max = [3.0, 2.0, 4.0][d];
+            let plane = if quad.normal[d] > 0.0 { max } else { 0.0 };
+            assert!(quad.corners.iter().all(|c| c[d] == plane));
+
+            let edge = |a: [f32; 3], b: [f32; 3]| [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+            let (e1, e2) = (
+                edge(quad.corners[0], quad.corners[1]),
+                edge(quad.corners[0], quad.corners[2]),
+            );
+            let cross = [
+                e1[1] * e2[2] - e1[2] * e2[1],
+                e1[2] * e2[0] - e1[0] * e2[2],
+                e1[0] * e2[1] - e1[1] * e2[0],
+            ];
+            assert!(cross[d] * quad.normal[d] > 0.0);
+        }
+
+        // faces of different materials are kept apart, and hidden faces dropped
+        let two = DenseGrid::from_fn((2, 1, 1), |(x, _, _)| [Voxel::Stone, Voxel::Wood][x]);
+        let quads = greedy_mesh(&two);
+        assert_eq!(quads.len(), 10);
+        assert_eq!(
+            quads.iter().filter(|q| q.material == Voxel::Wood).count(),
+            5
+        );
+        assert!(greedy_mesh(&DenseGrid::fill((2, 2, 2), Voxel::Empty)).is_empty());
+    }
+
+    #[test]
+    fn writes_ply_and_obj() {
+        let mut ply = Vec::new();
+        write_ply(&DenseGrid::fill((2, 2, 2), Voxel::Red), &mut ply).unwrap();
+        let ply = String::from_utf8(ply).unwrap();
+        assert!(ply.contains("element vertex 24\n"));
+        assert!(ply.contains("element face 6\n"));
+        let body: Vec<&str> = ply.split("end_header\n").nth(1).unwrap().lines().collect();
+        assert_eq!(body.len(), 24 + 6);
+        assert!(body[24..]
+            .iter()
+            .all(|f| f.starts_with("4 ") && f.ends_with(" 255 0 0")));
+
+        // the obj reads back as a closed mesh in the voxels' colors
+        let mut obj = Vec::new();
+        write_obj(&DenseGrid::fill((2, 2, 2), Voxel::Red), &mut obj, None).unwrap();
+        let mesh = Mesh::from_obj(&String::from_utf8(obj).unwrap(), None).unwrap();
+        assert_eq!(mesh.triangles.len(), 12);
+        assert!(mesh.is_closed());
+        assert!(mesh.triangles.iter().all(|t| t.color == Some((255, 0, 0))));
+    }
+
+    #[test]
+    fn exports_by_extension() {
+        let dir = temp_dir("world_exporter");
+        let space = Space::from_voxels(mixed());
+        for name in ["level.gox", "level.VOX", "level.ply", "level.prefab"] {
+            export(&space, &dir.join(name)).unwrap();
+            assert!(fs::metadata(dir.join(name)).unwrap().len() > 0, "{}", name);
+        }
+
+        export(&space, &dir.join("level.obj")).unwrap();
+        let obj = fs::read_to_string(dir.join("level.obj")).unwrap();
+        assert!(obj.starts_with("mtllib level.mtl\n"));
+        assert!(obj.contains("usemtl stone\n"));
+        let mtl = fs::read_to_string(dir.join("level.mtl")).unwrap();
+        assert!(mtl.contains("newmtl stone\nKd 0.5020 0.5020 0.5020\n"));
+
+        assert!(matches!(
+            export(&space, &dir.join("level.bmp")),
+            Err(ExportError::Unsupported(_))
+        ));
+    }
+}
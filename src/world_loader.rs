@@ -0,0 +1,246 @@
+use derive_more::{Display, From};
+
+use crate::types::Dimension3;
+use crate::world::{DenseGrid, Region, Space, Voxel};
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Display, From)]
+pub enum LoadError {
+    #[display(fmt = "I/O error: {}", _0)]
+    Io(std::io::Error),
+
+    #[display(fmt = "invalid level data: {}", _0)]
+    #[from(ignore)]
+    Parse(String),
+
+    #[display(fmt = "no level loader registered for {:?}", _0)]
+    #[from(ignore)]
+    UnsupportedFormat(String),
+}
+
+// A loaded level, with anything that went wrong without stopping it from loading
+pub struct Level {
+    pub space: Space,
+    pub warnings: Vec<String>,
+}
+
+impl From<Space> for Level {
+    fn from(space: Space) -> Level {
+        Level {
+            space,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+// A source of levels, picked by the file extension of the level path
+pub trait LevelLoader {
+    // lowercase file extensions (without the dot) handled by this loader
+    fn extensions(&self) -> &[&str];
+
+    fn load(&self, path: &Path, shape: Dimension3) -> Result<Level, LoadError>;
+
+    // Files the level at *path* is built from, so it can be reloaded when any of them changes.
+    // Sidecar files that don't exist but would be read if they did are included too.
+    fn dependencies(&self, path: &Path) -> Vec<PathBuf> {
+        vec![path.to_path_buf()]
+    }
+
+    // The voxels of the file on their own, to be stamped into a level. Unless the format has a
+    // size of its own, the file is loaded as a level of *shape* and cut down to its filled voxels.
+    fn load_prefab(&self, path: &Path, shape: Dimension3) -> Result<DenseGrid<Voxel>, LoadError> {
+        let voxels = self.load(path, shape)?.space.voxels;
+        let (sx, sy, sz) = shape;
+        let all = Region::new((0, 0, 0), (sx - 1, sy - 1, sz - 1));
+        let filled = Region::around(all.iter().filter(|&idx| !voxels[idx].is_empty()))
+            .ok_or_else(|| LoadError::Parse(format!("{:?} has no voxels", path)))?;
+        Ok(voxels.copy_region(filled))
+    }
+}
+
+/*************/
+/* Gox files */
+/*************/
+
+pub struct GoxLoader;
+
+impl LevelLoader for GoxLoader {
+    fn extensions(&self) -> &[&str] {
+        &["gox", "txt"]
+    }
+
+    fn load(&self, path: &Path, shape: Dimension3) -> Result<Level, LoadError> {
+        let mut file = File::open(path)?;
+        Ok(Space::from_gox(shape, &mut file)?.into())
+    }
+}
+
+/*******************/
+/* Loader registry */
+/*******************/
+
+pub struct LevelLoaders {
+    loaders: Vec<Box<dyn LevelLoader>>,
+}
+
+impl Default for LevelLoaders {
+    fn default() -> LevelLoaders {
+        let mut loaders = LevelLoaders {
+            loaders: Vec::new(),
+        };
+        loaders.register(Box::new(GoxLoader));
+        loaders.register(Box::new(crate::heightmap::HeightmapLoader::default()));
+        loaders.register(Box::new(crate::voxelize::MeshLoader {
+            voxelizer: Default::default(),
+        }));
+        loaders.register(Box::new(crate::prefab::PrefabLoader));
+        loaders
+    }
+}
+
+impl LevelLoaders {
+    // Adds a loader. Loaders registered later take precedence for shared extensions.
+    pub fn register(&mut self, loader: Box<dyn LevelLoader>) {
+        self.loaders.push(loader);
+    }
+
+    pub fn load(&self, path: &Path, shape: Dimension3) -> Result<Level, LoadError> {
+        match self.loader_for(path) {
+            Some(loader) => loader.load(path, shape),
+            None => Err(LoadError::UnsupportedFormat(path.display().to_string())),
+        }
+    }
+
+    // See `LevelLoader::load_prefab`
+    pub fn load_prefab(
+        &self,
+        path: &Path,
+        shape: Dimension3,
+    ) -> Result<DenseGrid<Voxel>, LoadError> {
+        match self.loader_for(path) {
+            Some(loader) => loader.load_prefab(path, shape),
+            None => Err(LoadError::UnsupportedFormat(path.display().to_string())),
+        }
+    }
+
+    // See `LevelLoader::dependencies`
+    pub fn dependencies(&self, path: &Path) -> Vec<PathBuf> {
+        match self.loader_for(path) {
+            Some(loader) => loader.dependencies(path),
+            None => vec![path.to_path_buf()],
+        }
+    }
+
+    fn loader_for(&self, path: &Path) -> Option<&dyn LevelLoader> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+
+        self.loaders
+            .iter()
+            .rev()
+            .find(|l| l.extensions().contains(&ext.as_str()))
+            .map(|l| l.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    use std::fs;
+
+    // stands in for another loader of gox files, filling the world with one material
+    struct FillLoader(Voxel);
+
+    impl LevelLoader for FillLoader {
+        fn extensions(&self) -> &[&str] {
+            &["gox"]
+        }
+
+        fn load(&self, _path: &Path, shape: Dimension3) -> Result<Level, LoadError> {
+            Ok(Space::from_voxels(DenseGrid::fill(shape, self.0)).into())
+        }
+    }
+
+    #[test]
+    fn dispatches_on_the_extension() {
+        let dir = temp_dir("world_loader");
+        let gox = dir.join("level.GOX");
+        fs::write(&gox, "# one red voxel\n1 2 3 ff0000\n").unwrap();
+        let mut loaders = LevelLoaders::default();
+
+        // extensions match whatever their case
+        let level = loaders.load(&gox, (4, 4, 4)).unwrap();
+        assert!(level.warnings.is_empty());
+        assert_eq!(level.space.voxels[(1, 2, 3)], Voxel::Red);
+        assert_eq!(
+            level.space.voxels.iter().filter(|v| !v.is_empty()).count(),
+            1
+        );
+
+        for name in ["level.bmp", "level"] {
+            let path = dir.join(name);
+            assert!(matches!(
+                loaders.load(&path, (4, 4, 4)),
+                Err(LoadError::UnsupportedFormat(_))
+            ));
+            assert!(matches!(
+                loaders.load_prefab(&path, (4, 4, 4)),
+                Err(LoadError::UnsupportedFormat(_))
+            ));
+            assert_eq!(loaders.dependencies(&path), [path]);
+        }
+        assert!(matches!(
+            loaders.load(&dir.join("missing.gox"), (4, 4, 4)),
+            Err(LoadError::Io(_))
+        ));
+
+        // the loader registered last wins
+        loaders.register(Box::new(FillLoader(Voxel::Sand)));
+        let level = loaders.load(&gox, (4, 4, 4)).unwrap();
+        assert!(level.space.voxels.iter().all(|v| *v == Voxel::Sand));
+    }
+
+    #[test]
+    fn refuses_malformed_gox() {
+        let dir = temp_dir("world_loader_gox");
+        let gox = dir.join("bad.gox");
+        for src in [
+            "1 2 3\n",
+            "1 2 x ff0000\n",
+            "1 2 3 ff00\n",
+            "1 2 3 ff00zz\n",
+            "1 2 3 ff0000 4\n",
+            "4 0 0 ff0000\n",
+            "0 0 99 ff0000\n",
+        ] {
+            fs::write(&gox, format!("# ok\n\n0 0 0 ff0000\n{}", src)).unwrap();
+            match GoxLoader.load(&gox, (4, 4, 4)) {
+                Err(LoadError::Parse(msg)) => assert!(msg.starts_with("gox line 4"), "{}", msg),
+                _ => panic!("{:?} loaded", src),
+            }
+        }
+    }
+
+    #[test]
+    fn cuts_prefabs_down_to_their_voxels() {
+        let dir = temp_dir("world_loader_prefab");
+        let gox = dir.join("step.gox");
+        fs::write(&gox, "1 1 1 00ff00\n2 2 1 00ff00\n").unwrap();
+
+        let prefab = GoxLoader.load_prefab(&gox, (4, 4, 4)).unwrap();
+        assert_eq!(*prefab.shape(), (2, 2, 1));
+        assert_eq!(prefab[(0, 0, 0)], Voxel::Green);
+        assert_eq!(prefab[(1, 0, 0)], Voxel::Empty);
+        assert_eq!(prefab[(1, 1, 0)], Voxel::Green);
+
+        fs::write(&gox, "# nothing\n").unwrap();
+        assert!(GoxLoader.load_prefab(&gox, (4, 4, 4)).is_err());
+    }
+}